@@ -8,6 +8,8 @@ fn main() -> Result<()> {
             "src/general/network/proto_src/metric.proto",
             "src/general/network/proto_src/remote_sys.proto",
             "src/general/network/proto_src/data.proto",
+            "src/general/network/proto_src/cluster.proto",
+            "src/general/network/proto_src/fn_log.proto",
             "src/worker/func/shared/process_rpc_proto.proto",
         ],
         &["src/"],
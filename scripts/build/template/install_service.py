@@ -29,6 +29,12 @@ if len(sys.argv)!=2:
     print("Usage: python3 install_service.py <node_id>")
     exit(1)
 
+# the node refuses to start without the cluster secret, generated once and copied to all the nodes
+CLUSTER_ENV="/etc/waverless/cluster.env"
+if not os.path.exists(CLUSTER_ENV):
+    print(f"{CLUSTER_ENV} not found, create it with `WS_CLUSTER_SECRET=<openssl rand -hex 32>`, same on all the nodes")
+    exit(1)
+
 # stop service
 os_system("systemctl stop waverless")
 
@@ -62,6 +68,7 @@ After=network.target
 
 [Service]
 Type=simple
+EnvironmentFile={CLUSTER_ENV}
 ExecStart={sys.executable} /waverless_deploy/waverless_backend/run_node.py {sys.argv[1]}
 Restart=always
 User=root
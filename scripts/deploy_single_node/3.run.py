@@ -32,6 +32,10 @@ def print_title(title):
 
 args=sys.argv
 
+if not os.environ.get("WS_CLUSTER_SECRET"):
+    print("env WS_CLUSTER_SECRET is required, e.g. `export WS_CLUSTER_SECRET=$(openssl rand -hex 32)`")
+    exit(1)
+
 print_title("update config")
 os_system_sure(f"cp node_config.yaml pack/waverless_backend/test_dir{args[1]}/files/node_config.yaml")
 
@@ -1,31 +1,322 @@
 use crate::sys::NodeID;
 use core::panic;
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     net::SocketAddr,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
+/// judged by master's failure detector, same values as proto cluster::NodeHealth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeHealth {
+    Alive = 0,
+    /// heartbeat delayed, no new work but not given up yet
+    Suspect = 1,
+    Dead = 2,
+}
+
+/// shared by all the nodes to authenticate each other, never printed
+#[derive(Clone)]
+pub struct ClusterSecret(String);
+
+impl ClusterSecret {
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl std::fmt::Debug for ClusterSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ClusterSecret(***)")
+    }
+}
+
+/// the remote RunCmd and GetDirContent rpcs, disabled if not configured
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemoteSysConfig {
+    #[serde(default)]
+    pub enable: bool,
+    /// programs allowed to be run, matched with the first word of the cmd
+    #[serde(default)]
+    pub cmd_allowlist: Vec<String>,
+    /// dirs allowed to be listed or used as workdir, including the sub dirs,
+    /// relative ones are under the file dir
+    #[serde(default)]
+    pub path_allowlist: Vec<PathBuf>,
+}
+
+/// api keys of the http gateway, no auth when none is configured
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct HttpAuthConfig {
+    /// upload apps and manage the cluster, invoke all the functions as well
+    #[serde(default)]
+    pub admin_keys: Vec<String>,
+    /// invoke the private functions
+    #[serde(default)]
+    pub invoke_keys: Vec<String>,
+}
+
+impl HttpAuthConfig {
+    pub fn enabled(&self) -> bool {
+        !self.admin_keys.is_empty() || !self.invoke_keys.is_empty()
+    }
+}
+
+impl std::fmt::Debug for HttpAuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "HttpAuthConfig({} admin keys, {} invoke keys)",
+            self.admin_keys.len(),
+            self.invoke_keys.len()
+        )
+    }
+}
+
+/// bounds of the function logs kept in memory on each node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FnLogsConfig {
+    /// the oldest lines are dropped when exceeded
+    #[serde(default = "FnLogsConfig::default_max_lines")]
+    pub max_lines: usize,
+    /// longer lines are truncated
+    #[serde(default = "FnLogsConfig::default_max_line_bytes")]
+    pub max_line_bytes: usize,
+}
+
+impl FnLogsConfig {
+    fn default_max_lines() -> usize {
+        10000
+    }
+    fn default_max_line_bytes() -> usize {
+        4096
+    }
+}
+
+impl Default for FnLogsConfig {
+    fn default() -> Self {
+        Self {
+            max_lines: Self::default_max_lines(),
+            max_line_bytes: Self::default_max_line_bytes(),
+        }
+    }
+}
+
+/// the resource metrics each node reports to master, also taken as the heartbeat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricConfig {
+    #[serde(default = "MetricConfig::default_report_interval_ms")]
+    pub report_interval_ms: u64,
+}
+
+impl MetricConfig {
+    fn default_report_interval_ms() -> u64 {
+        1000
+    }
+    pub fn report_interval(&self) -> Duration {
+        Duration::from_millis(self.report_interval_ms.max(1))
+    }
+}
+
+impl Default for MetricConfig {
+    fn default() -> Self {
+        Self {
+            report_interval_ms: Self::default_report_interval_ms(),
+        }
+    }
+}
+
+/// same as proto data::DataModeDistribute
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AppPackDistribute {
+    /// every node holds the package
+    #[default]
+    Broadcast,
+    /// `replica_cnt` nodes hold it, the others pull when they run the app
+    Replicate,
+    /// only the uploading node holds it
+    OnDemand,
+}
+
+/// how the uploaded app packages and their metas are spread over the nodes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppPackConfig {
+    #[serde(default)]
+    pub distribute: AppPackDistribute,
+    /// only for replicate, the uploading node is counted in
+    #[serde(default = "AppPackConfig::default_replica_cnt")]
+    pub replica_cnt: u32,
+}
+
+impl AppPackConfig {
+    fn default_replica_cnt() -> u32 {
+        3
+    }
+}
+
+impl Default for AppPackConfig {
+    fn default() -> Self {
+        Self {
+            distribute: AppPackDistribute::default(),
+            replica_cnt: Self::default_replica_cnt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+/// rotated log files besides the stdout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogFileConfig {
+    /// relative one is under the file dir
+    pub dir: PathBuf,
+    /// `node{id}.log` by default
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// the oldest files are removed when exceeded, keep all if absent
+    pub max_files: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogConfig {
+    /// directives like `info,wasm_serverless::worker=debug`, overridden by env WS_LOG
+    #[serde(default = "LogConfig::default_level")]
+    pub level: String,
+    #[serde(default)]
+    pub json: bool,
+    pub file: Option<LogFileConfig>,
+    /// export the spans by otlp http, like `http://127.0.0.1:4318`, or env WS_OTLP_ENDPOINT
+    pub otlp_endpoint: Option<String>,
+}
+
+impl LogConfig {
+    fn default_level() -> String {
+        "debug,async_raft=off,hyper=off".to_owned()
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: Self::default_level(),
+            json: false,
+            file: None,
+            otlp_endpoint: None,
+        }
+    }
+}
+
+/// peers change at runtime when nodes join or leave,
+/// all the clones share the same membership
 #[derive(Debug, Clone)]
 pub struct NodesConfig {
-    pub peers: HashMap<NodeID, NodeConfig>,
+    peers: Arc<RwLock<HashMap<NodeID, NodeConfig>>>,
+    /// nodes being decommissioned, no more new work is placed on them
+    draining: Arc<RwLock<HashSet<NodeID>>>,
+    /// the nodes not alive, absent ones are alive
+    health: Arc<RwLock<HashMap<NodeID, NodeHealth>>>,
     pub this: (NodeID, NodeConfig),
     pub file_dir: PathBuf,
+    pub cluster_secret: ClusterSecret,
+    pub remote_sys: RemoteSysConfig,
+    pub http_auth: HttpAuthConfig,
+    pub fn_logs: FnLogsConfig,
+    pub log: LogConfig,
+    pub metric: MetricConfig,
+    pub app_pack: AppPackConfig,
 }
 
 impl NodesConfig {
-    pub fn get_nodeconfig(&self, id: NodeID) -> &NodeConfig {
+    pub fn get_nodeconfig(&self, id: NodeID) -> NodeConfig {
         if self.this.0 == id {
-            &self.this.1
+            self.this.1.clone()
         } else {
-            self.peers.get(&id).unwrap_or_else(|| {
-                panic!("peers {:?}", self.peers);
+            self.get_peer(id).unwrap_or_else(|| {
+                panic!("peers {:?}", self.peers.read());
             })
         }
     }
+    pub fn get_peer(&self, id: NodeID) -> Option<NodeConfig> {
+        self.peers.read().get(&id).cloned()
+    }
+    pub fn peers(&self) -> HashMap<NodeID, NodeConfig> {
+        self.peers.read().clone()
+    }
+    pub fn peer_ids(&self) -> Vec<NodeID> {
+        self.peers.read().keys().cloned().collect()
+    }
+    pub fn find_peer_id(&self, addr: &SocketAddr) -> Option<NodeID> {
+        self.peers
+            .read()
+            .iter()
+            .find_map(|(id, peer)| if peer.addr == *addr { Some(*id) } else { None })
+    }
+    /// return the old config if the peer was known
+    pub fn add_peer(&self, id: NodeID, config: NodeConfig) -> Option<NodeConfig> {
+        assert!(id != self.this.0);
+        self.peers.write().insert(id, config)
+    }
+    pub fn remove_peer(&self, id: NodeID) -> Option<NodeConfig> {
+        let _ = self.draining.write().remove(&id);
+        let _ = self.health.write().remove(&id);
+        self.peers.write().remove(&id)
+    }
+    pub fn set_draining(&self, id: NodeID, draining: bool) {
+        let mut set = self.draining.write();
+        if draining {
+            let _ = set.insert(id);
+        } else {
+            let _ = set.remove(&id);
+        }
+    }
+    pub fn is_draining(&self, id: NodeID) -> bool {
+        self.draining.read().contains(&id)
+    }
+    pub fn node_health(&self, id: NodeID) -> NodeHealth {
+        self.health
+            .read()
+            .get(&id)
+            .cloned()
+            .unwrap_or(NodeHealth::Alive)
+    }
+    /// return the old one
+    pub fn set_node_health(&self, id: NodeID, health: NodeHealth) -> NodeHealth {
+        let old = if health == NodeHealth::Alive {
+            self.health.write().remove(&id)
+        } else {
+            self.health.write().insert(id, health)
+        };
+        old.unwrap_or(NodeHealth::Alive)
+    }
+    pub fn is_alive(&self, id: NodeID) -> bool {
+        self.node_health(id) == NodeHealth::Alive
+    }
+    /// the peers could be reached now
+    pub fn alive_peer_ids(&self) -> Vec<NodeID> {
+        let health = self.health.read();
+        self.peers
+            .read()
+            .keys()
+            .filter(|id| !health.contains_key(id))
+            .cloned()
+            .collect()
+    }
     pub fn node_cnt(&self) -> usize {
-        self.peers.len() + 1
+        self.peers.read().len() + 1
     }
     pub fn this_node(&self) -> NodeID {
         self.this.0
@@ -34,34 +325,38 @@ impl NodesConfig {
         if self.this.1.is_master() {
             return self.this.0;
         }
-        *self
-            .peers
+        let peers = self.peers.read();
+        *peers
             .iter()
             .find(|(_, config)| config.is_master())
             .unwrap_or_else(|| {
-                panic!("peers {:?}", self.peers);
+                panic!("peers {:?}", peers);
             })
             .0
     }
     pub fn get_meta_kv_nodes(&self) -> HashSet<NodeID> {
         self.peers
+            .read()
             .iter()
             .filter(|(_, config)| config.spec.contains("meta"))
             .map(|(id, _)| *id)
             .collect()
     }
+    /// draining and not alive workers are excluded
     pub fn get_worker_nodes(&self) -> HashSet<NodeID> {
+        let draining = self.draining.read();
+        let health = self.health.read();
         self.peers
+            .read()
             .iter()
-            .filter(|(_, config)| config.is_worker())
+            .filter(|(id, config)| {
+                config.is_worker() && !draining.contains(id) && !health.contains_key(id)
+            })
             .map(|(id, _)| *id)
             .collect()
     }
     pub fn node_exist(&self, id: NodeID) -> bool {
-        self.peers.contains_key(&id) || self.this.0 == id
-    }
-    pub fn all_nodes_iter<'a>(&'a self) -> impl Iterator<Item = (&'a NodeID, &'a NodeConfig)> {
-        self.peers.iter().chain(Some((&self.this.0, &self.this.1)))
+        self.peers.read().contains_key(&id) || self.this.0 == id
     }
 }
 
@@ -82,6 +377,9 @@ impl NodeConfig {
     pub fn is_worker(&self) -> bool {
         self.spec.contains("worker")
     }
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
     pub fn set_domain(&mut self, domain: Option<String>) {
         self.domain = domain;
     }
@@ -112,6 +410,21 @@ impl NodeConfig {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct YamlConfig {
     pub nodes: HashMap<NodeID, NodeConfig>,
+    /// overridden by env WS_CLUSTER_SECRET
+    pub cluster_secret: Option<String>,
+    /// disabled on this node anyway if env WS_REMOTE_SYS_DISABLE is set
+    #[serde(default)]
+    pub remote_sys: RemoteSysConfig,
+    #[serde(default)]
+    pub http_auth: HttpAuthConfig,
+    #[serde(default)]
+    pub fn_logs: FnLogsConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub metric: MetricConfig,
+    #[serde(default)]
+    pub app_pack: AppPackConfig,
     // pub this: NodeID,
 }
 
@@ -124,13 +437,59 @@ fn read_yaml_config(file_path: impl AsRef<Path>) -> YamlConfig {
     })
 }
 
+const MIN_CLUSTER_SECRET_LEN: usize = 16;
+
 pub fn read_config(this_id: NodeID, file_path: impl AsRef<Path>) -> NodesConfig {
     let config_path = file_path.as_ref().join("files/node_config.yaml");
     let mut yaml_config = read_yaml_config(config_path);
+    let cluster_secret = std::env::var("WS_CLUSTER_SECRET")
+        .ok()
+        .or(yaml_config.cluster_secret.take())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| {
+            panic!("cluster_secret is required in node_config.yaml or env WS_CLUSTER_SECRET");
+        });
+    if cluster_secret.len() < MIN_CLUSTER_SECRET_LEN {
+        panic!(
+            "cluster_secret should be at least {} bytes, e.g. `openssl rand -hex 32`",
+            MIN_CLUSTER_SECRET_LEN
+        );
+    }
+    if yaml_config.app_pack.distribute == AppPackDistribute::Replicate
+        && yaml_config.app_pack.replica_cnt == 0
+    {
+        panic!("app_pack.replica_cnt should be at least 1 to replicate");
+    }
+    if std::env::var_os("WS_REMOTE_SYS_DISABLE").is_some() {
+        yaml_config.remote_sys.enable = false;
+    }
+    if let Some(level) = std::env::var("WS_LOG").ok().filter(|s| !s.is_empty()) {
+        yaml_config.log.level = level;
+    }
+    if let Some(endpoint) = std::env::var("WS_OTLP_ENDPOINT")
+        .ok()
+        .filter(|s| !s.is_empty())
+    {
+        yaml_config.log.otlp_endpoint = Some(endpoint);
+    }
+    if let Some(file) = yaml_config.log.file.as_mut() {
+        if file.dir.is_relative() {
+            file.dir = file_path.as_ref().join(&file.dir);
+        }
+    }
 
     NodesConfig {
         this: (this_id, yaml_config.nodes.remove(&this_id).unwrap()),
-        peers: yaml_config.nodes,
+        peers: Arc::new(RwLock::new(yaml_config.nodes)),
+        draining: Arc::new(RwLock::new(HashSet::new())),
+        health: Arc::new(RwLock::new(HashMap::new())),
         file_dir: file_path.as_ref().to_path_buf(),
+        cluster_secret: ClusterSecret(cluster_secret),
+        remote_sys: yaml_config.remote_sys,
+        http_auth: yaml_config.http_auth,
+        fn_logs: yaml_config.fn_logs,
+        log: yaml_config.log,
+        metric: yaml_config.metric,
+        app_pack: yaml_config.app_pack,
     }
 }
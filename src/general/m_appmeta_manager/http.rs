@@ -1,11 +1,17 @@
+use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use axum::extract::{DefaultBodyLimit, Multipart, Path};
-use axum::http::StatusCode;
+use axum::extract::{DefaultBodyLimit, Extension, Multipart, Path, Query};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
+use axum::Json;
 use axum::{routing::post, Router};
 use lazy_static::lazy_static;
+use tracing::Instrument;
 
+use super::limits::{InvokeLimits, Throttled};
+use crate::general::network::http_handler::ApiRole;
+use crate::logging;
 use crate::master::m_master::ScheduleWorkload;
 use crate::util;
 
@@ -30,8 +36,82 @@ pub(super) fn binds(router: Router, view: super::View) -> Router {
     // ))
 }
 
-async fn call_app_fn(Path((app, func)): Path<(String, String)>, body: String) -> Response {
-    if view().p2p().nodes_config.this.1.is_master() {
+/// whether public, the app limits and the function limits
+async fn fn_invoke_meta(app: &str, func: &str) -> Option<(bool, InvokeLimits, InvokeLimits)> {
+    let appmeta_man = view().appmeta_manager().meta.read().await;
+    let appmeta = appmeta_man.get_app_meta(app).await?;
+    let fnmeta = appmeta.get_fn_meta(func)?;
+    Some((fnmeta.public, appmeta.limits, fnmeta.limits))
+}
+
+fn throttled_response(app: &str, func: &str, throttled: Throttled) -> Response {
+    tracing::debug!("throttled {}/{}: {:?}", app, func, throttled);
+    let retry_after = throttled.retry_after();
+    let mut resp = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(serde_json::json!({
+            "error": format!("{:?}", throttled),
+            "retry_after_ms": retry_after.as_millis() as u64,
+        })),
+    )
+        .into_response();
+    let _ = resp.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(throttled.retry_after_secs()),
+    );
+    resp
+}
+
+/// app names become dir names and data keys
+fn valid_app_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// the trace starts here, or continues the one of the client by `traceparent`,
+/// which is in the query when redirected by master
+async fn call_app_fn(
+    Path((app, func)): Path<(String, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(role): Extension<ApiRole>,
+    body: String,
+) -> Response {
+    let span = tracing::info_span!("http_invoke", app = %app, func = %func);
+    logging::set_remote_parent(&span, &logging::trace_ctx_from_request(&headers, &query));
+    invoke_app_fn(app, func, role, body).instrument(span).await
+}
+
+async fn invoke_app_fn(app: String, func: String, role: ApiRole, body: String) -> Response {
+    let invoke_meta = fn_invoke_meta(&app, &func).await;
+    // the ones without api key could only call the public functions
+    if role < ApiRole::Invoke && !invoke_meta.as_ref().map_or(false, |m| m.0) {
+        return (StatusCode::UNAUTHORIZED, "api key required").into_response();
+    }
+    let is_master = view().p2p().nodes_config.this.1.is_master();
+    let _in_flight = match invoke_meta {
+        Some((_, app_limits, fn_limits)) => {
+            // master only redirects, the calls are in flight on the workers,
+            // so only the rate applies here
+            let (app_limits, fn_limits) = if is_master {
+                (app_limits.rate_only(), fn_limits.rate_only())
+            } else {
+                (app_limits, fn_limits)
+            };
+            match view()
+                .appmeta_manager()
+                .limiter
+                .acquire(&app, &func, &app_limits, &fn_limits)
+            {
+                Ok(guard) => Some(guard),
+                Err(throttled) => return throttled_response(&app, &func, throttled),
+            }
+        }
+        None => None,
+    };
+    if is_master {
         view()
             .http_handler()
             .handle_request(&format!("{app}/{func}"), body)
@@ -68,8 +148,13 @@ async fn call_app_fn(Path((app, func)): Path<(String, String)>, body: String) ->
         // inject `req_arrive_time`
         let res = res.map(|v| {
             v.map(|v| {
-                let mut res: serde_json::Value = serde_json::from_str(&v).unwrap();
-                let _ = res.as_object_mut().unwrap().insert(
+                let Ok(mut res) = serde_json::from_str::<serde_json::Value>(&v) else {
+                    return v;
+                };
+                let Some(obj) = res.as_object_mut() else {
+                    return v;
+                };
+                let _ = obj.insert(
                     "req_arrive_time".to_owned(),
                     serde_json::Value::from(req_arrive_time),
                 );
@@ -88,7 +173,9 @@ async fn upload_app(mut multipart: Multipart) -> Response {
     tracing::debug!("upload_app called");
     // only worker can upload app
     if view().p2p().nodes_config.this.1.is_master() {
-        let tar = view().master().schedule(ScheduleWorkload::JavaAppConstruct);
+        let Some(tar) = view().master().schedule(ScheduleWorkload::JavaAppConstruct) else {
+            return (StatusCode::SERVICE_UNAVAILABLE, "no alive worker").into_response();
+        };
 
         tracing::debug!("redirect 2 worker");
         return tar
@@ -98,7 +185,16 @@ async fn upload_app(mut multipart: Multipart) -> Response {
 
     let mut tasks = vec![];
     while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
+        let Some(name) = field.name().map(|n| n.to_string()) else {
+            return (StatusCode::BAD_REQUEST, "app name required").into_response();
+        };
+        if !valid_app_name(&name) {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("invalid app name {}", name),
+            )
+                .into_response();
+        }
         // let file_name = field.file_name().unwrap().to_string();
         // let content_type = field.content_type().unwrap().to_string();
         let data = field.bytes().await.unwrap();
@@ -0,0 +1,59 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// outbound http of the wasm functions declared in app.yaml, no host is allowed if absent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpOutMeta {
+    /// `host`, `host:port` or `*.domain`, ipv6 ones in brackets
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    #[serde(default = "HttpOutMeta::default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// the body is dropped and the call fails when exceeded
+    #[serde(default = "HttpOutMeta::default_max_resp_bytes")]
+    pub max_resp_bytes: usize,
+}
+
+impl HttpOutMeta {
+    fn default_timeout_ms() -> u64 {
+        5000
+    }
+    fn default_max_resp_bytes() -> usize {
+        1 << 20
+    }
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+    /// only http and https to the declared hosts
+    pub fn allow(&self, url: &Url) -> bool {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return false;
+        }
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            return false;
+        };
+        self.allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            if let Some(domain) = allowed.strip_prefix("*.") {
+                host.ends_with(&format!(".{}", domain))
+            } else if let Some((allowed_host, allowed_port)) =
+                allowed.rsplit_once(':').filter(|_| !allowed.ends_with(']'))
+            {
+                allowed_host == host && allowed_port == port.to_string()
+            } else {
+                allowed == host
+            }
+        })
+    }
+}
+
+impl Default for HttpOutMeta {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: vec![],
+            timeout_ms: Self::default_timeout_ms(),
+            max_resp_bytes: Self::default_max_resp_bytes(),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// declared for the app or a function in app.yaml, unlimited if absent
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct InvokeLimits {
+    /// invocations per second
+    pub rate: Option<f64>,
+    /// invocations could be saved up, one second of rate by default
+    pub burst: Option<f64>,
+    pub max_in_flight: Option<u32>,
+}
+
+impl InvokeLimits {
+    /// master only redirects the calls, they are in flight on the workers
+    pub fn rate_only(&self) -> Self {
+        Self {
+            max_in_flight: None,
+            ..*self
+        }
+    }
+    fn capacity(&self) -> Option<f64> {
+        self.rate.map(|rate| self.burst.unwrap_or(rate).max(1.0))
+    }
+}
+
+#[derive(Debug)]
+pub enum Throttled {
+    Rate { retry_after: Duration },
+    InFlight,
+}
+
+impl Throttled {
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            Throttled::Rate { retry_after } => *retry_after,
+            // no idea when the running ones finish
+            Throttled::InFlight => Duration::from_secs(1),
+        }
+    }
+    /// for the Retry-After header, whole seconds, at least one
+    pub fn retry_after_secs(&self) -> u64 {
+        (self.retry_after().as_millis() as u64).div_ceil(1000).max(1)
+    }
+}
+
+struct LimitState {
+    tokens: f64,
+    refilled: Instant,
+    in_flight: u32,
+}
+
+impl LimitState {
+    fn refill(&mut self, limits: &InvokeLimits, now: Instant) {
+        if let (Some(rate), Some(capacity)) = (limits.rate, limits.capacity()) {
+            let elapsed = now.duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        }
+        self.refilled = now;
+    }
+    fn check(&self, limits: &InvokeLimits) -> Result<(), Throttled> {
+        if let Some(rate) = limits.rate {
+            if self.tokens < 1.0 {
+                return Err(Throttled::Rate {
+                    retry_after: Duration::try_from_secs_f64((1.0 - self.tokens) / rate)
+                        .unwrap_or(Duration::from_secs(60)),
+                });
+            }
+        }
+        if let Some(max) = limits.max_in_flight {
+            if self.in_flight >= max {
+                return Err(Throttled::InFlight);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// token buckets and in-flight counts of the apps and functions on this node
+#[derive(Clone, Default)]
+pub struct InvokeLimiter {
+    states: Arc<Mutex<HashMap<String, LimitState>>>,
+}
+
+/// the invocation is counted in flight until dropped
+pub struct InFlightGuard {
+    states: Arc<Mutex<HashMap<String, LimitState>>>,
+    scopes: Vec<String>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut states = self.states.lock();
+        for scope in &self.scopes {
+            if let Some(state) = states.get_mut(scope) {
+                state.in_flight = state.in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+impl InvokeLimiter {
+    /// both the app and the function limits must pass, nothing is taken if throttled
+    pub fn acquire(
+        &self,
+        app: &str,
+        func: &str,
+        app_limits: &InvokeLimits,
+        fn_limits: &InvokeLimits,
+    ) -> Result<InFlightGuard, Throttled> {
+        self.acquire_at(app, func, app_limits, fn_limits, Instant::now())
+    }
+    fn acquire_at(
+        &self,
+        app: &str,
+        func: &str,
+        app_limits: &InvokeLimits,
+        fn_limits: &InvokeLimits,
+        now: Instant,
+    ) -> Result<InFlightGuard, Throttled> {
+        let scopes = [
+            (app.to_owned(), app_limits),
+            (format!("{}/{}", app, func), fn_limits),
+        ];
+        let mut states = self.states.lock();
+        for (scope, limits) in scopes.iter() {
+            let state = states.entry(scope.clone()).or_insert_with(|| LimitState {
+                tokens: limits.capacity().unwrap_or(0.0),
+                refilled: now,
+                in_flight: 0,
+            });
+            state.refill(limits, now);
+            state.check(limits)?;
+        }
+        for (scope, limits) in scopes.iter() {
+            let state = states.get_mut(scope).unwrap();
+            if limits.rate.is_some() {
+                state.tokens -= 1.0;
+            }
+            state.in_flight += 1;
+        }
+        Ok(InFlightGuard {
+            states: self.states.clone(),
+            scopes: scopes.into_iter().map(|(scope, _)| scope).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rate(rate: f64, burst: Option<f64>) -> InvokeLimits {
+        InvokeLimits {
+            rate: Some(rate),
+            burst,
+            max_in_flight: None,
+        }
+    }
+
+    fn is_rate_throttled(res: Result<InFlightGuard, Throttled>) -> bool {
+        matches!(res, Err(Throttled::Rate { .. }))
+    }
+
+    #[test]
+    fn test_limiter_burst() {
+        let limiter = InvokeLimiter::default();
+        let t0 = Instant::now();
+        let none = InvokeLimits::default();
+        let limits = rate(1.0, Some(3.0));
+        for _ in 0..3 {
+            assert!(limiter.acquire_at("a", "f", &limits, &none, t0).is_ok());
+        }
+        assert!(is_rate_throttled(
+            limiter.acquire_at("a", "f", &limits, &none, t0)
+        ));
+
+        // one second of rate without burst
+        let limits = rate(2.0, None);
+        for _ in 0..2 {
+            assert!(limiter.acquire_at("b", "f", &none, &limits, t0).is_ok());
+        }
+        assert!(is_rate_throttled(
+            limiter.acquire_at("b", "f", &none, &limits, t0)
+        ));
+    }
+
+    #[test]
+    fn test_limiter_refill() {
+        let limiter = InvokeLimiter::default();
+        let t0 = Instant::now();
+        let none = InvokeLimits::default();
+        let limits = rate(1.0, Some(2.0));
+        for _ in 0..2 {
+            assert!(limiter.acquire_at("a", "f", &limits, &none, t0).is_ok());
+        }
+        let t1 = t0 + Duration::from_millis(500);
+        assert!(is_rate_throttled(
+            limiter.acquire_at("a", "f", &limits, &none, t1)
+        ));
+        let t2 = t0 + Duration::from_secs(1);
+        assert!(limiter.acquire_at("a", "f", &limits, &none, t2).is_ok());
+
+        // saved up to the burst only
+        let t3 = t2 + Duration::from_secs(100);
+        for _ in 0..2 {
+            assert!(limiter.acquire_at("a", "f", &limits, &none, t3).is_ok());
+        }
+        assert!(is_rate_throttled(
+            limiter.acquire_at("a", "f", &limits, &none, t3)
+        ));
+    }
+
+    #[test]
+    fn test_limiter_in_flight() {
+        let limiter = InvokeLimiter::default();
+        let t0 = Instant::now();
+        let none = InvokeLimits::default();
+        let limits = InvokeLimits {
+            max_in_flight: Some(2),
+            ..Default::default()
+        };
+        let g1 = limiter.acquire_at("a", "f", &none, &limits, t0).unwrap();
+        let _g2 = limiter.acquire_at("a", "f", &none, &limits, t0).unwrap();
+        assert!(matches!(
+            limiter.acquire_at("a", "f", &none, &limits, t0),
+            Err(Throttled::InFlight)
+        ));
+        // other functions of the app are not counted
+        assert!(limiter.acquire_at("a", "g", &none, &limits, t0).is_ok());
+        drop(g1);
+        assert!(limiter.acquire_at("a", "f", &none, &limits, t0).is_ok());
+        assert!(limiter
+            .acquire_at("a", "f", &none, &limits.rate_only(), t0)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_limiter_throttled_takes_nothing() {
+        let limiter = InvokeLimiter::default();
+        let t0 = Instant::now();
+        let app_limits = rate(1.0, Some(1.0));
+        let fn_limits = InvokeLimits {
+            max_in_flight: Some(0),
+            ..Default::default()
+        };
+        assert!(limiter
+            .acquire_at("a", "f", &app_limits, &fn_limits, t0)
+            .is_err());
+        // the token of the app is still there
+        assert!(limiter
+            .acquire_at("a", "g", &app_limits, &InvokeLimits::default(), t0)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_limiter_retry_after() {
+        let limiter = InvokeLimiter::default();
+        let t0 = Instant::now();
+        let none = InvokeLimits::default();
+        let limits = rate(0.25, Some(1.0));
+        assert!(limiter.acquire_at("a", "f", &limits, &none, t0).is_ok());
+        let t1 = t0 + Duration::from_secs(1);
+        let Err(throttled) = limiter.acquire_at("a", "f", &limits, &none, t1) else {
+            panic!("not throttled");
+        };
+        // 0.25 of the token is back, 3 seconds to a whole one
+        assert_eq!(throttled.retry_after(), Duration::from_secs(3));
+        assert_eq!(throttled.retry_after_secs(), 3);
+
+        let throttled = Throttled::Rate {
+            retry_after: Duration::from_millis(1200),
+        };
+        assert_eq!(throttled.retry_after_secs(), 2);
+        let throttled = Throttled::Rate {
+            retry_after: Duration::ZERO,
+        };
+        assert_eq!(throttled.retry_after_secs(), 1);
+        assert_eq!(Throttled::InFlight.retry_after_secs(), 1);
+    }
+}
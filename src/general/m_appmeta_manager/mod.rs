@@ -1,37 +1,42 @@
 pub mod fn_event;
 mod http;
+pub mod http_out;
+pub mod limits;
 mod v_os;
 
-use self::v_os::AppMetaVisitOs;
+use self::{
+    http_out::HttpOutMeta,
+    limits::{InvokeLimiter, InvokeLimits},
+    v_os::AppMetaVisitOs,
+};
 use super::{
-    m_data_general::DataGeneral,
+    m_data_general::{DataGeneral, DataVersion, LocalDataItem},
     m_kv_store_engine::{KeyTypeServiceList, KvStoreEngine},
     m_os::OperatingSystem,
     network::{
         http_handler::HttpHandler,
         m_p2p::P2PModule,
         proto::{
-            write_one_data_request::{
-                data_item::{self, Data},
-                DataItem, FileData,
-            },
+            kv::{kv_request, KvRequest},
             DataMeta, DataModeCache, DataModeDistribute,
         },
     },
 };
 use crate::worker::m_executor::Executor;
 use crate::{
+    config::AppPackDistribute,
     general::kv_interface::KvOps,
     logical_module_view_impl,
     master::m_master::Master,
-    result::{ErrCvt, WSResult, WsFuncError},
+    result::{ErrCvt, WSResult, WsFuncError, WsPermissionErr},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
-    util::{self, JoinHandleWrapper},
+    util::{self, JoinHandleWrapper, TryUtf8VecU8},
     worker::func::m_instance_manager::InstanceManager,
 };
 use async_trait::async_trait;
 use axum::body::Bytes;
 use enum_as_inner::EnumAsInner;
+use parking_lot::Mutex;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::{
     borrow::Borrow,
@@ -39,6 +44,7 @@ use std::{
     fs,
     io::Cursor,
     path::Path,
+    sync::Arc,
 };
 use tokio::sync::RwLock;
 
@@ -125,6 +131,9 @@ pub struct FnMetaYaml {
     /// key to operations
     pub calls: Vec<FnCallMeta>,
     pub kvs: Option<BTreeMap<String, Vec<String>>>,
+    /// callable through http without api key
+    pub public: bool,
+    pub limits: InvokeLimits,
 }
 
 impl<'de> Deserialize<'de> for FnMetaYaml {
@@ -184,8 +193,24 @@ impl<'de> Deserialize<'de> for FnMetaYaml {
             None
         };
 
+        let limits = match map.remove("limits") {
+            Some(limits) => serde_yaml::from_value(limits).map_err(serde::de::Error::custom)?,
+            None => InvokeLimits::default(),
+        };
+        let public = match map.get("public") {
+            Some(v) => v
+                .as_bool()
+                .ok_or_else(|| serde::de::Error::custom("public is not a bool"))?,
+            None => false,
+        };
+
         tracing::debug!("FnMetaYaml constructed, calls:{:?}", calls);
-        Ok(Self { calls, kvs })
+        Ok(Self {
+            calls,
+            kvs,
+            public,
+            limits,
+        })
     }
 }
 
@@ -200,17 +225,37 @@ pub struct KvMeta {
     pub pattern: KeyPattern,
 }
 
+impl KvMeta {
+    pub fn allow(&self, ope: KvOps) -> bool {
+        match ope {
+            KvOps::Get => self.get,
+            KvOps::Set => self.set,
+            KvOps::Delete => self.delete,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FnMeta {
     pub calls: Vec<FnCallMeta>,
     // pub event: Vec<FnEvent>,
     // pub args: Vec<FnArg>,
     pub kvs: Option<Vec<KvMeta>>,
+    pub public: bool,
+    pub limits: InvokeLimits,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AppMetaYaml {
     pub fns: HashMap<String, FnMetaYaml>,
+    /// other app 2 the keys of this app it could access, as `@this_app/key`
+    #[serde(default)]
+    pub kv_shares: HashMap<String, BTreeMap<String, Vec<String>>>,
+    /// for all the functions of the app together
+    #[serde(default)]
+    pub limits: InvokeLimits,
+    #[serde(default)]
+    pub http_out: HttpOutMeta,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -223,6 +268,10 @@ pub enum AppType {
 pub struct AppMeta {
     pub app_type: AppType,
     fns: HashMap<String, FnMeta>,
+    /// other app 2 the kvs shared with it
+    kv_shares: HashMap<String, Vec<KvMeta>>,
+    pub limits: InvokeLimits,
+    pub http_out: HttpOutMeta,
     cache_contains_http_fn: Option<bool>,
 }
 
@@ -242,6 +291,37 @@ impl AppMeta {
         }
         res
     }
+    /// whether the key of this app is shared with the other app for the operation
+    pub fn kv_shared_with(&self, app: &str, key: &[u8], ope: KvOps) -> bool {
+        let Ok(key) = std::str::from_utf8(key) else {
+            return false;
+        };
+        self.kv_shares.get(app).map_or(false, |kvs| {
+            kvs.iter().any(|kv| kv.allow(ope) && kv.pattern.match_key(key))
+        })
+    }
+}
+
+/// `@owner/key` refers to the key in the kv namespace of the owner app
+pub fn split_shared_kv_key(key: &[u8]) -> Option<(&str, &[u8])> {
+    let key = key.strip_prefix(b"@")?;
+    let split = key.iter().position(|b| *b == b'/')?;
+    let owner = std::str::from_utf8(&key[..split]).ok()?;
+    if owner.is_empty() {
+        return None;
+    }
+    Some((owner, &key[split + 1..]))
+}
+
+/// the key operated by the request, lock takes set
+pub fn kv_request_key(req: &KvRequest) -> Option<(&[u8], KvOps)> {
+    let (key, ope) = match req.op.as_ref()? {
+        kv_request::Op::Set(set) => (&set.kv.as_ref()?.key, KvOps::Set),
+        kv_request::Op::Get(get) => (&get.range.as_ref()?.start, KvOps::Get),
+        kv_request::Op::Delete(delete) => (&delete.range.as_ref()?.start, KvOps::Delete),
+        kv_request::Op::Lock(lock) => (&lock.range.as_ref()?.start, KvOps::Set),
+    };
+    Some((key.as_slice(), ope))
 }
 
 // #[derive(Debug, Serialize, Deserialize)]
@@ -254,6 +334,8 @@ impl AppMeta {
 pub struct AppMetas {
     app_metas: HashMap<String, AppMeta>,
     pattern_2_app_fn: HashMap<String, Vec<(String, String)>>,
+    /// decoded from the meta data item, replaced when the data version changes
+    decoded: Mutex<HashMap<String, (DataVersion, Arc<AppMeta>)>>,
 }
 
 // impl FnEvent {
@@ -306,6 +388,17 @@ impl FnMeta {
             _ => None,
         })
     }
+    /// return the method when the function is declared with `call: direct`,
+    /// which means the app listens on its own http port and the worker just forwards
+    pub fn direct_http_call(&self) -> Option<HttpMethod> {
+        self.calls.iter().find_map(|v| match v {
+            FnCallMeta::Http {
+                method,
+                call: HttpCall::Direct,
+            } => Some(method.clone()),
+            _ => None,
+        })
+    }
 
     pub fn match_key(&self, key: &[u8], ope: KvOps) -> Option<KeyPattern> {
         let key = if let Ok(key) = std::str::from_utf8(key) {
@@ -342,6 +435,21 @@ impl FnMeta {
         None
     }
 
+    /// every key in the batch must be declared with the operation
+    pub fn check_kv_requests(&self, app: &str, func: &str, reqs: &[KvRequest]) -> WSResult<()> {
+        for (key, ope) in reqs.iter().filter_map(kv_request_key) {
+            if self.match_key(key, ope).is_none() {
+                return Err(WsPermissionErr::AccessKeyPermissionDenied {
+                    app: app.to_owned(),
+                    func: func.to_owned(),
+                    access_key: TryUtf8VecU8(key.to_owned()),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
     pub fn try_get_kv_meta_by_index(&self, index: usize) -> Option<&KvMeta> {
         if let Some(kvs) = &self.kvs {
             return kvs.get(index);
@@ -429,42 +537,42 @@ impl KeyPattern {
     // }
 }
 
+fn kv_metas_from_yaml(kvs: BTreeMap<String, Vec<String>>) -> Vec<KvMeta> {
+    kvs.into_iter()
+        .map(|(key, ops)| {
+            let mut set = false;
+            let mut get = false;
+            let mut delete = false;
+            for op in ops {
+                if op == "set" {
+                    set = true;
+                } else if op == "get" {
+                    get = true;
+                } else if op == "delete" {
+                    delete = true;
+                } else {
+                    panic!("invalid operation: {}", op);
+                }
+            }
+            // TODO: check key pattern
+            KvMeta {
+                delete,
+                set,
+                get,
+                pattern: KeyPattern::new(key),
+            }
+        })
+        .collect()
+}
+
 impl From<FnMetaYaml> for FnMeta {
     fn from(yaml: FnMetaYaml) -> Self {
-        let kvs = if let Some(kvs) = yaml.kvs {
-            Some(
-                kvs.into_iter()
-                    .map(|(key, ops)| {
-                        let mut set = false;
-                        let mut get = false;
-                        let mut delete = false;
-                        for op in ops {
-                            if op == "set" {
-                                set = true;
-                            } else if op == "get" {
-                                get = true;
-                            } else if op == "delete" {
-                                delete = true;
-                            } else {
-                                panic!("invalid operation: {}", op);
-                            }
-                        }
-                        // TODO: check key pattern
-                        KvMeta {
-                            delete,
-                            set,
-                            get,
-                            pattern: KeyPattern::new(key),
-                        }
-                    })
-                    .collect(),
-            )
-        } else {
-            None
-        };
+        let kvs = yaml.kvs.map(kv_metas_from_yaml);
         let res = Self {
             calls: yaml.calls,
             kvs,
+            public: yaml.public,
+            limits: yaml.limits,
         };
         // assert!(res.check_kv_valid());
         res
@@ -488,6 +596,10 @@ impl AppMeta {
         app_name: &str,
         meta_fs: &AppMetaVisitOs,
     ) -> WSResult<Self> {
+        let app_type = meta_fs.get_app_type(app_name).await?;
+        Ok(Self::from_yaml(metayaml, app_type))
+    }
+    pub fn from_yaml(metayaml: AppMetaYaml, app_type: AppType) -> Self {
         let fns = metayaml
             .fns
             .into_iter()
@@ -496,12 +608,19 @@ impl AppMeta {
                 (fnname, fnmeta)
             })
             .collect();
-        let app_type = meta_fs.get_app_type(app_name).await?;
-        Ok(Self {
+        let kv_shares = metayaml
+            .kv_shares
+            .into_iter()
+            .map(|(app, kvs)| (app, kv_metas_from_yaml(kvs)))
+            .collect();
+        Self {
             app_type,
             fns,
+            kv_shares,
+            limits: metayaml.limits,
+            http_out: metayaml.http_out,
             cache_contains_http_fn: None,
-        })
+        }
     }
     pub fn fns(&self) -> Vec<String> {
         self.fns.iter().map(|(fnname, _)| fnname.clone()).collect()
@@ -531,6 +650,8 @@ fn view() -> &'static View {
 pub struct AppMetaManager {
     pub meta: RwLock<AppMetas>,
     pub fs_layer: AppMetaVisitOs,
+    /// http invocations on this node
+    pub limiter: InvokeLimiter,
     view: View,
     // app_meta_list_lock: Mutex<()>,
 }
@@ -550,9 +671,11 @@ impl LogicalModule for AppMetaManager {
             meta: RwLock::new(AppMetas {
                 app_metas: HashMap::new(),
                 pattern_2_app_fn: HashMap::new(),
+                decoded: Mutex::new(HashMap::new()),
             }),
             view,
             fs_layer,
+            limiter: InvokeLimiter::default(),
             // app_meta_list_lock: Mutex::new(()),
         }
     }
@@ -587,33 +710,64 @@ impl AppMetas {
      fn get_tmp_app_meta(&self, app: &str) -> Option<AppMeta> {
         self.app_metas.get(app).cloned()
     }
-    pub async fn get_app_meta(&self, app: &str) -> Option<AppMeta> {
+    /// decoded only when the version of the meta data changed
+    pub async fn get_app_meta(&self, app: &str) -> Option<Arc<AppMeta>> {
         if let Some(res)=self.get_tmp_app_meta(app){
-            return Some(res);
+            return Some(Arc::new(res));
         }
-        
-        // self.app_metas.get(app)
-        let meta = view()
+
+        let Some((version, metabytes)) = view()
             .data_general()
-            .get_data_item(format!("app{}", app), 0)
-            .await;
-        let Some(DataItem {
-            data: Some(Data::RawBytes(metabytes)),
-        }) = meta
+            .get_data_item(&format!("app{}", app), 0)
+            .await
         else {
+            // deleted
+            let _ = self.decoded.lock().remove(app);
             return None;
         };
+        if let Some((v, meta)) = self.decoded.lock().get(app) {
+            if *v == version {
+                return Some(meta.clone());
+            }
+        }
 
-        let meta = bincode::deserialize_from::<_, AppMeta>(Cursor::new(metabytes));
-        let meta = match meta {
+        let meta = match bincode::deserialize::<AppMeta>(&metabytes) {
             Err(e) => {
                 tracing::warn!("meta decode failed {:?}", e);
                 return None;
             }
-            Ok(meta) => meta,
+            Ok(meta) => Arc::new(meta),
         };
+        let _ = self
+            .decoded
+            .lock()
+            .insert(app.to_owned(), (version, meta.clone()));
         Some(meta)
     }
+    /// the keys of other apps in the batch must be shared with the app by their owners
+    pub async fn check_kv_shares(&self, app: &str, func: &str, reqs: &[KvRequest]) -> WSResult<()> {
+        for (key, ope) in reqs.iter().filter_map(kv_request_key) {
+            let Some((owner, owner_key)) = split_shared_kv_key(key) else {
+                continue;
+            };
+            if owner == app {
+                continue;
+            }
+            let shared = match self.get_app_meta(owner).await {
+                Some(owner_meta) => owner_meta.kv_shared_with(app, owner_key, ope),
+                None => false,
+            };
+            if !shared {
+                return Err(WsPermissionErr::AccessKeyPermissionDenied {
+                    app: app.to_owned(),
+                    func: func.to_owned(),
+                    access_key: TryUtf8VecU8(key.to_owned()),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
     pub fn get_pattern_triggers(
         &self,
         pattern: impl Borrow<str>,
@@ -732,10 +886,26 @@ impl AppMetaManager {
         Ok(self
             .view
             .data_general()
-            .get_data_item(format!("app{}", app), 0)
+            .get_data_item(&format!("app{}", app), 0)
             .await
             .is_some())
     }
+    /// the meta and the package are removed on all the nodes,
+    /// the kv of the app is dropped by master with it
+    pub async fn delete_app(&self, app: &str) -> WSResult<()> {
+        if !self.app_available(app).await? {
+            return Err(WsFuncError::AppNotFound {
+                app: app.to_owned(),
+            }
+            .into());
+        }
+        self.view
+            .data_general()
+            .delete_data(format!("app{}", app))
+            .await?;
+        tracing::info!("app {} deleted", app);
+        Ok(())
+    }
     pub async fn app_uploaded(&self, appname: String, data: Bytes) -> WSResult<()> {
         // 1. tmpapp name & dir
         // TODO: fobidden tmpapp public access
@@ -787,31 +957,21 @@ impl AppMetaManager {
             Ok(appmeta) => appmeta,
         };
 
-        // 4. zip tmp dir to memory
-        let zipfiledata = {
-            tracing::debug!("zip tmp dir to memory");
-            // if let Ok(direntries) = fs::read_dir(tmpappdir.join("checkpoint-dir")) {
-            //     for f in direntries {
-            //         tracing::debug!(
-            //             "file in checkpoint-dir: {:?}",
-            //             f.map(|v| v.file_name().to_str().unwrap().to_owned())
-            //         );
-            //     }
-            // }
-            let view = self.view.clone();
-            tokio::task::spawn_blocking(move || {
-                view.os()
-                    .zip_dir_2_data(&tmpappdir, zip::CompressionMethod::Deflated)
-            })
-            .await
-            .unwrap()
-        }?;
-
-        // remove temp dir
-        // let _ = fs::remove_dir_all(&tmpappdir).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+        // 4. zip tmp dir to a tmp file, streamed from the disk when sent
+        let appfile = self
+            .view
+            .data_general()
+            .local_file_item(format!("apps/{}", appname), true)
+            .await?;
 
-        // 3. broadcast meta and appfile
-        tracing::debug!("broadcast meta and appfile");
+        // 3. distribute meta and appfile as configured
+        let app_pack = &self.view.p2p().nodes_config.app_pack;
+        let distribute = match app_pack.distribute {
+            AppPackDistribute::Broadcast => DataModeDistribute::BroadcastRough,
+            AppPackDistribute::Replicate => DataModeDistribute::ReplicateN,
+            AppPackDistribute::OnDemand => DataModeDistribute::OnDemandPull,
+        } as i32;
+        tracing::debug!("distribute meta and appfile, {:?}", app_pack);
         self.view
             .data_general()
             .write_data(
@@ -819,26 +979,18 @@ impl AppMetaManager {
                 vec![
                     DataMeta {
                         cache: DataModeCache::AlwaysInMem as i32,
-                        distribute: DataModeDistribute::BroadcastRough as i32,
+                        distribute,
+                        replica_cnt: app_pack.replica_cnt,
                     },
                     DataMeta {
                         cache: DataModeCache::AlwaysInFs as i32,
-                        distribute: DataModeDistribute::BroadcastRough as i32,
+                        distribute,
+                        replica_cnt: app_pack.replica_cnt,
                     },
                 ],
                 vec![
-                    DataItem {
-                        data: Some(data_item::Data::RawBytes(
-                            bincode::serialize(&appmeta).unwrap(),
-                        )),
-                    },
-                    DataItem {
-                        data: Some(data_item::Data::File(FileData {
-                            file_name: format!("apps/{}", appname),
-                            is_dir: true,
-                            file_content: zipfiledata,
-                        })),
-                    },
+                    LocalDataItem::RawBytes(bincode::serialize(&appmeta).unwrap()),
+                    appfile,
                 ],
             )
             .await;
@@ -1047,10 +1199,127 @@ mod test {
     use crate::util;
 
     use super::*;
+    use crate::general::network::proto::kv::{
+        kv_request::{KvDeleteRequest, KvGetRequest, KvLockRequest, KvPutRequest, Op},
+        KeyRange, KvPair,
+    };
+
     #[test]
     fn test_key_pattern() {
         util::test_tracing_start();
         let pattern = KeyPattern::new("xxxx_{}_{}".to_owned());
         assert!(pattern.match_key("xxxx_abc_123"));
     }
+
+    fn app_meta() -> AppMeta {
+        let yaml: AppMetaYaml = serde_yaml::from_str(
+            r#"
+fns:
+  f:
+    rpc:
+    kvs:
+      user_{}: [get]
+      out_{}: [set, delete]
+kv_shares:
+  reader:
+    shared_{}: [get]
+"#,
+        )
+        .unwrap();
+        AppMeta::from_yaml(yaml, AppType::Wasm)
+    }
+
+    fn range(key: &str) -> Option<KeyRange> {
+        Some(KeyRange {
+            start: key.as_bytes().to_owned(),
+            end: vec![],
+        })
+    }
+
+    fn set(key: &str) -> KvRequest {
+        let kv = Some(KvPair {
+            key: key.as_bytes().to_owned(),
+            value: vec![1],
+        });
+        KvRequest {
+            op: Some(Op::Set(KvPutRequest { kv })),
+        }
+    }
+
+    fn get(key: &str) -> KvRequest {
+        KvRequest {
+            op: Some(Op::Get(KvGetRequest { range: range(key) })),
+        }
+    }
+
+    fn delete(key: &str) -> KvRequest {
+        KvRequest {
+            op: Some(Op::Delete(KvDeleteRequest { range: range(key) })),
+        }
+    }
+
+    fn lock(key: &str) -> KvRequest {
+        KvRequest {
+            op: Some(Op::Lock(KvLockRequest {
+                read_or_write: false,
+                release_id: vec![],
+                range: range(key),
+            })),
+        }
+    }
+
+    #[test]
+    fn test_check_kv_requests() {
+        let appmeta = app_meta();
+        let fnmeta = appmeta.get_fn_meta("f").unwrap();
+        let check = |reqs: &[KvRequest]| fnmeta.check_kv_requests("app", "f", reqs).is_ok();
+
+        assert!(check(&[get("user_1"), set("out_1"), delete("out_1")]));
+        assert!(!check(&[set("user_1")]));
+        assert!(!check(&[delete("user_1")]));
+        assert!(!check(&[get("out_1")]));
+        assert!(!check(&[get("other_1")]));
+        // one denied denies the batch
+        assert!(!check(&[get("user_1"), set("user_1")]));
+
+        // lock takes set
+        assert_eq!(kv_request_key(&lock("out_1")).unwrap().1, KvOps::Set);
+        assert!(check(&[lock("out_1")]));
+        assert!(!check(&[lock("user_1")]));
+    }
+
+    #[test]
+    fn test_split_shared_kv_key() {
+        assert_eq!(
+            split_shared_kv_key(b"@owner/key/1"),
+            Some(("owner", b"key/1".as_slice()))
+        );
+        assert_eq!(split_shared_kv_key(b"owner/key"), None);
+        assert_eq!(split_shared_kv_key(b"@owner"), None);
+        assert_eq!(split_shared_kv_key(b"@/key"), None);
+        assert_eq!(split_shared_kv_key(b"@\xff/key"), None);
+    }
+
+    #[tokio::test]
+    async fn test_check_kv_shares() {
+        let metas = AppMetas {
+            app_metas: HashMap::from([("owner".to_owned(), app_meta())]),
+            pattern_2_app_fn: HashMap::new(),
+            decoded: Mutex::new(HashMap::new()),
+        };
+        let check = |app: &'static str, reqs: Vec<KvRequest>| {
+            let metas = &metas;
+            async move { metas.check_kv_shares(app, "f", &reqs).await.is_ok() }
+        };
+
+        assert!(check("reader", vec![get("@owner/shared_1")]).await);
+        assert!(!check("reader", vec![set("@owner/shared_1")]).await);
+        assert!(!check("reader", vec![get("@owner/user_1")]).await);
+        assert!(!check("other", vec![get("@owner/shared_1")]).await);
+        // the own keys are checked by the fn meta
+        assert!(check("owner", vec![set("@owner/user_1")]).await);
+        assert!(check("reader", vec![set("user_1")]).await);
+        // malformed, taken as a key of the app itself
+        assert!(check("reader", vec![get("@owner")]).await);
+    }
 }
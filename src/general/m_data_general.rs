@@ -1,11 +1,15 @@
 use super::{
-    m_kv_store_engine::{KeyTypeDataSetItem, KeyTypeDataSetMeta, KvStoreEngine},
+    m_kv_store_engine::{
+        KeyTypeDataSetFileItem, KeyTypeDataSetItem, KeyTypeDataSetMeta, KvStoreEngine,
+    },
     m_os::OperatingSystem,
     network::{
         m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor},
         proto::{
-            write_one_data_request::{data_item::Data, DataItem},
-            DataMeta, DataModeDistribute, DataVersionRequest, WriteOneDataRequest,
+            write_one_data_request::{data_item::Data, DataItem, FileData},
+            DataHoldersRequest, DataMeta, DataModeCache, DataModeDistribute, DataVersionPhase,
+            DataVersionRequest, DataVersionResponse, PullDataRequest, PullDataResponse,
+            WriteDataChunkRequest, WriteDataChunkResponse, WriteOneDataRequest,
             WriteOneDataResponse,
         },
     },
@@ -18,12 +22,24 @@ use crate::{
     util::JoinHandleWrapper,
 };
 use crate::{
-    result::{WsDataError},
+    result::{WSError, WsDataError, WsIoErr},
     sys::LogicalModulesRef,
 };
 use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, time::Duration};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    os::unix::fs::FileExt,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use uuid::Uuid;
 use ws_derive::LogicalModule;
 
 // use super::m_appmeta_manager::AppMeta;
@@ -36,6 +52,17 @@ logical_module_view_impl!(DataGeneralView, os, OperatingSystem);
 
 pub type DataVersion = u64;
 
+/// files bigger than this are streamed by chunks before the WriteOneDataRequest
+const DATA_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// chunk retry times without progress before giving up the transfer
+const DATA_CHUNK_MAX_RETRY: usize = 5;
+/// dir under file_dir holding the chunks received but not yet applied
+const DATA_CHUNK_DIR: &str = "data_chunks";
+/// dir under file_dir holding the zips of the dirs being sent
+const DATA_SEND_DIR: &str = "data_send";
+/// total bytes of the InMemLru items kept in memory
+const DATA_MEM_CACHE_LRU_BYTES: u64 = 256 * 1024 * 1024;
+
 #[derive(LogicalModule)]
 pub struct DataGeneral {
     view: DataGeneralView,
@@ -43,6 +70,19 @@ pub struct DataGeneral {
 
     rpc_call_write_once_data: RPCCaller<WriteOneDataRequest>,
     rpc_handler_write_once_data: RPCHandler<WriteOneDataRequest>,
+
+    rpc_call_write_data_chunk: RPCCaller<WriteDataChunkRequest>,
+    rpc_handler_write_data_chunk: RPCHandler<WriteDataChunkRequest>,
+
+    rpc_call_data_holders: RPCCaller<DataHoldersRequest>,
+    rpc_call_pull_data: RPCCaller<PullDataRequest>,
+    rpc_handler_pull_data: RPCHandler<PullDataRequest>,
+
+    /// serialize the read modify write of DataSetMeta
+    data_set_meta_lock: Mutex<()>,
+    /// unique id 2 lock, avoid pulling the same data concurrently
+    pulling: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    mem_cache: DataMemCache,
 }
 
 #[async_trait]
@@ -56,10 +96,20 @@ impl LogicalModule for DataGeneral {
             rpc_call_data_version: RPCCaller::new(),
             rpc_call_write_once_data: RPCCaller::new(),
             rpc_handler_write_once_data: RPCHandler::new(),
+            rpc_call_write_data_chunk: RPCCaller::new(),
+            rpc_handler_write_data_chunk: RPCHandler::new(),
+            rpc_call_data_holders: RPCCaller::new(),
+            rpc_call_pull_data: RPCCaller::new(),
+            rpc_handler_pull_data: RPCHandler::new(),
+            data_set_meta_lock: Mutex::new(()),
+            pulling: Mutex::new(HashMap::new()),
+            mem_cache: DataMemCache::new(DATA_MEM_CACHE_LRU_BYTES),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
         tracing::info!("start as master");
+        // left by the sendings interrupted last time
+        let _ = fs::remove_dir_all(self.view.os().file_path.join(DATA_SEND_DIR));
         let p2p = self.view.p2p();
         self.rpc_call_data_version.regist(p2p);
         self.rpc_call_write_once_data.regist(p2p);
@@ -72,14 +122,97 @@ impl LogicalModule for DataGeneral {
                 });
                 Ok(())
             });
+        self.rpc_call_write_data_chunk.regist(p2p);
+        let view = self.view.clone();
+        self.rpc_handler_write_data_chunk
+            .regist(p2p, move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    view.data_general().write_data_chunk(responsor, req).await;
+                });
+                Ok(())
+            });
+        self.rpc_call_data_holders.regist(p2p);
+        self.rpc_call_pull_data.regist(p2p);
+        let view = self.view.clone();
+        self.rpc_handler_pull_data
+            .regist(p2p, move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    view.data_general()
+                        .rpc_handle_pull_data(responsor, req)
+                        .await;
+                });
+                Ok(())
+            });
         Ok(vec![])
     }
 }
 
-// pub enum DataWrapper {
-//     Bytes(Vec<u8>),
-//     File(PathBuf),
-// }
+/// a data item held by this node to be written to the others
+pub enum LocalDataItem {
+    RawBytes(Vec<u8>),
+    /// the content is read from the disk when sent, never held in memory as a whole
+    File {
+        file_name: String,
+        is_dir: bool,
+        content: FileContent,
+    },
+}
+
+/// the file to send as the content of a data item, the zip of a dir is removed on drop
+pub struct FileContent {
+    path: PathBuf,
+    len: u64,
+    checksum: u32,
+    tmp: bool,
+}
+
+impl FileContent {
+    fn open(path: PathBuf, tmp: bool) -> WSResult<Self> {
+        let mut content = Self {
+            path,
+            len: 0,
+            checksum: 0,
+            tmp,
+        };
+        let mut file = File::open(&content.path).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = file
+                .read(&mut buf)
+                .map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            content.len += n as u64;
+        }
+        content.checksum = hasher.finalize();
+        Ok(content)
+    }
+
+    async fn read_at(&self, offset: u64, len: usize) -> WSResult<Vec<u8>> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut data = vec![0; len];
+            File::open(path)?.read_exact_at(&mut data, offset)?;
+            Ok::<_, std::io::Error>(data)
+        })
+        .await
+        .map_err(|e| WSError::from(WsIoErr::Io(std::io::Error::other(e))))?
+        .map_err(|e| WSError::from(WsIoErr::Io(e)))
+    }
+}
+
+impl Drop for FileContent {
+    fn drop(&mut self) {
+        if self.tmp {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
 
 impl DataGeneral {
     async fn write_one_data(
@@ -94,49 +227,42 @@ impl DataGeneral {
             .kv_store_engine()
             .get(KeyTypeDataSetMeta(req.unique_id.as_bytes()))
         else {
-            responsor.send_resp(WriteOneDataResponse {
-                remote_version: 0,
-                success: false,
-                message: "Data meta not found".to_owned(),
-            });
+            self.write_one_data_failed(&responsor, 0, "Data meta not found".to_owned())
+                .await;
             return;
         };
         if res.version != req.version {
-            responsor.send_resp(WriteOneDataResponse {
-                remote_version: res.version,
-                success: false,
-                message: "Data meta version not match".to_owned(),
-            });
+            self.write_one_data_failed(
+                &responsor,
+                res.version,
+                "Data meta version not match".to_owned(),
+            )
+            .await;
             return;
         }
         if req.data.is_empty() {
-            responsor.send_resp(WriteOneDataResponse {
-                remote_version: res.version,
-                success: false,
-                message: "Data is empty".to_owned(),
-            });
+            self.write_one_data_failed(&responsor, res.version, "Data is empty".to_owned())
+                .await;
             return;
         }
         if req.data[0].data.is_none() {
-            responsor.send_resp(WriteOneDataResponse {
-                remote_version: res.version,
-                success: false,
-                message: "Data enum is none".to_owned(),
-            });
+            self.write_one_data_failed(&responsor, res.version, "Data enum is none".to_owned())
+                .await;
             return;
         }
         for (_idx, data) in req.data.iter().enumerate() {
             match data.data.as_ref().unwrap() {
                 write_one_data_request::data_item::Data::File(f) => {
-                    if f.file_name.starts_with("/") {
-                        responsor.send_resp(WriteOneDataResponse {
-                            remote_version: res.version,
-                            success: false,
-                            message: format!(
-                                "File name {} starts with / is forbidden",
-                                f.file_name
-                            ),
-                        });
+                    let under_file_dir = Path::new(&f.file_name)
+                        .components()
+                        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+                    if !under_file_dir {
+                        self.write_one_data_failed(
+                            &responsor,
+                            res.version,
+                            format!("File name {} out of the file dir is forbidden", f.file_name),
+                        )
+                        .await;
                         return;
                     }
                 }
@@ -149,10 +275,34 @@ impl DataGeneral {
             match data.data.unwrap() {
                 write_one_data_request::data_item::Data::File(f) => {
                     tracing::debug!("writing data part{} file {}", idx, f.file_name);
+                    let file_item = DataFileItemMeta {
+                        file_name: f.file_name.clone(),
+                        is_dir: f.is_dir,
+                    };
                     let p: std::path::PathBuf = self.view.os().file_path.join(f.file_name);
                     let view = self.view.clone();
 
                     let p2 = p.clone();
+                    if f.chunked_len > 0 {
+                        let tmp = self.data_chunk_path(&req.unique_id, req.version, idx as u32);
+                        let unique_id = req.unique_id.clone();
+                        let res = tokio::task::spawn_blocking(move || {
+                            apply_chunked_file(view.os(), &unique_id, idx as u32, &tmp, p2, &f)
+                        })
+                        .await
+                        .unwrap();
+                        if let Err(e) = res {
+                            self.write_one_data_failed(
+                                &responsor,
+                                req.version,
+                                format!("Apply chunked file error: {:?}, path: {:?}", e, p),
+                            )
+                            .await;
+                            return;
+                        }
+                        self.set_file_item(&req.unique_id, idx as u8, &file_item);
+                        continue;
+                    }
                     let res = if f.is_dir {
                         tokio::task::spawn_blocking(move || {
                             view.os().unzip_data_2_path(p2, f.file_content);
@@ -165,13 +315,15 @@ impl DataGeneral {
                     };
                     let res = res.await;
                     if let Err(e) = res {
-                        responsor.send_resp(WriteOneDataResponse {
-                            remote_version: req.version,
-                            success: false,
-                            message: format!("Write file error: {:?}, path: {:?}", e, p),
-                        });
+                        self.write_one_data_failed(
+                            &responsor,
+                            req.version,
+                            format!("Write file error: {:?}, path: {:?}", e, p),
+                        )
+                        .await;
                         return;
                     }
+                    self.set_file_item(&req.unique_id, idx as u8, &file_item);
                 }
                 write_one_data_request::data_item::Data::RawBytes(bytes) => {
                     tracing::debug!("writing data part{} bytes", idx);
@@ -182,30 +334,748 @@ impl DataGeneral {
                         },
                         &bytes,
                     );
+                    self.mem_cache.put(
+                        &req.unique_id,
+                        idx as u8,
+                        req.version,
+                        res.data_metas.get(idx).map(|m| m.cache),
+                        bytes.into(),
+                    );
                 }
             }
         }
         self.view.kv_store_engine().flush();
+        let _ = fs::remove_dir_all(self.data_chunk_dir(&req.unique_id));
         tracing::debug!("data is written");
-        responsor
+        self.mark_data_synced(&req.unique_id, req.version).await;
+        if let Err(e) = responsor
             .send_resp(WriteOneDataResponse {
                 remote_version: req.version,
                 success: true,
                 message: "".to_owned(),
             })
-            .await;
+            .await
+        {
+            tracing::warn!("send write data resp failed: {:?}", e);
+        }
         // ## response
     }
-    pub async fn get_data_item(&self, unique_id: String, idx: u8) -> Option<DataItem> {
-        let Some(itembytes) = self.view.kv_store_engine().get(KeyTypeDataSetItem {
+    /// the writer gets the failure instead of waiting for the rpc timeout
+    async fn write_one_data_failed(
+        &self,
+        responsor: &RPCResponsor<WriteOneDataRequest>,
+        remote_version: u64,
+        message: String,
+    ) {
+        tracing::warn!("write data failed: {}", message);
+        if let Err(e) = responsor
+            .send_resp(WriteOneDataResponse {
+                remote_version,
+                success: false,
+                message,
+            })
+            .await
+        {
+            tracing::warn!("send write data resp failed: {:?}", e);
+        }
+    }
+    fn set_file_item(&self, unique_id: &str, idx: u8, file_item: &DataFileItemMeta) {
+        self.view.kv_store_engine().set(
+            KeyTypeDataSetFileItem {
+                uid: unique_id.as_bytes(),
+                idx,
+            },
+            file_item,
+        );
+    }
+
+    /// read modify write the local data set meta, return None if the meta not exist
+    pub fn update_data_set_meta<R>(
+        &self,
+        unique_id: &str,
+        f: impl FnOnce(&mut DataSetMeta) -> R,
+    ) -> Option<R> {
+        let _guard = self.data_set_meta_lock.lock();
+        let kv = self.view.kv_store_engine();
+        let mut meta = kv.get(KeyTypeDataSetMeta(unique_id.as_bytes()))?;
+        let res = f(&mut meta);
+        kv.set(KeyTypeDataSetMeta(unique_id.as_bytes()), &meta);
+        kv.flush();
+        Some(res)
+    }
+
+    pub fn data_synced_locally(&self, unique_id: &str) -> bool {
+        let this = self.view.p2p().nodes_config.this_node();
+        self.view
+            .kv_store_engine()
+            .get(KeyTypeDataSetMeta(unique_id.as_bytes()))
+            .map_or(false, |meta| meta.synced_nodes.contains(&this))
+    }
+
+    /// record this node as a holder, locally and on master
+    async fn mark_data_synced(&self, unique_id: &str, version: DataVersion) {
+        let p2p = self.view.p2p();
+        let this = p2p.nodes_config.this_node();
+        let _ = self.update_data_set_meta(unique_id, |meta| {
+            if meta.version == version {
+                let _ = meta.synced_nodes.insert(this);
+            }
+        });
+        if p2p.nodes_config.this.1.is_master() {
+            // master's meta is the global one, already updated
+            return;
+        }
+        match self
+            .rpc_call_data_version
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                DataVersionRequest {
+                    unique_id: unique_id.to_owned(),
+                    version,
+                    data_metas: vec![],
+                    phase: DataVersionPhase::Commit as i32,
+                    trace_ctx: Default::default(),
+                },
+                Some(Duration::from_secs(30)),
+            )
+            .await
+        {
+            Err(e) => tracing::warn!(
+                "report synced data({}) to master failed: {:?}",
+                unique_id,
+                e
+            ),
+            Ok(resp) if resp.version != version => tracing::debug!(
+                "data({}) synced version {}, but master is at {}",
+                unique_id,
+                version,
+                resp.version
+            ),
+            Ok(_) => {}
+        }
+    }
+
+    /// fetch the whole data set from one of the holders recorded on master
+    pub async fn pull_data(&self, unique_id: &str) -> WSResult<()> {
+        let lock = self
+            .pulling
+            .lock()
+            .entry(unique_id.to_owned())
+            .or_insert_with(|| Arc::new(tokio::sync::Mutex::new(())))
+            .clone();
+        let _guard = lock.lock().await;
+        if self.data_synced_locally(unique_id) {
+            // pulled by the one holding the lock before
+            return Ok(());
+        }
+
+        let p2p = self.view.p2p();
+        let holders = self
+            .rpc_call_data_holders
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                DataHoldersRequest {
+                    unique_id: unique_id.to_owned(),
+                },
+                Some(Duration::from_secs(10)),
+            )
+            .await?;
+        let this = p2p.nodes_config.this_node();
+        let mut nodes: Vec<NodeID> = holders.nodes.into_iter().filter(|n| *n != this).collect();
+        if holders.version == 0 || nodes.is_empty() {
+            return Err(WsDataError::DataHolderNotFound {
+                unique_id: unique_id.to_owned(),
+            }
+            .into());
+        }
+        nodes.shuffle(&mut rand::thread_rng());
+
+        // keep up with master's version so that the pushed data won't be rejected
+        let local_version = self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeDataSetMeta(unique_id.as_bytes()))
+            .map_or(0, |meta| meta.version);
+        if local_version != holders.version {
+            self.set_dataversion(DataVersionRequest {
+                unique_id: unique_id.to_owned(),
+                version: holders.version,
+                data_metas: holders.data_metas,
+                phase: DataVersionPhase::Commit as i32,
+                trace_ctx: Default::default(),
+            })
+            .await?;
+        }
+
+        let mut last_err = None;
+        for node in nodes {
+            tracing::debug!("pull data({}) from node {}", unique_id, node);
+            let res = self
+                .rpc_call_pull_data
+                .call(
+                    p2p,
+                    node,
+                    PullDataRequest {
+                        unique_id: unique_id.to_owned(),
+                        version: holders.version,
+                    },
+                    Some(Duration::from_secs(120)),
+                )
+                .await;
+            match res {
+                Ok(resp) if resp.success => return Ok(()),
+                Ok(resp) => {
+                    tracing::warn!(
+                        "pull data({}) from {} failed: {}",
+                        unique_id,
+                        node,
+                        resp.message
+                    );
+                    last_err = Some(
+                        WsDataError::PullDataFailed {
+                            unique_id: unique_id.to_owned(),
+                            node,
+                            message: resp.message,
+                        }
+                        .into(),
+                    );
+                }
+                Err(e) => {
+                    tracing::warn!("pull data({}) from {} failed: {:?}", unique_id, node, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    async fn rpc_handle_pull_data(
+        &self,
+        responsor: RPCResponsor<PullDataRequest>,
+        req: PullDataRequest,
+    ) {
+        let resp = match self
+            .push_local_data(responsor.node_id(), &req.unique_id, req.version)
+            .await
+        {
+            Ok(()) => PullDataResponse {
+                success: true,
+                message: "".to_owned(),
+            },
+            Err(e) => PullDataResponse {
+                success: false,
+                message: format!("{:?}", e),
+            },
+        };
+        if let Err(e) = responsor.send_resp(resp).await {
+            tracing::warn!("send pull data resp failed: {:?}", e);
+        }
+    }
+
+    async fn push_local_data(
+        &self,
+        node: NodeID,
+        unique_id: &str,
+        version: DataVersion,
+    ) -> WSResult<()> {
+        let not_held = || WsDataError::DataNotHeld {
+            unique_id: unique_id.to_owned(),
+            version,
+        };
+        let Some(meta) = self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeDataSetMeta(unique_id.as_bytes()))
+        else {
+            return Err(not_held().into());
+        };
+        let this = self.view.p2p().nodes_config.this_node();
+        if meta.version != version || !meta.synced_nodes.contains(&this) {
+            return Err(not_held().into());
+        }
+
+        let mut datas = Vec::with_capacity(meta.data_metas.len());
+        for idx in 0..meta.data_metas.len() {
+            let Some(item) = self.load_local_data_item(unique_id, idx as u8).await? else {
+                return Err(not_held().into());
+            };
+            datas.push(item);
+        }
+        let resp = self
+            .write_one_data_to_node(node, unique_id.to_owned(), version, &datas)
+            .await?;
+        if !resp.success {
+            return Err(WsDataError::PullDataFailed {
+                unique_id: unique_id.to_owned(),
+                node,
+                message: resp.message,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// before this node leaves, push the data nobody else holds to another worker.
+    /// broadcast data is held by every node, so it's skipped
+    pub async fn rehome_local_data(&self) -> WSResult<()> {
+        let p2p = self.view.p2p();
+        let this = p2p.nodes_config.this_node();
+        for (unique_id, meta) in self.view.kv_store_engine().scan_data_set_metas() {
+            let broadcast = meta.data_metas.first().map_or(true, |m| {
+                m.distribute == DataModeDistribute::BroadcastRough as i32
+            });
+            if broadcast || !meta.synced_nodes.contains(&this) {
+                continue;
+            }
+            let holders = self
+                .rpc_call_data_holders
+                .call(
+                    p2p,
+                    p2p.nodes_config.get_master_node(),
+                    DataHoldersRequest {
+                        unique_id: unique_id.clone(),
+                    },
+                    Some(Duration::from_secs(10)),
+                )
+                .await?;
+            if holders.version != meta.version {
+                // the local copy is stale, the current one is held by the others
+                continue;
+            }
+            if holders
+                .nodes
+                .iter()
+                .any(|n| *n != this && !p2p.nodes_config.is_draining(*n))
+            {
+                continue;
+            }
+
+            let mut targets = p2p
+                .nodes_config
+                .get_worker_nodes()
+                .into_iter()
+                .collect::<Vec<_>>();
+            targets.shuffle(&mut rand::thread_rng());
+            let mut rehomed = false;
+            for node in targets {
+                tracing::debug!("rehome data({}) to node {}", unique_id, node);
+                match self.push_local_data(node, &unique_id, meta.version).await {
+                    Ok(()) => {
+                        rehomed = true;
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::warn!("rehome data({}) to {} failed: {:?}", unique_id, node, e)
+                    }
+                }
+            }
+            if !rehomed {
+                return Err(WsDataError::DataHolderNotFound { unique_id }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// forget the node as a holder of any data, called on master when it leaves
+    pub fn drop_data_holder(&self, node: NodeID) {
+        for (unique_id, _) in self.view.kv_store_engine().scan_data_set_metas() {
+            let _ = self.update_data_set_meta(&unique_id, |meta| {
+                let _ = meta.synced_nodes.remove(&node);
+            });
+        }
+    }
+
+    /// raw bytes from kv, files read back from where they were written
+    async fn load_local_data_item(
+        &self,
+        unique_id: &str,
+        idx: u8,
+    ) -> WSResult<Option<LocalDataItem>> {
+        let kv = self.view.kv_store_engine();
+        if let Some(bytes) = kv.get(KeyTypeDataSetItem {
+            uid: unique_id.as_bytes(),
+            idx,
+        }) {
+            return Ok(Some(LocalDataItem::RawBytes(bytes)));
+        }
+        let Some(file_item) = kv.get(KeyTypeDataSetFileItem {
             uid: unique_id.as_bytes(),
-            idx: idx as u8,
+            idx,
         }) else {
-            return None;
+            return Ok(None);
+        };
+        self.local_file_item(file_item.file_name, file_item.is_dir)
+            .await
+            .map(Some)
+    }
+
+    /// the file or dir under the file dir as a data item, a dir is zipped to a tmp file
+    pub async fn local_file_item(
+        &self,
+        file_name: String,
+        is_dir: bool,
+    ) -> WSResult<LocalDataItem> {
+        let p = self.view.os().file_path.join(&file_name);
+        let view = self.view.clone();
+        let content = tokio::task::spawn_blocking(move || {
+            if !is_dir {
+                return FileContent::open(p, false);
+            }
+            let zip_path = view
+                .os()
+                .file_path
+                .join(DATA_SEND_DIR)
+                .join(Uuid::new_v4().to_string());
+            let res = view
+                .os()
+                .zip_dir_2_file(&p, zip::CompressionMethod::Deflated, &zip_path)
+                .and_then(|_| FileContent::open(zip_path.clone(), true));
+            if res.is_err() {
+                let _ = fs::remove_file(&zip_path);
+            }
+            res
+        })
+        .await
+        .unwrap()?;
+        Ok(LocalDataItem::File {
+            file_name,
+            is_dir,
+            content,
+        })
+    }
+
+    /// named by the hash, so that no unique id could lead out of the chunk dir
+    fn data_chunk_dir(&self, unique_id: &str) -> PathBuf {
+        self.view
+            .os()
+            .file_path
+            .join(DATA_CHUNK_DIR)
+            .join(format!("{:x}", Sha256::digest(unique_id.as_bytes())))
+    }
+
+    fn data_chunk_path(&self, unique_id: &str, version: DataVersion, idx: u32) -> PathBuf {
+        self.data_chunk_dir(unique_id)
+            .join(format!("{}_{}", version, idx))
+    }
+
+    async fn write_data_chunk(
+        &self,
+        responsor: RPCResponsor<WriteDataChunkRequest>,
+        req: WriteDataChunkRequest,
+    ) {
+        let fail = |received: u64, message: String| WriteDataChunkResponse {
+            success: false,
+            received,
+            message,
+        };
+        let Some(meta) = self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeDataSetMeta(req.unique_id.as_bytes()))
+        else {
+            let _ = responsor
+                .send_resp(fail(0, "Data meta not found".to_owned()))
+                .await;
+            return;
         };
-        Some(DataItem {
-            data: Some(Data::RawBytes(itembytes)),
+        if meta.version != req.version {
+            let _ = responsor
+                .send_resp(fail(0, "Data meta version not match".to_owned()))
+                .await;
+            return;
+        }
+
+        let p = self.data_chunk_path(&req.unique_id, req.version, req.idx);
+        let chunk_ok = crc32fast::hash(&req.data) == req.checksum;
+        let (offset, total_len) = (req.offset, req.total_len);
+        let res = tokio::task::spawn_blocking(move || {
+            append_data_chunk(
+                &p,
+                offset,
+                total_len,
+                if chunk_ok { Some(&req.data[..]) } else { None },
+            )
         })
+        .await
+        .unwrap();
+        let resp = match res {
+            Ok(received) if !chunk_ok => fail(received, "Chunk checksum not match".to_owned()),
+            Ok(received) => WriteDataChunkResponse {
+                success: received > offset,
+                received,
+                message: "".to_owned(),
+            },
+            Err(e) => fail(offset, format!("Write chunk error: {:?}", e)),
+        };
+        if let Err(e) = responsor.send_resp(resp).await {
+            tracing::warn!("send write data chunk resp failed: {:?}", e);
+        }
+    }
+
+    /// stream one big data item to the node, resume from what the node already has
+    async fn send_data_chunks(
+        &self,
+        node: NodeID,
+        unique_id: &str,
+        version: DataVersion,
+        idx: u32,
+        content: &FileContent,
+    ) -> WSResult<()> {
+        let total_len = content.len;
+        let mut offset = 0;
+        let mut retry = 0;
+        while offset < total_len {
+            let len = (total_len - offset).min(DATA_CHUNK_SIZE as u64) as usize;
+            let data = content.read_at(offset, len).await?;
+            let res = self
+                .rpc_call_write_data_chunk
+                .call(
+                    self.view.p2p(),
+                    node,
+                    WriteDataChunkRequest {
+                        unique_id: unique_id.to_owned(),
+                        version,
+                        idx,
+                        offset,
+                        total_len,
+                        checksum: crc32fast::hash(&data),
+                        data,
+                    },
+                    Some(Duration::from_secs(30)),
+                )
+                .await;
+            let failed_msg = match res {
+                Ok(resp) if resp.received > total_len => {
+                    return Err(WsDataError::WriteDataChunkFailed {
+                        unique_id: unique_id.to_owned(),
+                        idx,
+                        node,
+                        message: format!("remote received {} > {}", resp.received, total_len),
+                    }
+                    .into());
+                }
+                Ok(resp) => {
+                    let progressed = resp.received > offset;
+                    offset = resp.received;
+                    if progressed {
+                        retry = 0;
+                        continue;
+                    }
+                    resp.message
+                }
+                Err(e) => format!("{:?}", e),
+            };
+            retry += 1;
+            tracing::warn!(
+                "write data chunk {}:{} at {} to node {} failed {} times: {}",
+                unique_id,
+                idx,
+                offset,
+                node,
+                retry,
+                failed_msg
+            );
+            if retry >= DATA_CHUNK_MAX_RETRY {
+                return Err(WsDataError::WriteDataChunkFailed {
+                    unique_id: unique_id.to_owned(),
+                    idx,
+                    node,
+                    message: failed_msg,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// big files are streamed ahead by chunks, then the request only carries their checksum
+    async fn write_one_data_to_node(
+        &self,
+        node: NodeID,
+        unique_id: String,
+        version: DataVersion,
+        datas: &[LocalDataItem],
+    ) -> WSResult<WriteOneDataResponse> {
+        let mut items = Vec::with_capacity(datas.len());
+        for (idx, item) in datas.iter().enumerate() {
+            let data = match item {
+                LocalDataItem::RawBytes(bytes) => Data::RawBytes(bytes.clone()),
+                LocalDataItem::File {
+                    file_name,
+                    is_dir,
+                    content,
+                } if content.len > DATA_CHUNK_SIZE as u64 => {
+                    self.send_data_chunks(node, &unique_id, version, idx as u32, content)
+                        .await?;
+                    Data::File(FileData {
+                        file_name: file_name.clone(),
+                        is_dir: *is_dir,
+                        file_content: vec![],
+                        chunked_len: content.len,
+                        checksum: content.checksum,
+                    })
+                }
+                LocalDataItem::File {
+                    file_name,
+                    is_dir,
+                    content,
+                } => Data::File(FileData {
+                    file_name: file_name.clone(),
+                    is_dir: *is_dir,
+                    file_content: content.read_at(0, content.len as usize).await?,
+                    chunked_len: 0,
+                    checksum: 0,
+                }),
+            };
+            items.push(DataItem { data: Some(data) });
+        }
+        self.rpc_call_write_once_data
+            .call(
+                self.view.p2p(),
+                node,
+                WriteOneDataRequest {
+                    unique_id,
+                    version,
+                    data: items,
+                },
+                Some(Duration::from_secs(60)),
+            )
+            .await
+    }
+
+    /// the raw bytes item with its version, shared with the memory tier without copying,
+    /// data announced to this node but not written here is pulled from the holders
+    pub async fn get_data_item(
+        &self,
+        unique_id: &str,
+        idx: u8,
+    ) -> Option<(DataVersion, Arc<[u8]>)> {
+        if let Some(cached) = self.mem_cache.get(unique_id, idx) {
+            return Some(cached);
+        }
+        let get_local = || {
+            let itembytes = self.view.kv_store_engine().get(KeyTypeDataSetItem {
+                uid: unique_id.as_bytes(),
+                idx,
+            })?;
+            self.cache_loaded_item(unique_id, idx, itembytes)
+        };
+        if let Some(item) = get_local() {
+            return Some(item);
+        }
+        // not announced, or deleted
+        if self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeDataSetMeta(unique_id.as_bytes()))
+            .map_or(true, |meta| meta.data_metas.len() <= idx as usize)
+        {
+            return None;
+        }
+        if let Err(e) = self.pull_data(unique_id).await {
+            tracing::warn!("pull data({}) failed: {:?}", unique_id, e);
+            return None;
+        }
+        get_local()
+    }
+
+    fn cache_loaded_item(
+        &self,
+        unique_id: &str,
+        idx: u8,
+        itembytes: Vec<u8>,
+    ) -> Option<(DataVersion, Arc<[u8]>)> {
+        let meta = self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeDataSetMeta(unique_id.as_bytes()))?;
+        let itembytes: Arc<[u8]> = itembytes.into();
+        self.mem_cache.put(
+            unique_id,
+            idx,
+            meta.version,
+            meta.data_metas.get(idx as usize).map(|m| m.cache),
+            itembytes.clone(),
+        );
+        Some((meta.version, itembytes))
+    }
+
+    /// drop the memory tier items of the other versions, all of them if `keep_version` is None
+    pub fn invalidate_mem_cache(
+        &self,
+        unique_id: &str,
+        item_cnt: usize,
+        keep_version: Option<DataVersion>,
+    ) {
+        self.mem_cache.invalidate(unique_id, item_cnt, keep_version);
+    }
+
+    pub fn drop_data_items(&self, unique_id: &str, item_cnt: usize) {
+        self.invalidate_mem_cache(unique_id, item_cnt, None);
+        let kv = self.view.kv_store_engine();
+        for idx in 0..item_cnt {
+            let (uid, idx) = (unique_id.as_bytes(), idx as u8);
+            kv.del(KeyTypeDataSetItem { uid, idx });
+            kv.del(KeyTypeDataSetFileItem { uid, idx });
+        }
+    }
+
+    /// the set is deleted, also remove the files written for it on this node
+    pub fn drop_deleted_data(&self, unique_id: &str, item_cnt: usize) {
+        let kv = self.view.kv_store_engine();
+        for idx in 0..item_cnt {
+            let Some(file_item) = kv.get(KeyTypeDataSetFileItem {
+                uid: unique_id.as_bytes(),
+                idx: idx as u8,
+            }) else {
+                continue;
+            };
+            let p = self.view.os().file_path.join(&file_item.file_name);
+            let res = if file_item.is_dir {
+                fs::remove_dir_all(&p)
+            } else {
+                fs::remove_file(&p)
+            };
+            if let Err(e) = res {
+                tracing::warn!(
+                    "remove file {:?} of deleted data({}) failed: {:?}",
+                    p,
+                    unique_id,
+                    e
+                );
+            }
+        }
+        self.drop_data_items(unique_id, item_cnt);
+    }
+
+    /// a version without items deletes the set on all the nodes
+    pub async fn delete_data(&self, unique_id: String) -> WSResult<()> {
+        let p2p = self.view.p2p();
+        let resp = self
+            .rpc_call_data_version
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                DataVersionRequest {
+                    unique_id: unique_id.clone(),
+                    version: 0,
+                    data_metas: vec![],
+                    phase: DataVersionPhase::Commit as i32,
+                    trace_ctx: Default::default(),
+                },
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
+        if !resp.error.is_empty() {
+            return Err(WsDataError::DeleteDataFailed {
+                unique_id,
+                message: resp.error,
+            }
+            .into());
+        }
+        Ok(())
     }
 
     pub async fn set_dataversion(&self, req: DataVersionRequest) -> WSResult<()> {
@@ -214,7 +1084,7 @@ impl DataGeneral {
             .view
             .kv_store_engine()
             .get(KeyTypeDataSetMeta(req.unique_id.as_bytes()));
-        if let Some(old) = old {
+        if let Some(old) = &old {
             if old.version > req.version {
                 return Err(WsDataError::SetExpiredDataVersion {
                     target_version: req.version,
@@ -231,6 +1101,25 @@ impl DataGeneral {
                 // return Ok(());
             }
         }
+        let old_item_cnt = old.as_ref().map_or(0, |old| old.data_metas.len());
+        let on_demand = req.data_metas.first().map_or(false, |meta| {
+            meta.distribute != DataModeDistribute::BroadcastRough as i32
+        });
+        if let Some(old) = old.filter(|old| old.version != req.version) {
+            if req.data_metas.is_empty() {
+                self.drop_deleted_data(&req.unique_id, old.data_metas.len());
+            } else if on_demand {
+                // this node may not be chosen to hold the new version,
+                // drop the old one so that it will be pulled when needed
+                self.drop_data_items(&req.unique_id, old.data_metas.len());
+            }
+        }
+        self.invalidate_mem_cache(
+            &req.unique_id,
+            old_item_cnt.max(req.data_metas.len()),
+            Some(req.version),
+        );
+        let _guard = self.data_set_meta_lock.lock();
         self.view.kv_store_engine().set(
             KeyTypeDataSetMeta(req.unique_id.as_bytes()),
             &DataSetMeta {
@@ -247,7 +1136,7 @@ impl DataGeneral {
         &self,
         unique_id: String,
         data_metas: Vec<DataMeta>,
-        datas: Vec<DataItem>,
+        datas: Vec<LocalDataItem>,
     ) {
         if data_metas.len() == 0 {
             tracing::warn!("write_data must have >0 data metas");
@@ -257,16 +1146,46 @@ impl DataGeneral {
             tracing::warn!("write_data data metas and datas length not match");
             return;
         }
-        if DataModeDistribute::BroadcastRough as i32 == data_metas[0].distribute {
-            self.write_data_broadcast_rough(unique_id, data_metas, datas)
-                .await;
-        }
+        let p2p = self.view.p2p();
+        let this = p2p.nodes_config.this_node();
+        let targets = match DataModeDistribute::from_i32(data_metas[0].distribute) {
+            // the ones not alive pull by reconciling when they are back
+            Some(DataModeDistribute::BroadcastRough) => {
+                let mut nodes = p2p.nodes_config.alive_peer_ids();
+                nodes.push(this);
+                nodes
+            }
+            Some(DataModeDistribute::ReplicateN) => {
+                let mut others = p2p
+                    .nodes_config
+                    .alive_peer_ids()
+                    .into_iter()
+                    .filter(|n| !p2p.nodes_config.is_draining(*n))
+                    .collect::<Vec<_>>();
+                others.shuffle(&mut rand::thread_rng());
+                others.truncate((data_metas[0].replica_cnt.max(1) - 1) as usize);
+                others.push(this);
+                others
+            }
+            Some(DataModeDistribute::OnDemandPull) => vec![this],
+            None => {
+                tracing::warn!(
+                    "write_data with unknown distribute mode {}",
+                    data_metas[0].distribute
+                );
+                return;
+            }
+        };
+        self.write_data_to_nodes(unique_id, data_metas, datas, targets)
+            .await;
     }
-    async fn write_data_broadcast_rough(
+    /// the other nodes only get the new version, and pull the data when they need it
+    async fn write_data_to_nodes(
         &self,
         unique_id: String,
         data_metas: Vec<DataMeta>,
-        datas: Vec<DataItem>,
+        datas: Vec<LocalDataItem>,
+        targets: Vec<NodeID>,
     ) {
         let p2p = self.view.p2p();
         let resp = self
@@ -278,25 +1197,32 @@ impl DataGeneral {
                     unique_id: unique_id.clone(),
                     version: 0,
                     data_metas,
+                    phase: DataVersionPhase::Commit as i32,
+                    trace_ctx: Default::default(),
                 },
                 Some(Duration::from_secs(60)),
             )
             .await;
         let resp = match resp {
             Err(e) => {
-                tracing::warn!("write_data_broadcast_rough require version error: {:?}", e);
+                tracing::warn!("write_data require version error: {:?}", e);
                 return;
             }
             Ok(ok) => ok,
         };
+        if !resp.error.is_empty() {
+            tracing::warn!("write_data require version failed: {}", resp.error);
+            return;
+        }
 
-        tracing::debug!("start broadcast data with version");
+        tracing::debug!("start write data to nodes {:?} with version", targets);
         let version = resp.version;
         // use the got version to send to global paralell
         let mut tasks = vec![];
+        // shared by all the sending tasks, big files are not copied for each node
+        let datas = Arc::new(datas);
 
-        for (_idx, node) in p2p.nodes_config.all_nodes_iter().enumerate() {
-            let n = *node.0;
+        for n in targets {
             let view = self.view.clone();
             let datas = datas.clone();
             let unique_id = unique_id.clone();
@@ -304,17 +1230,7 @@ impl DataGeneral {
 
             let t = tokio::spawn(async move {
                 view.data_general()
-                    .rpc_call_write_once_data
-                    .call(
-                        view.p2p(),
-                        n,
-                        WriteOneDataRequest {
-                            unique_id,
-                            version,
-                            data: datas,
-                        },
-                        Some(Duration::from_secs(60)),
-                    )
+                    .write_one_data_to_node(n, unique_id, version, &datas)
                     .await
             });
 
@@ -324,14 +1240,11 @@ impl DataGeneral {
             let res = t.await.unwrap();
             match res {
                 Err(e) => {
-                    tracing::warn!("write_data_broadcast_rough broadcast error: {:?}", e);
+                    tracing::warn!("write_data to nodes error: {:?}", e);
                 }
                 Ok(ok) => {
                     if !ok.success {
-                        tracing::warn!(
-                            "write_data_broadcast_rough broadcast error: {:?}",
-                            ok.message
-                        );
+                        tracing::warn!("write_data to nodes error: {:?}", ok.message);
                     }
                 }
             }
@@ -339,16 +1252,108 @@ impl DataGeneral {
     }
 }
 
+/// append the chunk only when it starts right at the end of the tmp file,
+/// so that a retried or resumed transfer never writes twice.
+/// return the length of the tmp file after this
+fn append_data_chunk(
+    p: &Path,
+    offset: u64,
+    total_len: u64,
+    data: Option<&[u8]>,
+) -> std::io::Result<u64> {
+    if let Some(parent) = p.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(p)?;
+    let mut len = f.metadata()?.len();
+    if len > total_len {
+        // left by another transfer of the same version, start over
+        f.set_len(0)?;
+        len = 0;
+    }
+    let Some(data) = data else {
+        return Ok(len);
+    };
+    if offset != len || len + data.len() as u64 > total_len {
+        return Ok(len);
+    }
+    f.write_all(data)?;
+    Ok(len + data.len() as u64)
+}
+
+/// verify the received chunks and move them to the data path
+fn apply_chunked_file(
+    os: &OperatingSystem,
+    unique_id: &str,
+    idx: u32,
+    tmp: &Path,
+    dst: PathBuf,
+    f: &FileData,
+) -> WSResult<()> {
+    let invalid = |reason: String| WsDataError::ChunkedDataInvalid {
+        unique_id: unique_id.to_owned(),
+        idx,
+        reason,
+    };
+    let mut file = File::open(tmp).map_err(|e| invalid(format!("open chunks failed: {:?}", e)))?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut len = 0;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| invalid(format!("read chunks failed: {:?}", e)))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        len += n as u64;
+    }
+    if len != f.chunked_len || hasher.finalize() != f.checksum {
+        let _ = fs::remove_file(tmp);
+        return Err(invalid(format!(
+            "received {} bytes of {}, or checksum not match",
+            len, f.chunked_len
+        ))
+        .into());
+    }
+    drop(file);
+
+    if f.is_dir {
+        os.unzip_file_2_path(&dst, tmp)?;
+        let _ = fs::remove_file(tmp);
+    } else {
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+        }
+        fs::rename(tmp, &dst).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+    }
+    Ok(())
+}
+
+/// of the data version phases, on both master and followers
+pub async fn send_version_resp(
+    responsor: &RPCResponsor<DataVersionRequest>,
+    resp: DataVersionResponse,
+) {
+    if let Err(e) = responsor.send_resp(resp).await {
+        tracing::warn!("send data version resp failed: {:?}", e);
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct DataMetaSys {
     pub cache: i32,
     pub distribute: i32,
+    #[serde(default)]
+    pub replica_cnt: u32,
 }
 impl From<DataMeta> for DataMetaSys {
     fn from(d: DataMeta) -> Self {
         Self {
             cache: d.cache,
             distribute: d.distribute,
+            replica_cnt: d.replica_cnt,
         }
     }
 }
@@ -357,10 +1362,83 @@ impl Into<DataMeta> for DataMetaSys {
         DataMeta {
             cache: self.cache,
             distribute: self.distribute,
+            replica_cnt: self.replica_cnt,
         }
     }
 }
 
+type DataMemCacheKey = (String, u8);
+type DataMemCacheValue = (DataVersion, Arc<[u8]>);
+
+/// memory tier of the raw bytes items, keyed by unique id and item idx with the version.
+/// sled is still the persistent one, items here are replaced on write
+/// and dropped on version change, so they are always the current version.
+struct DataMemCache {
+    /// AlwaysInMem items
+    pinned: RwLock<HashMap<DataMemCacheKey, DataMemCacheValue>>,
+    /// InMemLru items, bounded by total bytes
+    lru: moka::sync::Cache<DataMemCacheKey, DataMemCacheValue>,
+}
+
+impl DataMemCache {
+    fn new(lru_bytes: u64) -> Self {
+        Self {
+            pinned: RwLock::new(HashMap::new()),
+            lru: moka::sync::Cache::builder()
+                .weigher(|_k, v: &DataMemCacheValue| v.1.len().try_into().unwrap_or(u32::MAX))
+                .max_capacity(lru_bytes)
+                .build(),
+        }
+    }
+
+    fn get(&self, unique_id: &str, idx: u8) -> Option<DataMemCacheValue> {
+        let key = (unique_id.to_owned(), idx);
+        if let Some(cached) = self.pinned.read().get(&key) {
+            return Some(cached.clone());
+        }
+        self.lru.get(&key)
+    }
+
+    fn put(
+        &self,
+        unique_id: &str,
+        idx: u8,
+        version: DataVersion,
+        cache_mode: Option<i32>,
+        bytes: Arc<[u8]>,
+    ) {
+        let key = (unique_id.to_owned(), idx);
+        match cache_mode.and_then(DataModeCache::from_i32) {
+            Some(DataModeCache::AlwaysInMem) => {
+                let _ = self.pinned.write().insert(key, (version, bytes));
+            }
+            Some(DataModeCache::InMemLru) => self.lru.insert(key, (version, bytes)),
+            Some(DataModeCache::AlwaysInFs) | None => {}
+        }
+    }
+
+    fn invalidate(&self, unique_id: &str, item_cnt: usize, keep_version: Option<DataVersion>) {
+        let stale = |v: &DataMemCacheValue| Some(v.0) != keep_version;
+        let mut pinned = self.pinned.write();
+        for idx in 0..item_cnt {
+            let key = (unique_id.to_owned(), idx as u8);
+            if pinned.get(&key).map_or(false, stale) {
+                let _ = pinned.remove(&key);
+            }
+            if self.lru.get(&key).map_or(false, |v| stale(&v)) {
+                self.lru.invalidate(&key);
+            }
+        }
+    }
+}
+
+/// where a file item of a data set is written on this node
+#[derive(Serialize, Deserialize)]
+pub struct DataFileItemMeta {
+    pub file_name: String,
+    pub is_dir: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct DataSetMeta {
     // unique_id: Vec<u8>,
@@ -403,3 +1481,62 @@ pub struct DataSetMeta {
 //         DataDescription::default()
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mem_cache_tiers() {
+        let cache = DataMemCache::new(1024);
+        let bytes = |n: usize| -> Arc<[u8]> { vec![0; n].into() };
+        let (pinned, lru, fs) = (
+            Some(DataModeCache::AlwaysInMem as i32),
+            Some(DataModeCache::InMemLru as i32),
+            Some(DataModeCache::AlwaysInFs as i32),
+        );
+        cache.put("a", 0, 1, pinned, bytes(10));
+        cache.put("a", 1, 1, lru, bytes(20));
+        cache.put("a", 2, 1, fs, bytes(30));
+        assert_eq!(cache.get("a", 0).map(|(v, b)| (v, b.len())), Some((1, 10)));
+        assert_eq!(cache.get("a", 1).map(|(v, b)| (v, b.len())), Some((1, 20)));
+        assert!(cache.get("a", 2).is_none());
+
+        // the same bytes are shared, not copied
+        let (_, b1) = cache.get("a", 0).unwrap();
+        let (_, b2) = cache.get("a", 0).unwrap();
+        assert!(Arc::ptr_eq(&b1, &b2));
+
+        // the kept version stays, the others are dropped from both tiers
+        cache.invalidate("a", 3, Some(1));
+        assert!(cache.get("a", 0).is_some() && cache.get("a", 1).is_some());
+        cache.invalidate("a", 3, Some(2));
+        assert!(cache.get("a", 0).is_none() && cache.get("a", 1).is_none());
+    }
+
+    #[test]
+    fn test_mem_cache_lru_bounded_by_bytes() {
+        let cache = DataMemCache::new(100);
+        let lru = Some(DataModeCache::InMemLru as i32);
+        for idx in 0..10 {
+            cache.put("b", idx, 1, lru, vec![0; 40].into());
+        }
+        cache.lru.run_pending_tasks();
+        assert!(cache.lru.weighted_size() <= 100);
+        let held = (0..10).filter(|idx| cache.get("b", *idx).is_some()).count();
+        assert!(held <= 2, "held {}", held);
+    }
+
+    #[test]
+    fn test_data_meta_sys_round_trip() {
+        let meta = DataMeta {
+            cache: DataModeCache::InMemLru as i32,
+            distribute: DataModeDistribute::ReplicateN as i32,
+            replica_cnt: 3,
+        };
+        // the way the kv store keeps it
+        let bytes = bincode::serialize(&DataMetaSys::from(meta.clone())).unwrap();
+        let back: DataMeta = bincode::deserialize::<DataMetaSys>(&bytes).unwrap().into();
+        assert_eq!(back, meta);
+    }
+}
@@ -0,0 +1,428 @@
+//! Function logs of this node, kept in a bounded ring.
+//!
+//! Guests write them with the `write_log` host function, java apps by their stdout and stderr.
+//! Master serves `/appmgmt/logs` by collecting the matched lines from all the nodes.
+
+use crate::{
+    config::FnLogsConfig,
+    general::network::{
+        http_handler::{HttpHandler, ReqId},
+        m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor},
+        proto::fn_log::{FnLogLine, FnLogsReq, FnLogsResp},
+    },
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
+    util::JoinHandleWrapper,
+};
+use async_trait::async_trait;
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use ws_derive::LogicalModule;
+
+logical_module_view_impl!(FnLogsView);
+logical_module_view_impl!(FnLogsView, p2p, P2PModule);
+logical_module_view_impl!(FnLogsView, fn_logs, FnLogs);
+logical_module_view_impl!(FnLogsView, http_handler, Box<dyn HttpHandler>);
+
+lazy_static::lazy_static! {
+    static ref VIEW: OnceLock<FnLogsView> = OnceLock::new();
+}
+
+/// lines returned when the query has no limit
+const DEFAULT_QUERY_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FnLogStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FnLogEntry {
+    pub time_ms: u64,
+    pub node: NodeID,
+    pub app: String,
+    /// empty for the process output not belonging to an invocation
+    pub func: String,
+    pub req_id: Option<ReqId>,
+    pub stream: FnLogStream,
+    pub line: String,
+}
+
+impl From<FnLogEntry> for FnLogLine {
+    fn from(e: FnLogEntry) -> Self {
+        FnLogLine {
+            time_ms: e.time_ms,
+            node: e.node,
+            app: e.app,
+            func: e.func,
+            has_req_id: e.req_id.is_some(),
+            req_id: e.req_id.unwrap_or(0) as u64,
+            stderr: e.stream == FnLogStream::Stderr,
+            line: e.line,
+        }
+    }
+}
+
+impl From<FnLogLine> for FnLogEntry {
+    fn from(l: FnLogLine) -> Self {
+        FnLogEntry {
+            time_ms: l.time_ms,
+            node: l.node,
+            app: l.app,
+            func: l.func,
+            req_id: l.has_req_id.then_some(l.req_id as ReqId),
+            stream: if l.stderr {
+                FnLogStream::Stderr
+            } else {
+                FnLogStream::Stdout
+            },
+            line: l.line,
+        }
+    }
+}
+
+/// absent fields match any, req_id is local to the node running the invocation
+#[derive(Debug, Default, Deserialize)]
+pub struct FnLogQuery {
+    pub app: Option<String>,
+    pub func: Option<String>,
+    pub node: Option<NodeID>,
+    pub req_id: Option<ReqId>,
+    pub limit: Option<usize>,
+}
+
+impl FnLogQuery {
+    fn to_req(&self) -> FnLogsReq {
+        FnLogsReq {
+            app: self.app.clone().unwrap_or_default(),
+            func: self.func.clone().unwrap_or_default(),
+            by_req_id: self.req_id.is_some(),
+            req_id: self.req_id.unwrap_or(0) as u64,
+            limit: self.limit.unwrap_or(DEFAULT_QUERY_LIMIT) as u32,
+        }
+    }
+}
+
+fn matches(req: &FnLogsReq, e: &FnLogEntry) -> bool {
+    (req.app.is_empty() || req.app == e.app)
+        && (req.func.is_empty() || req.func == e.func)
+        && (!req.by_req_id || e.req_id == Some(req.req_id as ReqId))
+}
+
+fn truncate_line(line: &str, max: usize) -> &str {
+    if line.len() <= max {
+        return line;
+    }
+    let mut end = max;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// shared with the tasks capturing process output
+#[derive(Clone)]
+struct FnLogRing {
+    lines: Arc<Mutex<VecDeque<FnLogEntry>>>,
+    config: FnLogsConfig,
+    node: NodeID,
+}
+
+impl FnLogRing {
+    fn push(&self, app: &str, func: &str, req_id: Option<ReqId>, stream: FnLogStream, text: &str) {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as u64;
+        let mut lines = self.lines.lock();
+        for line in text.lines() {
+            if lines.len() >= self.config.max_lines {
+                let _ = lines.pop_front();
+            }
+            lines.push_back(FnLogEntry {
+                time_ms,
+                node: self.node,
+                app: app.to_owned(),
+                func: func.to_owned(),
+                req_id,
+                stream,
+                line: truncate_line(line, self.config.max_line_bytes).to_owned(),
+            });
+        }
+    }
+    /// the newest matched lines, oldest first
+    fn tail(&self, req: &FnLogsReq) -> Vec<FnLogEntry> {
+        let lines = self.lines.lock();
+        let mut res: Vec<FnLogEntry> = lines
+            .iter()
+            .rev()
+            .filter(|e| matches(req, e))
+            .take(req.limit as usize)
+            .cloned()
+            .collect();
+        res.reverse();
+        res
+    }
+}
+
+/// the newest `limit` of the lines from all the nodes, oldest first
+fn merge_tail(mut lines: Vec<FnLogEntry>, limit: usize) -> Vec<FnLogEntry> {
+    lines.sort_by_key(|e| e.time_ms);
+    let skip = lines.len().saturating_sub(limit);
+    let _ = lines.drain(..skip);
+    lines
+}
+
+#[derive(LogicalModule)]
+pub struct FnLogs {
+    view: FnLogsView,
+    ring: FnLogRing,
+    rpc_caller: RPCCaller<FnLogsReq>,
+    rpc_handler: RPCHandler<FnLogsReq>,
+}
+
+#[async_trait]
+impl LogicalModule for FnLogs {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: FnLogsView::new(args.logical_modules_ref.clone()),
+            ring: FnLogRing {
+                lines: Arc::new(Mutex::new(VecDeque::new())),
+                config: args.nodes_config.fn_logs.clone(),
+                node: args.nodes_config.this_node(),
+            },
+            rpc_caller: RPCCaller::new(),
+            rpc_handler: RPCHandler::new(),
+        }
+    }
+    async fn init(&self) -> WSResult<()> {
+        if !self.view.p2p().nodes_config.this.1.is_master() {
+            return Ok(());
+        }
+        let _ = VIEW.get_or_init(|| self.view.clone());
+        let mut router = self.view.http_handler().building_router();
+        let take = router.option_mut().take().unwrap();
+        let take = take.route("/appmgmt/logs", get(http_logs));
+        let _ = router.option_mut().replace(take);
+        Ok(())
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let view = self.view.clone();
+        self.rpc_handler
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    view.fn_logs().rpc_handle_logs(responsor, req).await;
+                });
+                Ok(())
+            });
+        self.rpc_caller.regist(self.view.p2p());
+        Ok(vec![])
+    }
+}
+
+async fn http_logs(Query(query): Query<FnLogQuery>) -> Response {
+    Json(VIEW.get().unwrap().fn_logs().collect(&query).await).into_response()
+}
+
+impl FnLogs {
+    /// multi-line text is split, each line tagged the same
+    pub fn push(
+        &self,
+        app: &str,
+        func: &str,
+        req_id: Option<ReqId>,
+        stream: FnLogStream,
+        text: &str,
+    ) {
+        self.ring.push(app, func, req_id, stream, text);
+    }
+    /// keep the lines of a long running process until it closes the output
+    pub fn capture_process_output(
+        &self,
+        app: &str,
+        stream: FnLogStream,
+        output: impl AsyncRead + Send + Unpin + 'static,
+    ) {
+        let ring = self.ring.clone();
+        let app = app.to_owned();
+        let _ = tokio::spawn(async move {
+            let mut lines = BufReader::new(output).lines();
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) => ring.push(&app, "", None, stream, &line),
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::warn!("read output of app {} failed: {}", app, e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+    fn local_logs(&self, req: &FnLogsReq) -> Vec<FnLogEntry> {
+        self.ring.tail(req)
+    }
+    async fn rpc_handle_logs(&self, responsor: RPCResponsor<FnLogsReq>, req: FnLogsReq) {
+        let lines = self
+            .local_logs(&req)
+            .into_iter()
+            .map(FnLogLine::from)
+            .collect();
+        if let Err(e) = responsor.send_resp(FnLogsResp { lines }).await {
+            tracing::warn!("send fn logs resp failed: {:?}", e);
+        }
+    }
+    /// lines from the unreachable nodes are left out
+    pub async fn collect(&self, query: &FnLogQuery) -> Vec<FnLogEntry> {
+        let req = query.to_req();
+        let nodes_config = &self.view.p2p().nodes_config;
+        let this = nodes_config.this_node();
+        let nodes = match query.node {
+            Some(node) => vec![node],
+            None => {
+                let mut nodes = nodes_config.peer_ids();
+                nodes.push(this);
+                nodes
+            }
+        };
+        let remote = nodes.iter().filter(|n| **n != this).map(|node| {
+            let req = req.clone();
+            async move {
+                match self
+                    .rpc_caller
+                    .call(self.view.p2p(), *node, req, Some(Duration::from_secs(5)))
+                    .await
+                {
+                    Ok(resp) => resp.lines.into_iter().map(FnLogEntry::from).collect(),
+                    Err(e) => {
+                        tracing::warn!("collect fn logs from node {} failed: {:?}", node, e);
+                        vec![]
+                    }
+                }
+            }
+        });
+        let mut res: Vec<FnLogEntry> = futures::future::join_all(remote)
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+        if nodes.contains(&this) {
+            res.extend(self.local_logs(&req));
+        }
+        merge_tail(res, req.limit as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ring(max_lines: usize, max_line_bytes: usize) -> FnLogRing {
+        FnLogRing {
+            lines: Arc::new(Mutex::new(VecDeque::new())),
+            config: FnLogsConfig {
+                max_lines,
+                max_line_bytes,
+            },
+            node: 1,
+        }
+    }
+
+    fn req(app: &str, func: &str, req_id: Option<ReqId>, limit: u32) -> FnLogsReq {
+        FnLogsReq {
+            app: app.to_owned(),
+            func: func.to_owned(),
+            by_req_id: req_id.is_some(),
+            req_id: req_id.unwrap_or(0) as u64,
+            limit,
+        }
+    }
+
+    fn texts(lines: &[FnLogEntry]) -> Vec<&str> {
+        lines.iter().map(|e| e.line.as_str()).collect()
+    }
+
+    #[test]
+    fn test_push_evicts_oldest() {
+        let ring = ring(3, 100);
+        ring.push("app", "f", Some(1), FnLogStream::Stdout, "l1\nl2");
+        ring.push("app", "f", Some(2), FnLogStream::Stderr, "l3\nl4\n");
+        let lines = ring.tail(&req("", "", None, 10));
+        assert_eq!(texts(&lines), vec!["l2", "l3", "l4"]);
+        assert_eq!(lines[0].req_id, Some(1));
+        assert_eq!(lines[2].stream, FnLogStream::Stderr);
+    }
+
+    #[test]
+    fn test_truncate_line() {
+        assert_eq!(truncate_line("abc", 3), "abc");
+        assert_eq!(truncate_line("abcd", 3), "abc");
+        // "é" is 2 bytes, not cut in the middle
+        assert_eq!(truncate_line("aé", 2), "a");
+        assert_eq!(truncate_line("日本", 4), "日");
+        assert_eq!(truncate_line("日本", 2), "");
+
+        let ring = ring(10, 4);
+        ring.push("app", "f", None, FnLogStream::Stdout, "ab日本");
+        assert_eq!(texts(&ring.tail(&req("", "", None, 10))), vec!["ab"]);
+    }
+
+    #[test]
+    fn test_filters() {
+        let ring = ring(10, 100);
+        ring.push("app1", "f1", Some(1), FnLogStream::Stdout, "a");
+        ring.push("app1", "f2", Some(2), FnLogStream::Stdout, "b");
+        ring.push("app2", "f1", Some(1), FnLogStream::Stdout, "c");
+        ring.push("app1", "", None, FnLogStream::Stdout, "d");
+
+        let tail = |app, func, req_id| texts(&ring.tail(&req(app, func, req_id, 10))).join("");
+        assert_eq!(tail("", "", None), "abcd");
+        assert_eq!(tail("app1", "", None), "abd");
+        assert_eq!(tail("", "f1", None), "ac");
+        assert_eq!(tail("app1", "f1", None), "a");
+        assert_eq!(tail("", "", Some(1)), "ac");
+        assert_eq!(tail("app1", "", Some(2)), "b");
+        assert_eq!(tail("app2", "f2", None), "");
+    }
+
+    #[test]
+    fn test_limit_tail() {
+        let ring = ring(10, 100);
+        ring.push("app", "f", None, FnLogStream::Stdout, "1\n2\n3\n4");
+        ring.push("other", "f", None, FnLogStream::Stdout, "5");
+        // the newest matched, oldest first
+        assert_eq!(texts(&ring.tail(&req("app", "", None, 2))), vec!["3", "4"]);
+        assert!(ring.tail(&req("app", "", None, 0)).is_empty());
+
+        let entry = |time_ms, line: &str| FnLogEntry {
+            time_ms,
+            node: 1,
+            app: "app".to_owned(),
+            func: "f".to_owned(),
+            req_id: None,
+            stream: FnLogStream::Stdout,
+            line: line.to_owned(),
+        };
+        let merged = merge_tail(vec![entry(3, "c"), entry(1, "a"), entry(2, "b")], 2);
+        assert_eq!(texts(&merged), vec!["b", "c"]);
+    }
+}
@@ -3,10 +3,15 @@
 //     pub view: KvStorageView,
 // }
 
-use super::{m_data_general::DataSetMeta, m_os::OperatingSystem, network::m_p2p::P2PModule};
+use super::{
+    m_data_general::{DataFileItemMeta, DataSetMeta},
+    m_membership::MemberRecord,
+    m_os::OperatingSystem,
+    network::m_p2p::P2PModule,
+};
 use crate::{
     logical_module_view_impl,
-    result::WSResult,
+    result::{WSResult, WsDataError},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
 };
@@ -18,6 +23,10 @@ use serde::{de::DeserializeOwned, ser::SerializeTuple};
 use std::sync::OnceLock;
 use ws_derive::LogicalModule;
 
+/// ids of the kv shared by all the apps before the per app namespaces,
+/// the owner apps of the keys are unknown, so they can't be migrated
+const FLAT_KV_IDS: [u8; 2] = [0, 1];
+
 logical_module_view_impl!(View);
 logical_module_view_impl!(View, os, OperatingSystem);
 logical_module_view_impl!(View, p2p, P2PModule);
@@ -55,10 +64,47 @@ impl LogicalModule for KvStoreEngine {
                 );
             db
         });
+        check_flat_kv(
+            self.db.get().unwrap(),
+            std::env::var_os("WS_KV_DROP_FLAT").is_some(),
+        )?;
         Ok(vec![])
     }
 }
 
+/// refuse to start with the flat kv keys left, unless they are allowed to be dropped
+fn check_flat_kv(db: &sled::Db, drop: bool) -> WSResult<()> {
+    let count: usize = FLAT_KV_IDS
+        .iter()
+        .map(|id| db.scan_prefix([*id]).count())
+        .sum();
+    if count == 0 {
+        return Ok(());
+    }
+    if !drop {
+        tracing::error!(
+            "{} kv keys left by a version without the per app namespaces, \
+            they can't be told to which app they belong, \
+            export them with the old version, or start with env WS_KV_DROP_FLAT to drop them",
+            count
+        );
+        return Err(WsDataError::FlatKvKeysLeft { count }.into());
+    }
+    for id in FLAT_KV_IDS {
+        let keys = db
+            .scan_prefix([id])
+            .keys()
+            .filter_map(|k| k.ok())
+            .collect::<Vec<_>>();
+        for k in keys {
+            let _ = db.remove(k).unwrap();
+        }
+    }
+    let _ = db.flush().unwrap();
+    tracing::warn!("dropped {} kv keys of the flat keyspace", count);
+    Ok(())
+}
+
 impl KvStoreEngine {
     pub fn set<K>(&self, key: K, value: &K::Value)
     where
@@ -92,11 +138,82 @@ impl KvStoreEngine {
         let key = key.make_key();
         let _ = self.db.get().unwrap().remove(key).unwrap();
     }
+    /// all the data set metas on this node, with the unique ids
+    pub fn scan_data_set_metas(&self) -> Vec<(String, DataSetMeta)> {
+        self.scan_key_type::<Vec<u8>, DataSetMeta>(KeyTypeDataSetMeta(&[]).id())
+            .into_iter()
+            .filter_map(|(uid, meta)| Some((String::from_utf8(uid).ok()?, meta)))
+            .collect()
+    }
+    /// all the cluster members recorded on this node
+    pub fn scan_cluster_members(&self) -> Vec<(NodeID, MemberRecord)> {
+        self.scan_key_type(KeyTypeClusterMember(0).id())
+    }
+    fn scan_key_type<K: DeserializeOwned, V: DeserializeOwned>(&self, id: u8) -> Vec<(K, V)> {
+        self.db
+            .get()
+            .unwrap()
+            .scan_prefix([id])
+            .filter_map(|kv| {
+                let (k, v) = kv
+                    .map_err(|e| tracing::error!("scan kv error: {:?}", e))
+                    .ok()?;
+                let key = bincode::deserialize::<K>(&k[1..]).ok()?;
+                let value = bincode::deserialize_from(v.as_ref()).ok()?;
+                Some((key, value))
+            })
+            .collect()
+    }
+    /// key count and total bytes of the keys and values in the kv namespace of the app
+    pub fn app_kv_usage(&self, app: &str) -> (usize, usize) {
+        let prefix = app_kv_prefix(KeyTypeKv { app, key: &[] }.id(), app);
+        self.db
+            .get()
+            .unwrap()
+            .scan_prefix(&prefix)
+            .filter_map(|kv| {
+                kv.map_err(|e| tracing::error!("scan kv error: {:?}", e))
+                    .ok()
+            })
+            .fold((0, 0), |(cnt, bytes), (k, v)| {
+                (cnt + 1, bytes + k.len() - prefix.len() + v.len())
+            })
+    }
+    /// remove the whole kv namespace of the app, return the removed key count
+    pub fn drop_app_kv(&self, app: &str) -> usize {
+        let _ = self.remove_prefix(&app_kv_prefix(
+            KeyTypeKvPosition { app, key: &[] }.id(),
+            app,
+        ));
+        self.remove_prefix(&app_kv_prefix(KeyTypeKv { app, key: &[] }.id(), app))
+    }
+    fn remove_prefix(&self, prefix: &[u8]) -> usize {
+        let db = self.db.get().unwrap();
+        let keys = db
+            .scan_prefix(prefix)
+            .keys()
+            .filter_map(|k| {
+                k.map_err(|e| tracing::error!("scan kv error: {:?}", e))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        for k in keys.iter() {
+            let _ = db.remove(k).unwrap();
+        }
+        keys.len()
+    }
     pub fn flush(&self) {
         let _ = self.db.get().unwrap().flush().unwrap();
     }
 }
 
+/// the app is serialized ahead of the key, so the namespace is a prefix
+fn app_kv_prefix(id: u8, app: &str) -> Vec<u8> {
+    let mut prefix = vec![id];
+    serialize_into(&mut prefix, app).unwrap();
+    prefix
+}
+
 pub trait KeyType: Serialize {
     type Value: Serialize + DeserializeOwned;
     fn id(&self) -> u8;
@@ -108,9 +225,16 @@ pub trait KeyType: Serialize {
     }
 }
 
-pub struct KeyTypeKv<'a>(pub &'a [u8]);
+/// kv of the functions, in the namespace of the app
+pub struct KeyTypeKv<'a> {
+    pub app: &'a str,
+    pub key: &'a [u8],
+}
 
-pub struct KeyTypeKvPosition<'a>(pub &'a [u8]);
+pub struct KeyTypeKvPosition<'a> {
+    pub app: &'a str,
+    pub key: &'a [u8],
+}
 
 pub struct KeyTypeServiceMeta<'a>(pub &'a [u8]);
 
@@ -123,16 +247,26 @@ pub struct KeyTypeDataSetItem<'a> {
     pub idx: u8,
 }
 
+/// where a file data item is placed on this node
+pub struct KeyTypeDataSetFileItem<'a> {
+    pub uid: &'a [u8],
+    pub idx: u8,
+}
+
+/// membership persisted on master
+pub struct KeyTypeClusterMember(pub NodeID);
+
+// 0 and 1 were the flat kv keyspace shared by all the apps
 impl KeyType for KeyTypeKvPosition<'_> {
     type Value = NodeID;
     fn id(&self) -> u8 {
-        0
+        9
     }
 }
 impl KeyType for KeyTypeKv<'_> {
     type Value = Vec<u8>;
     fn id(&self) -> u8 {
-        1
+        8
     }
 }
 impl KeyType for KeyTypeServiceMeta<'_> {
@@ -162,15 +296,35 @@ impl KeyType for KeyTypeDataSetItem<'_> {
     }
 }
 
+impl KeyType for KeyTypeDataSetFileItem<'_> {
+    type Value = DataFileItemMeta;
+    fn id(&self) -> u8 {
+        6
+    }
+}
+
+impl KeyType for KeyTypeClusterMember {
+    type Value = MemberRecord;
+    fn id(&self) -> u8 {
+        7
+    }
+}
+
 impl Serialize for KeyTypeKvPosition<'_> {
     fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        self.0.serialize(serializer)
+        let mut tup = serializer.serialize_tuple(2)?;
+        tup.serialize_element(self.app)?;
+        tup.serialize_element(self.key)?;
+        tup.end()
     }
 }
 
 impl Serialize for KeyTypeKv<'_> {
     fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        self.0.serialize(serializer)
+        let mut tup = serializer.serialize_tuple(2)?;
+        tup.serialize_element(self.app)?;
+        tup.serialize_element(self.key)?;
+        tup.end()
     }
 }
 
@@ -192,6 +346,12 @@ impl Serialize for KeyTypeDataSetMeta<'_> {
     }
 }
 
+impl Serialize for KeyTypeClusterMember {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
 impl Serialize for KeyTypeDataSetItem<'_> {
     fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         let mut tup = serializer.serialize_tuple(2)?;
@@ -200,3 +360,38 @@ impl Serialize for KeyTypeDataSetItem<'_> {
         tup.end()
     }
 }
+
+impl Serialize for KeyTypeDataSetFileItem<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tup = serializer.serialize_tuple(2)?;
+        tup.serialize_element(self.uid)?;
+        tup.serialize_element(&self.idx)?;
+        tup.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::result::WSError;
+
+    #[test]
+    fn test_check_flat_kv() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let _ = db.insert(KeyTypeServiceList.make_key(), vec![0u8]).unwrap();
+        check_flat_kv(&db, false).unwrap();
+
+        let _ = db
+            .insert([1, 3, 0, 0, 0, 0, 0, 0, 0, b'k'], vec![0u8])
+            .unwrap();
+        assert!(matches!(
+            check_flat_kv(&db, false),
+            Err(WSError::WsDataError(WsDataError::FlatKvKeysLeft {
+                count: 1
+            }))
+        ));
+        check_flat_kv(&db, true).unwrap();
+        check_flat_kv(&db, false).unwrap();
+        assert!(db.get(KeyTypeServiceList.make_key()).unwrap().is_some());
+    }
+}
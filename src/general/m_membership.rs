@@ -0,0 +1,689 @@
+//! Cluster membership beyond the static node_config.yaml.
+//!
+//! A new node only needs itself and master in its config, master admits it on the
+//! first connection and records it in the kv store. The others learn the members
+//! from master. Decommission drains the node and re-homes its data before removing it.
+
+use super::{
+    m_data_general::DataGeneral,
+    m_kv_store_engine::{KeyTypeClusterMember, KvStoreEngine},
+    network::{
+        http_handler::HttpHandler,
+        m_p2p::{MsgHandler, MsgSender, P2PModule, RPCCaller, RPCHandler, RPCResponsor},
+        proto::{
+            self,
+            cluster::{
+                ClusterMember, ClusterMembersRequest, ClusterMembersResponse, ClusterMembersUpdate,
+                DrainNodeRequest, DrainNodeResponse, LeaveClusterRequest, LeaveClusterResponse,
+            },
+        },
+    },
+};
+use crate::{
+    config::{NodeConfig, NodeHealth, NodesConfig},
+    logical_module_view_impl,
+    result::{WSResult, WsClusterErr},
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
+    util::JoinHandleWrapper,
+    worker::func::m_instance_manager::InstanceManager,
+};
+use async_trait::async_trait;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use ws_derive::LogicalModule;
+
+/// how often the non-master nodes sync the members from master
+const MEMBERS_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+/// max time waiting for the running functions on the draining node
+const DRAIN_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+/// draining includes pushing the data, so it takes a while
+const DRAIN_NODE_RPC_TIMEOUT: Duration = Duration::from_secs(600);
+
+logical_module_view_impl!(MembershipView);
+logical_module_view_impl!(MembershipView, p2p, P2PModule);
+logical_module_view_impl!(MembershipView, membership, Membership);
+logical_module_view_impl!(MembershipView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(MembershipView, data_general, DataGeneral);
+logical_module_view_impl!(MembershipView, http_handler, Box<dyn HttpHandler>);
+logical_module_view_impl!(MembershipView, instance_manager, Option<InstanceManager>);
+
+lazy_static::lazy_static!(
+    static ref VIEW: OnceLock<MembershipView> = OnceLock::new();
+);
+
+fn view() -> &'static MembershipView {
+    VIEW.get().unwrap()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MemberRecord {
+    pub config: NodeConfig,
+    /// kept so that a node listed in the yaml stays removed after master restarts
+    pub decommissioned: bool,
+}
+
+#[derive(LogicalModule)]
+pub struct Membership {
+    view: MembershipView,
+    rpc_call_members: RPCCaller<ClusterMembersRequest>,
+    rpc_handler_members: RPCHandler<ClusterMembersRequest>,
+    msg_sender_update: MsgSender<ClusterMembersUpdate>,
+    msg_handler_update: MsgHandler<ClusterMembersUpdate>,
+    rpc_call_drain: RPCCaller<DrainNodeRequest>,
+    rpc_handler_drain: RPCHandler<DrainNodeRequest>,
+    rpc_call_leave: RPCCaller<LeaveClusterRequest>,
+    rpc_handler_leave: RPCHandler<LeaveClusterRequest>,
+    /// one decommission at a time
+    decommission_lock: tokio::sync::Mutex<()>,
+}
+
+#[async_trait]
+impl LogicalModule for Membership {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: MembershipView::new(args.logical_modules_ref.clone()),
+            rpc_call_members: RPCCaller::new(),
+            rpc_handler_members: RPCHandler::new(),
+            msg_sender_update: MsgSender::new(),
+            msg_handler_update: MsgHandler::new(),
+            rpc_call_drain: RPCCaller::new(),
+            rpc_handler_drain: RPCHandler::new(),
+            rpc_call_leave: RPCCaller::new(),
+            rpc_handler_leave: RPCHandler::new(),
+            decommission_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+    async fn init(&self) -> WSResult<()> {
+        let _ = VIEW.get_or_init(|| self.view.clone());
+        let mut router = self.view.http_handler().building_router();
+        let take = router.option_mut().take().unwrap();
+        let take = if self.view.p2p().nodes_config.this.1.is_master() {
+            take.route("/cluster/members", get(http_members))
+                .route("/cluster/decommission/:node_id", post(http_decommission))
+        } else {
+            take.route("/cluster/leave", post(http_leave))
+        };
+        let _ = router.option_mut().replace(take);
+        Ok(())
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let p2p = self.view.p2p();
+        self.rpc_call_members.regist(p2p);
+        let view = self.view.clone();
+        self.rpc_handler_members
+            .regist(p2p, move |responsor, _req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    let members = view.membership().members();
+                    if let Err(e) = responsor
+                        .send_resp(ClusterMembersResponse { members })
+                        .await
+                    {
+                        tracing::warn!("send members resp failed: {:?}", e);
+                    }
+                });
+                Ok(())
+            });
+        let view = self.view.clone();
+        self.msg_handler_update.regist(p2p, move |responser, msg| {
+            if responser.node_id != view.p2p().nodes_config.get_master_node() {
+                tracing::warn!("members update from non master {}", responser.node_id);
+                return Ok(());
+            }
+            let view = view.clone();
+            let _ = tokio::spawn(async move {
+                view.membership().apply_members(msg.members).await;
+            });
+            Ok(())
+        });
+        self.rpc_call_drain.regist(p2p);
+        let view = self.view.clone();
+        self.rpc_handler_drain.regist(p2p, move |responsor, _req| {
+            let view = view.clone();
+            let _ = tokio::spawn(async move {
+                view.membership().rpc_handle_drain(responsor).await;
+            });
+            Ok(())
+        });
+        self.rpc_call_leave.regist(p2p);
+        let view = self.view.clone();
+        self.rpc_handler_leave.regist(p2p, move |responsor, _req| {
+            let view = view.clone();
+            let _ = tokio::spawn(async move {
+                view.membership().rpc_handle_leave(responsor).await;
+            });
+            Ok(())
+        });
+
+        if p2p.nodes_config.this.1.is_master() {
+            self.load_members();
+            return Ok(vec![]);
+        }
+        let view = self.view.clone();
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            sync_members_task(view).await;
+        }))])
+    }
+}
+
+async fn sync_members_task(view: MembershipView) {
+    // wait for the connection to master
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    loop {
+        view.membership().sync_members().await;
+        tokio::time::sleep(MEMBERS_SYNC_INTERVAL).await;
+    }
+}
+
+/// master only, and neither the id nor the master role is taken
+fn can_admit(nodes_config: &NodesConfig, id: NodeID, config: &NodeConfig) -> bool {
+    if !nodes_config.this.1.is_master() {
+        return false;
+    }
+    if nodes_config.node_exist(id) || config.is_master() {
+        tracing::warn!(
+            "node {} joining from {} conflicts with the members",
+            id,
+            config.addr
+        );
+        return false;
+    }
+    true
+}
+
+/// the connections to change after the peers are updated
+#[derive(Debug, Default, PartialEq, Eq)]
+struct MembersChange {
+    connect: Vec<(NodeID, SocketAddr)>,
+    disconnect: Vec<SocketAddr>,
+}
+
+/// make the local peers the same as master's
+fn update_members(nodes_config: &NodesConfig, members: Vec<ClusterMember>) -> MembersChange {
+    let this = nodes_config.this_node();
+    if !members.iter().any(|m| m.node_id == this) {
+        tracing::warn!("this node is not a member any more, it's safe to stop it");
+    }
+
+    let mut change = MembersChange::default();
+    let mut ids = HashSet::new();
+    for m in members {
+        let _ = ids.insert(m.node_id);
+        nodes_config.set_draining(m.node_id, m.draining);
+        if m.node_id == this {
+            continue;
+        }
+        let health = match proto::cluster::NodeHealth::from_i32(m.health) {
+            Some(proto::cluster::NodeHealth::Suspect) => NodeHealth::Suspect,
+            Some(proto::cluster::NodeHealth::Dead) => NodeHealth::Dead,
+            _ => NodeHealth::Alive,
+        };
+        let _ = nodes_config.set_node_health(m.node_id, health);
+        let Ok(addr) = m.addr.parse::<SocketAddr>() else {
+            tracing::warn!("member {} with invalid addr {}", m.node_id, m.addr);
+            continue;
+        };
+        let domain = if m.domain.is_empty() {
+            None
+        } else {
+            Some(m.domain)
+        };
+        let config = NodeConfig::new(addr, domain, m.spec.into_iter().collect());
+        match nodes_config.add_peer(m.node_id, config) {
+            Some(old) if old.addr == addr => {}
+            old => {
+                tracing::info!("member {} at {}", m.node_id, addr);
+                if let Some(old) = old {
+                    change.disconnect.push(old.addr);
+                }
+                change.connect.push((m.node_id, addr));
+            }
+        }
+    }
+
+    let master = nodes_config.get_master_node();
+    for id in nodes_config.peer_ids() {
+        if ids.contains(&id) || id == master {
+            continue;
+        }
+        if let Some(old) = nodes_config.remove_peer(id) {
+            tracing::info!("member {} left", id);
+            change.disconnect.push(old.addr);
+        }
+    }
+    change
+}
+
+impl Membership {
+    /// members recorded on master, on top of the ones in the yaml
+    fn load_members(&self) {
+        let nodes_config = &self.view.p2p().nodes_config;
+        for (id, record) in self.view.kv_store_engine().scan_cluster_members() {
+            if id == nodes_config.this_node() {
+                continue;
+            }
+            if record.decommissioned {
+                if nodes_config.remove_peer(id).is_some() {
+                    tracing::info!("node {} was decommissioned", id);
+                }
+            } else {
+                self.add_member(id, record.config);
+            }
+        }
+    }
+    fn add_member(&self, id: NodeID, config: NodeConfig) {
+        let p2p = self.view.p2p();
+        let addr = config.addr;
+        if p2p.nodes_config.add_peer(id, config).map(|old| old.addr) != Some(addr) {
+            p2p.p2p_kernel.connect_peer(id, addr);
+        }
+    }
+
+    /// called on connection from an unknown addr, only master admits
+    pub fn admit_node(&self, id: NodeID, config: NodeConfig) -> bool {
+        if !can_admit(&self.view.p2p().nodes_config, id, &config) {
+            return false;
+        }
+        tracing::info!("node {} joins from {}", id, config.addr);
+        let kv = self.view.kv_store_engine();
+        kv.set(
+            KeyTypeClusterMember(id),
+            &MemberRecord {
+                config: config.clone(),
+                decommissioned: false,
+            },
+        );
+        kv.flush();
+        self.add_member(id, config);
+        let view = self.view.clone();
+        let _ = tokio::spawn(async move {
+            view.membership().broadcast_members().await;
+        });
+        true
+    }
+
+    pub fn members(&self) -> Vec<ClusterMember> {
+        let nodes_config = &self.view.p2p().nodes_config;
+        nodes_config
+            .peers()
+            .into_iter()
+            .chain(Some(nodes_config.this.clone()))
+            .map(|(id, config)| ClusterMember {
+                node_id: id,
+                addr: config.addr.to_string(),
+                domain: config.domain().unwrap_or("").to_owned(),
+                spec: config.spec.iter().cloned().collect(),
+                draining: nodes_config.is_draining(id),
+                health: nodes_config.node_health(id) as i32,
+            })
+            .collect()
+    }
+
+    pub async fn broadcast_members(&self) {
+        let p2p = self.view.p2p();
+        let members = self.members();
+        for node in p2p.nodes_config.peer_ids() {
+            self.send_members(node, members.clone()).await;
+        }
+    }
+    async fn send_members(&self, node: NodeID, members: Vec<ClusterMember>) {
+        if let Err(e) = self
+            .msg_sender_update
+            .send(self.view.p2p(), node, ClusterMembersUpdate { members })
+            .await
+        {
+            // it syncs from master periodically
+            tracing::warn!("send members to {} failed: {:?}", node, e);
+        }
+    }
+
+    async fn sync_members(&self) {
+        let p2p = self.view.p2p();
+        match self
+            .rpc_call_members
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                ClusterMembersRequest {},
+                None,
+            )
+            .await
+        {
+            Ok(resp) => self.apply_members(resp.members).await,
+            Err(e) => tracing::warn!("sync members from master failed: {:?}", e),
+        }
+    }
+
+    /// make the local peers the same as master's
+    async fn apply_members(&self, members: Vec<ClusterMember>) {
+        let p2p = self.view.p2p();
+        let change = update_members(&p2p.nodes_config, members);
+        for addr in change.disconnect {
+            p2p.p2p_kernel.disconnect_peer(addr).await;
+        }
+        for (id, addr) in change.connect {
+            p2p.p2p_kernel.connect_peer(id, addr);
+        }
+    }
+
+    /// drain the node, re-home its data, then remove it from the cluster
+    pub async fn decommission(&self, node: NodeID) -> WSResult<()> {
+        let _guard = self.decommission_lock.lock().await;
+        let p2p = self.view.p2p();
+        let nodes_config = &p2p.nodes_config;
+        if node == nodes_config.get_master_node() {
+            return Err(WsClusterErr::DecommissionMaster(node).into());
+        }
+        let Some(config) = nodes_config.get_peer(node) else {
+            return Err(WsClusterErr::NodeNotMember(node).into());
+        };
+        tracing::info!("decommission node {}", node);
+
+        // ## no more new work on it
+        nodes_config.set_draining(node, true);
+        self.broadcast_members().await;
+
+        // ## drain instances and re-home data
+        let err = match self
+            .rpc_call_drain
+            .call(p2p, node, DrainNodeRequest {}, Some(DRAIN_NODE_RPC_TIMEOUT))
+            .await
+        {
+            Ok(resp) if resp.success => None,
+            Ok(resp) => Some(resp.message),
+            Err(e) => Some(format!("{:?}", e)),
+        };
+        if let Some(message) = err {
+            tracing::warn!("drain node {} failed: {}", node, message);
+            nodes_config.set_draining(node, false);
+            self.broadcast_members().await;
+            return Err(WsClusterErr::DrainNodeFailed { node, message }.into());
+        }
+
+        // ## remove it
+        let kv = self.view.kv_store_engine();
+        kv.set(
+            KeyTypeClusterMember(node),
+            &MemberRecord {
+                config: config.clone(),
+                decommissioned: true,
+            },
+        );
+        kv.flush();
+        self.view.data_general().drop_data_holder(node);
+        // the leaving one learns it's removed too
+        let members = self
+            .members()
+            .into_iter()
+            .filter(|m| m.node_id != node)
+            .collect();
+        self.send_members(node, members).await;
+        let _ = nodes_config.remove_peer(node);
+        p2p.p2p_kernel.disconnect_peer(config.addr).await;
+        self.broadcast_members().await;
+        tracing::info!("node {} is decommissioned", node);
+        Ok(())
+    }
+
+    async fn rpc_handle_drain(&self, responsor: RPCResponsor<DrainNodeRequest>) {
+        let resp = match self.drain().await {
+            Ok(()) => DrainNodeResponse {
+                success: true,
+                message: "".to_owned(),
+            },
+            Err(e) => DrainNodeResponse {
+                success: false,
+                message: format!("{:?}", e),
+            },
+        };
+        if let Err(e) = responsor.send_resp(resp).await {
+            tracing::warn!("send drain resp failed: {:?}", e);
+        }
+    }
+    async fn drain(&self) -> WSResult<()> {
+        let nodes_config = &self.view.p2p().nodes_config;
+        nodes_config.set_draining(nodes_config.this_node(), true);
+        if nodes_config.this.1.is_worker() {
+            self.drain_instances().await;
+        }
+        self.view.data_general().rehome_local_data().await
+    }
+    /// wait for the running functions, then stop all the instances
+    async fn drain_instances(&self) {
+        let instance_manager = self.view.instance_manager();
+        let begin = Instant::now();
+        loop {
+            let running = instance_manager.instance_running_function.read().len();
+            if running == 0 {
+                break;
+            }
+            if begin.elapsed() > DRAIN_WAIT_TIMEOUT {
+                tracing::warn!("drain timeout, {} functions still running", running);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        let apps = instance_manager
+            .app_instances
+            .iter()
+            .map(|e| e.key().clone())
+            .collect::<Vec<_>>();
+        for app in apps {
+            if let Some(entry) = instance_manager.app_instances.get(&app) {
+                entry.value().kill().await;
+            }
+            instance_manager.drap_app_instances(&app).await;
+        }
+    }
+
+    async fn rpc_handle_leave(&self, responsor: RPCResponsor<LeaveClusterRequest>) {
+        let resp = if !self.view.p2p().nodes_config.this.1.is_master() {
+            LeaveClusterResponse {
+                success: false,
+                message: "not master".to_owned(),
+            }
+        } else {
+            match self.decommission(responsor.node_id()).await {
+                Ok(()) => LeaveClusterResponse {
+                    success: true,
+                    message: "".to_owned(),
+                },
+                Err(e) => LeaveClusterResponse {
+                    success: false,
+                    message: format!("{:?}", e),
+                },
+            }
+        };
+        if let Err(e) = responsor.send_resp(resp).await {
+            tracing::warn!("send leave resp failed: {:?}", e);
+        }
+    }
+    /// ask master to decommission this node
+    pub async fn leave(&self) -> WSResult<()> {
+        let p2p = self.view.p2p();
+        let resp = self
+            .rpc_call_leave
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                LeaveClusterRequest {},
+                Some(DRAIN_NODE_RPC_TIMEOUT),
+            )
+            .await?;
+        if !resp.success {
+            return Err(WsClusterErr::LeaveClusterFailed {
+                message: resp.message,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+async fn http_members() -> Response {
+    let members = view()
+        .membership()
+        .members()
+        .into_iter()
+        .map(|m| {
+            serde_json::json!({
+                "node_id": m.node_id,
+                "addr": m.addr,
+                "spec": m.spec,
+                "draining": m.draining,
+                "health": m.health,
+            })
+        })
+        .collect::<Vec<_>>();
+    (StatusCode::OK, serde_json::Value::from(members).to_string()).into_response()
+}
+
+async fn http_decommission(Path(node_id): Path<NodeID>) -> Response {
+    match view().membership().decommission(node_id).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("err: {:?}", e)).into_response(),
+    }
+}
+
+async fn http_leave() -> Response {
+    match view().membership().leave().await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("err: {:?}", e)).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::read_config;
+
+    const NODES_YAML: &str = r#"
+nodes:
+  1:
+    addr: 127.0.0.1:2500
+    spec: [meta, master]
+  2:
+    addr: 127.0.0.1:2505
+    spec: [meta, worker]
+  3:
+    addr: 127.0.0.1:2510
+    spec: [meta, worker]
+  4:
+    addr: 127.0.0.1:2515
+    spec: [meta, worker]
+cluster_secret: 0123456789abcdef0123456789abcdef
+"#;
+
+    fn nodes_config(this: NodeID, name: &str) -> NodesConfig {
+        let dir = std::env::temp_dir().join(format!(
+            "ws_membership_{}_{}_{}",
+            name,
+            this,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(dir.join("files")).unwrap();
+        std::fs::write(dir.join("files/node_config.yaml"), NODES_YAML).unwrap();
+        let config = read_config(this, &dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        config
+    }
+
+    fn node(addr: &str, spec: &[&str]) -> NodeConfig {
+        let spec = spec.iter().map(|s| s.to_string()).collect();
+        NodeConfig::new(addr.parse().unwrap(), None, spec)
+    }
+
+    fn member(node_id: NodeID, addr: &str, spec: &[&str]) -> ClusterMember {
+        ClusterMember {
+            node_id,
+            addr: addr.to_owned(),
+            domain: "".to_owned(),
+            spec: spec.iter().map(|s| s.to_string()).collect(),
+            draining: false,
+            health: proto::cluster::NodeHealth::Alive as i32,
+        }
+    }
+
+    #[test]
+    fn test_admit_node() {
+        let master = nodes_config(1, "admit");
+        assert!(can_admit(&master, 5, &node("127.0.0.1:2520", &["worker"])));
+        // the ids and the master role are taken
+        assert!(!can_admit(&master, 1, &node("127.0.0.1:2520", &["worker"])));
+        assert!(!can_admit(&master, 2, &node("127.0.0.1:2520", &["worker"])));
+        assert!(!can_admit(&master, 5, &node("127.0.0.1:2520", &["master"])));
+
+        // only master admits
+        let worker = nodes_config(2, "admit");
+        assert!(!can_admit(&worker, 5, &node("127.0.0.1:2520", &["worker"])));
+    }
+
+    #[test]
+    fn test_update_members() {
+        let nodes_config = nodes_config(2, "update");
+        let mut this = member(2, "127.0.0.1:2505", &["meta", "worker"]);
+        this.draining = true;
+        let mut moved = member(3, "127.0.0.1:2530", &["meta", "worker"]);
+        moved.health = proto::cluster::NodeHealth::Suspect as i32;
+        let change = update_members(
+            &nodes_config,
+            vec![
+                member(1, "127.0.0.1:2500", &["meta", "master"]),
+                this,
+                moved,
+                member(5, "127.0.0.1:2520", &["worker"]),
+                member(6, "invalid", &["worker"]),
+            ],
+        );
+        assert_eq!(
+            change,
+            MembersChange {
+                connect: vec![
+                    (3, "127.0.0.1:2530".parse().unwrap()),
+                    (5, "127.0.0.1:2520".parse().unwrap())
+                ],
+                // the old addr of 3 and the 4 left
+                disconnect: vec![
+                    "127.0.0.1:2510".parse().unwrap(),
+                    "127.0.0.1:2515".parse().unwrap()
+                ],
+            }
+        );
+        assert!(nodes_config.is_draining(2));
+        assert_eq!(nodes_config.node_health(3), NodeHealth::Suspect);
+        assert!(nodes_config.get_peer(4).is_none());
+        assert!(nodes_config.get_peer(6).is_none());
+        let mut peers = nodes_config.peer_ids();
+        peers.sort();
+        assert_eq!(peers, vec![1, 3, 5]);
+
+        // same members, nothing to change, and master is never removed
+        let change = update_members(
+            &nodes_config,
+            vec![
+                member(2, "127.0.0.1:2505", &["meta", "worker"]),
+                member(3, "127.0.0.1:2530", &["meta", "worker"]),
+                member(5, "127.0.0.1:2520", &["worker"]),
+            ],
+        );
+        assert_eq!(change, MembersChange::default());
+        assert!(!nodes_config.is_draining(2));
+        assert_eq!(nodes_config.node_health(3), NodeHealth::Alive);
+        assert!(nodes_config.get_peer(1).is_some());
+    }
+}
@@ -1,5 +1,8 @@
 use async_trait::async_trait;
-use sysinfo::{CpuExt, CpuRefreshKind, RefreshKind, System, SystemExt};
+use std::{path::Path, time::Instant};
+use sysinfo::{
+    CpuExt, DiskExt, NetworkExt, NetworksExt, Pid, PidExt, ProcessExt, System, SystemExt,
+};
 use ws_derive::LogicalModule;
 
 use crate::{
@@ -7,6 +10,7 @@ use crate::{
     result::WSResult,
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
     util::JoinHandleWrapper,
+    worker::func::m_instance_manager::InstanceManager,
 };
 
 use super::network::{
@@ -18,6 +22,11 @@ logical_module_view_impl!(MetricPublisherView);
 logical_module_view_impl!(MetricPublisherView, p2p, P2PModule);
 // logical_module_view_impl!(MetricPublisherView, metric_observor, Option<MetricObservor>);
 logical_module_view_impl!(MetricPublisherView, metric_publisher, MetricPublisher);
+logical_module_view_impl!(
+    MetricPublisherView,
+    instance_manager,
+    Option<InstanceManager>
+);
 
 #[derive(LogicalModule)]
 pub struct MetricPublisher {
@@ -46,50 +55,93 @@ impl LogicalModule for MetricPublisher {
     }
 }
 
+/// the disk holding the dir, with the longest mount point
+fn disk_usage(sys: &System, dir: &Path) -> (u64, u64) {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    sys.disks()
+        .iter()
+        .filter(|d| dir.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+        .map(|d| {
+            (
+                d.total_space().saturating_sub(d.available_space()),
+                d.total_space(),
+            )
+        })
+        .unwrap_or((0, 0))
+}
+
+/// of this node process, none on the systems without procfs
+fn open_fds() -> u32 {
+    std::fs::read_dir("/proc/self/fd")
+        .map(|d| d.count() as u32)
+        .unwrap_or(0)
+}
+
 async fn report_metric_task(view: MetricPublisherView) {
-    // let mut m = machine_info::Machine::new();
-    // let info = m.system_info();
-    // let total_mem = info.memory as u32;
-    // let total_cpu = (info.processor.frequency * (info.total_processors as u64)) as u32;
-    let mut sys = System::new_with_specifics(
-        RefreshKind::new()
-            .with_cpu(CpuRefreshKind::everything())
-            .with_memory(),
-    );
-    // First we update all information of our `System` struct.
-    sys.refresh_all();
+    let nodes_config = &view.p2p().nodes_config;
+    let interval = nodes_config.metric.report_interval();
+    let mut sys = System::new();
+    sys.refresh_cpu();
+    sys.refresh_disks_list();
+    sys.refresh_networks_list();
+    let mut last_refresh = Instant::now();
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-        sys.refresh_all();
-        // let status = m.system_status().unwrap();
+        tokio::time::sleep(interval).await;
+        sys.refresh_cpu();
+        sys.refresh_memory();
+        sys.refresh_disks();
+        sys.refresh_networks();
+        let secs = last_refresh.elapsed().as_secs_f64().max(0.001);
+        last_refresh = Instant::now();
 
-        let cpu_all = sys.cpus()[0].frequency() * sys.cpus().len() as u64;
-        let cpu_used =
-            sys.cpus().iter().map(|c| c.cpu_usage()).sum::<f32>() / sys.cpus().len() as f32;
+        let cpus = sys.cpus();
+        let cpu_used = cpus.iter().map(|c| c.cpu_usage()).sum::<f32>() / cpus.len() as f32;
+        let load = sys.load_average();
+        let (disk_used, disk_all) = disk_usage(&sys, &nodes_config.file_dir);
+        let (rx, tx) = sys.networks().iter().fold((0, 0), |(rx, tx), (_, n)| {
+            (rx + n.received(), tx + n.transmitted())
+        });
 
-        let metric = proto::metric::RscMetric {
+        let mut metric = proto::metric::RscMetric {
             cpu_used,
             mem_used: sys.used_memory() as f32,
-            cpu_all: cpu_all as f32,
+            cpu_all: cpus.len() as f32,
             mem_all: sys.total_memory() as f32,
+            load_avg_1: load.one as f32,
+            load_avg_5: load.five as f32,
+            load_avg_15: load.fifteen as f32,
+            disk_used,
+            disk_all,
+            net_rx_rate: (rx as f64 / secs) as u64,
+            net_tx_rate: (tx as f64 / secs) as u64,
+            open_fds: open_fds(),
+            ..Default::default()
         };
-        // println!("send metrics to master");
-        // let node_config = view.p2p().nodes_config;
+        if nodes_config.this.1.is_worker() {
+            let instance_manager = view.instance_manager();
+            let (wasm, running, processes) = instance_manager.instance_counts();
+            metric.wasm_instances = wasm as u32;
+            metric.wasm_running = running as u32;
+            metric.process_instances = processes as u32;
+            for (app, pid) in instance_manager.app_pids() {
+                let pid = Pid::from_u32(pid);
+                if !sys.refresh_process(pid) {
+                    continue;
+                }
+                if let Some(p) = sys.process(pid) {
+                    metric.app_rss.push(proto::metric::AppRss {
+                        app,
+                        rss: p.memory(),
+                    });
+                }
+            }
+        }
 
-        // if node_config.this_node() == node_config.get_master_node() {
-        //     view.metric_observor()
-        //         .insert_node_rsc_metric(view.p2p().nodes_config.this.0, metric);
-        // } else {
         let _res = view
             .metric_publisher()
             .msg_sender
-            .send(
-                view.p2p(),
-                view.p2p().nodes_config.get_master_node(),
-                metric,
-            )
+            .send(view.p2p(), nodes_config.get_master_node(), metric)
             .await;
-        // .send_resp(1, 0, metric).await;
-        // }
     }
 }
@@ -1,3 +1,4 @@
+mod sandbox;
 pub mod zip;
 
 use super::{
@@ -11,9 +12,10 @@ use super::{
     },
 };
 use crate::{
+    config::RemoteSysConfig,
     general::network::proto,
     logical_module_view_impl,
-    result::{ErrCvt, WSError, WSResult, WsIoErr},
+    result::{ErrCvt, WSError, WSResult, WsIoErr, WsPermissionErr},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
     util::JoinHandleWrapper,
 };
@@ -27,7 +29,6 @@ use std::{
     path::{Path, PathBuf},
     process::Stdio,
     sync::Arc,
-    time::SystemTime,
 };
 use tokio::process::{self, Command};
 use ws_derive::LogicalModule;
@@ -52,6 +53,8 @@ pub struct OperatingSystem {
     pub remote_run_cmd_caller: RPCCaller<proto::remote_sys::RunCmdReq>,
 
     remote_run_cmd_handler: RPCHandler<proto::remote_sys::RunCmdReq>,
+
+    remote_sys: RemoteSysConfig,
 }
 
 #[async_trait]
@@ -70,6 +73,8 @@ impl LogicalModule for OperatingSystem {
 
             remote_run_cmd_caller: RPCCaller::new(),
             remote_run_cmd_handler: RPCHandler::new(),
+
+            remote_sys: args.nodes_config.remote_sys.clone(),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
@@ -107,29 +112,46 @@ pub enum OsProcessType {
     JavaCheckpoints(String),
 }
 
+/// how a function opens a file in its sandbox
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FnOpenOptions {
+    pub write: bool,
+    /// writes go to the end whatever the offset
+    pub append: bool,
+    /// also creates the parent dirs
+    pub create: bool,
+    pub truncate: bool,
+}
+
+pub struct FnDirEntry {
+    pub name: String,
+    pub is_dir: bool,
+}
+
 impl OperatingSystem {
     pub fn app_path(&self, app: &str) -> PathBuf {
         self.view.appmeta_manager().fs_layer.concat_app_dir(app)
     }
+    /// a java app with a checkpoint is restored by CRaC instead of started from scratch
+    pub fn java_has_checkpoint(&self, app: &str) -> bool {
+        let appdir = self.view.appmeta_manager().fs_layer.concat_app_dir(app);
+        std::fs::read_dir(appdir.join("checkpoint-dir")).is_ok()
+    }
+    /// the output of java apps and checkpoints is piped for the caller to capture
     pub fn start_process(&self, p: OsProcessType) -> process::Child {
-        let (mut binding, log_file) = match p {
+        let mut binding = match p {
             OsProcessType::JavaApp(app) => {
                 // let crac_config_path = self.view.appmeta_manager().fs_layer.crac_file_path();
                 let appdir = self.view.appmeta_manager().fs_layer.concat_app_dir(&app);
-                // 打开或创建日志文件
-                let log_file_path = appdir.join(format!("app{:?}.log", SystemTime::now()));
-                // 打开或创建日志文件
-                let log_file = File::create(log_file_path).expect("Failed to create log file");
-
                 // check dir contains checkpoint-dir
-                if std::fs::read_dir(appdir.join("checkpoint-dir")).is_ok() {
+                if self.java_has_checkpoint(&app) {
                     tracing::debug!("start process with checkpoint");
                     let mut binding = Command::new("java");
                     let _ = binding
                         // .arg("-Djava.net.preferIPv4Stack=true")
                         .arg("-XX:CRaCRestoreFrom=checkpoint-dir")
                         .current_dir(appdir);
-                    (binding, log_file)
+                    binding
                 } else {
                     tracing::debug!("start process app without checkpoint {}", app);
 
@@ -143,7 +165,7 @@ impl OperatingSystem {
                         .arg("--agentSock=../../agent.sock")
                         .arg(format!("--appName={}", app))
                         .current_dir(appdir);
-                    (binding, log_file)
+                    binding
                 }
             }
             OsProcessType::JavaCheckpoints(app) => {
@@ -151,24 +173,17 @@ impl OperatingSystem {
                 // create checkpoint-dir
                 let _ = std::fs::create_dir(appdir.join("checkpoint-dir"));
 
-                // 打开或创建日志文件
-                let log_file_path = appdir.join("checkpoint.log");
-                // 打开或创建日志文件
-                let log_file = File::create(log_file_path).expect("Failed to create log file");
-
                 let mut binding = Command::new("jcmd");
                 let _ = binding
                     .arg("app.jar")
                     .arg("JDK.checkpoint")
                     .current_dir(appdir);
-                (binding, log_file)
+                binding
             }
         };
         binding
-            .stdout(Stdio::from(
-                log_file.try_clone().expect("Failed to clone log file"),
-            )) // 重定向stdout到日志文件
-            .stderr(Stdio::from(log_file)) // 重定向stderr到日志文件
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             // binding.stdout(
             //     // out put to terminal
             //     // std::process::Stdio::inherit()
@@ -181,65 +196,70 @@ impl OperatingSystem {
 
     // pub async fn run_cmd_local(&self, cmd: OsCmd) {}
 
-    async fn remote_run_cmd_handler(&self, responser: RPCResponsor<RunCmdReq>, msg: RunCmdReq) {
-        let res = tokio::task::spawn_blocking(move || {
-            // temp run use random name
-            let tmpsh = std::env::temp_dir().join(format!("tmpsh-{}.sh", uuid::Uuid::new_v4()));
-
-            let mut file = match File::create(tmpsh.clone()) {
-                Ok(file) => file,
-                Err(e) => {
-                    return RunCmdResp {
-                        dispatch: Some(proto::remote_sys::run_cmd_resp::Dispatch::Err(
-                            proto::remote_sys::run_cmd_resp::RunCmdRespErr {
-                                error: format!("err in remote_run_cmd_handler1({:?}): {}", &msg, e),
-                            },
-                        )),
-                    };
-                }
-            };
-
-            // 将内容写入文件
-            tracing::debug!("will run cmd: {}", &msg.cmd);
-            match file.write_all(format!("cd {}\n{}\n", &msg.workdir, &msg.cmd).as_bytes()) {
-                Ok(()) => (),
-                Err(e) => {
-                    return RunCmdResp {
-                        dispatch: Some(proto::remote_sys::run_cmd_resp::Dispatch::Err(
-                            proto::remote_sys::run_cmd_resp::RunCmdRespErr {
-                                error: format!("err in remote_run_cmd_handler2({:?}): {}", &msg, e),
-                            },
-                        )),
-                    };
-                }
+    /// the workdir and the listed dir must be under the allowed ones,
+    /// relative ones are under the file dir as the allowlist
+    fn remote_sys_check_path(&self, path: &str) -> WSResult<PathBuf> {
+        sandbox::resolve_allowed_path(
+            &self.file_path,
+            &self.remote_sys.path_allowlist,
+            &self.file_path,
+            path,
+        )
+        .ok_or_else(|| {
+            WsPermissionErr::RemotePathNotAllowed {
+                path: path.to_owned(),
             }
+            .into()
+        })
+    }
 
-            let output = std::process::Command::new("bash")
-                .arg(tmpsh)
-                // .current_dir(&msg.workdir)
-                .output();
-            match output {
-                Ok(output) => {
-                    let output = String::from_utf8_lossy(&output.stdout).to_string();
-                    tracing::debug!("remote_run_cmd_handler output: {}", output);
-                    RunCmdResp {
-                        dispatch: Some(proto::remote_sys::run_cmd_resp::Dispatch::Ok(
-                            proto::remote_sys::run_cmd_resp::RunCmdRespOk { output },
-                        )),
-                    }
-                }
-                Err(e) => RunCmdResp {
-                    dispatch: Some(proto::remote_sys::run_cmd_resp::Dispatch::Err(
-                        proto::remote_sys::run_cmd_resp::RunCmdRespErr {
-                            error: format!("err in remote_run_cmd_handler({:?}): {}", &msg, e),
-                        },
-                    )),
-                },
-            }
+    async fn remote_run_cmd(&self, msg: &RunCmdReq) -> WSResult<String> {
+        if !self.remote_sys.enable {
+            return Err(WsPermissionErr::RemoteSysDisabled.into());
+        }
+        let workdir = self.remote_sys_check_path(&msg.workdir)?;
+        let (program, args) =
+            sandbox::split_allowed_cmd(&self.file_path, &self.remote_sys, &workdir, &msg.cmd)?;
+        let output = tokio::task::spawn_blocking(move || {
+            std::process::Command::new(program)
+                .args(args)
+                .current_dir(workdir)
+                .output()
         })
         .await
-        .unwrap();
-        responser.send_resp(res).await;
+        .unwrap()
+        .map_err(|e| ErrCvt(e).to_ws_io_err())?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    async fn remote_run_cmd_handler(&self, responser: RPCResponsor<RunCmdReq>, msg: RunCmdReq) {
+        let res = self.remote_run_cmd(&msg).await;
+        tracing::info!(
+            target: "audit",
+            "remote run cmd from node {}, cmd: {:?}, workdir: {:?}, ok: {}",
+            responser.node_id(),
+            msg.cmd,
+            msg.workdir,
+            res.is_ok()
+        );
+        let dispatch = match res {
+            Ok(output) => {
+                tracing::debug!("remote_run_cmd_handler output: {}", output);
+                proto::remote_sys::run_cmd_resp::Dispatch::Ok(
+                    proto::remote_sys::run_cmd_resp::RunCmdRespOk { output },
+                )
+            }
+            Err(e) => proto::remote_sys::run_cmd_resp::Dispatch::Err(
+                proto::remote_sys::run_cmd_resp::RunCmdRespErr {
+                    error: format!("err in remote_run_cmd_handler({:?}): {:?}", &msg, e),
+                },
+            ),
+        };
+        let _ = responser
+            .send_resp(RunCmdResp {
+                dispatch: Some(dispatch),
+            })
+            .await;
     }
 
     async fn remote_get_dir_content_handler(
@@ -247,10 +267,36 @@ impl OperatingSystem {
         responser: RPCResponsor<GetDirContentReq>,
         msg: GetDirContentReq,
     ) {
+        let checked = if self.remote_sys.enable {
+            self.remote_sys_check_path(&msg.path)
+        } else {
+            Err(WsPermissionErr::RemoteSysDisabled.into())
+        };
+        tracing::info!(
+            target: "audit",
+            "remote get dir content from node {}, path: {:?}, ok: {}",
+            responser.node_id(),
+            msg.path,
+            checked.is_ok()
+        );
+        let path = match checked {
+            Ok(path) => path,
+            Err(e) => {
+                let _ = responser
+                    .send_resp(GetDirContentResp {
+                        dispatch: Some(get_dir_content_resp::Dispatch::Fail(
+                            GetDirContentRespFail {
+                                error: format!("{:?}", e),
+                            },
+                        )),
+                    })
+                    .await;
+                return;
+            }
+        };
         let res = tokio::task::spawn_blocking(move || {
-            let path = Path::new(&msg.path);
-            if path.exists() && path.is_dir() {
-                if let Ok(entries) = fs::read_dir(path) {
+            if path.is_dir() {
+                if let Ok(entries) = fs::read_dir(&path) {
                     let files = entries
                         .filter_map(|entry| {
                             if let Ok(entry) = entry {
@@ -309,8 +355,15 @@ impl OperatingSystem {
         responser.send_resp(res).await;
     }
 
-    pub fn open_file(&self, fname: &str) -> WSResult<i32> {
-        let fp = self.file_path.join("files").join(fname);
+    /// read only, `fname` is relative to the shared `files` dir and can't get out of it
+    pub fn open_file(&self, app: &str, fname: &str) -> WSResult<i32> {
+        let dir = self.file_path.join("files");
+        let fp = sandbox::resolve_app_path(&dir, fname).ok_or_else(|| {
+            WsPermissionErr::FnPathNotAllowed {
+                app: app.to_owned(),
+                path: fname.to_owned(),
+            }
+        })?;
         tracing::debug!("openning file {:?}", fp);
         let f = File::open(fp).map_err(|e| ErrCvt(e).to_ws_io_err())?;
         let fd = f.as_raw_fd();
@@ -318,24 +371,88 @@ impl OperatingSystem {
         Ok(fd)
     }
     pub fn close_file(&self, fd: i32) -> WSResult<()> {
-        if let Some(_) = self.fd_files.remove(&fd) {}
+        if self.fd_files.remove(&fd).is_none() {
+            return Err(WsIoErr::FdNotOpened(fd).into());
+        }
         Ok(())
     }
-    pub fn read_file_at(&self, fd: i32, offset: i32, buf: &mut [u8]) -> WSResult<usize> {
-        if let Some(f) = self.fd_files.get(&fd).map(|v| v.value().clone()) {
-            let mut f = f.lock();
-            let _ = f
-                .seek(std::io::SeekFrom::Start(offset as u64))
-                .map_err(|e| ErrCvt(e).to_ws_io_err())?;
-            // Read into the buffer
-            let bytes_read = f.read(buf).map_err(|e| ErrCvt(e).to_ws_io_err())?;
+    /// the ones left open when an invocation ends
+    pub fn close_files(&self, fds: &[i32]) {
+        for fd in fds {
+            let _ = self.fd_files.remove(fd);
+        }
+    }
+    pub fn read_file_at(&self, fd: i32, offset: u64, buf: &mut [u8]) -> WSResult<usize> {
+        let Some(f) = self.fd_files.get(&fd).map(|v| v.value().clone()) else {
+            return Err(WsIoErr::FdNotOpened(fd).into());
+        };
+        let mut f = f.lock();
+        let _ = f
+            .seek(std::io::SeekFrom::Start(offset))
+            .map_err(|e| ErrCvt(e).to_ws_io_err())?;
+        // Read into the buffer
+        let bytes_read = f.read(buf).map_err(|e| ErrCvt(e).to_ws_io_err())?;
 
-            Ok(bytes_read)
-        } else {
-            tracing::error!("function read_file_at: invalid fd {}", fd);
+        Ok(bytes_read)
+    }
+    pub fn write_file_at(&self, fd: i32, offset: u64, data: &[u8]) -> WSResult<usize> {
+        let Some(f) = self.fd_files.get(&fd).map(|v| v.value().clone()) else {
+            return Err(WsIoErr::FdNotOpened(fd).into());
+        };
+        let mut f = f.lock();
+        let _ = f.seek(std::io::SeekFrom::Start(offset))?;
+        let bytes_written = f.write(data)?;
+        Ok(bytes_written)
+    }
 
-            Ok(0)
+    /// the functions of the app could only touch the files under it
+    pub fn app_sandbox_dir(&self, app: &str) -> PathBuf {
+        self.file_path.join("sandbox").join(app)
+    }
+    /// relative to the sandbox, `..`, absolute paths and symlinks out of it are denied
+    fn app_sandbox_path(&self, app: &str, path: &str) -> WSResult<PathBuf> {
+        let dir = self.app_sandbox_dir(app);
+        fs::create_dir_all(&dir)?;
+        sandbox::resolve_app_path(&dir, path).ok_or_else(|| {
+            WsPermissionErr::FnPathNotAllowed {
+                app: app.to_owned(),
+                path: path.to_owned(),
+            }
+            .into()
+        })
+    }
+    pub fn open_app_file(&self, app: &str, path: &str, opts: FnOpenOptions) -> WSResult<i32> {
+        let fp = self.app_sandbox_path(app, path)?;
+        if opts.create {
+            if let Some(parent) = fp.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let f = fs::OpenOptions::new()
+            .read(true)
+            .write(opts.write || opts.create || opts.truncate)
+            .append(opts.append)
+            .create(opts.create)
+            .truncate(opts.truncate)
+            .open(fp)?;
+        let fd = f.as_raw_fd();
+        let _ = self.fd_files.insert(fd, Arc::new(Mutex::new(f)));
+        Ok(fd)
+    }
+    pub fn stat_app_file(&self, app: &str, path: &str) -> WSResult<fs::Metadata> {
+        Ok(fs::metadata(self.app_sandbox_path(app, path)?)?)
+    }
+    pub fn list_app_dir(&self, app: &str, path: &str) -> WSResult<Vec<FnDirEntry>> {
+        let dir = self.app_sandbox_path(app, path)?;
+        let mut entries = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            entries.push(FnDirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                is_dir: entry.file_type()?.is_dir(),
+            });
         }
+        Ok(entries)
     }
 
     pub fn cover_data_2_path(&self, p: impl AsRef<Path>, data: Vec<u8>) -> WSResult<()> {
@@ -0,0 +1,227 @@
+//! Path checks of the remote sys rpcs and the file host functions.
+use crate::{
+    config::RemoteSysConfig,
+    result::{WSError, WSResult, WsPermissionErr},
+};
+use std::path::{Component, Path, PathBuf};
+
+/// `path` relative to `base`, resolved with the `..` and symlinks,
+/// must be under one of the allowed dirs, the relative ones of which are under `file_dir`
+pub(super) fn resolve_allowed_path(
+    file_dir: &Path,
+    allowlist: &[PathBuf],
+    base: &Path,
+    path: &str,
+) -> Option<PathBuf> {
+    let path = base.join(path).canonicalize().ok()?;
+    let allowed = allowlist.iter().any(|dir| {
+        file_dir
+            .join(dir)
+            .canonicalize()
+            .is_ok_and(|dir| path.starts_with(dir))
+    });
+    allowed.then_some(path)
+}
+
+/// `path` relative to the sandbox `dir` of an app, lexically and with the symlinks resolved,
+/// the missing part to be created is checked from its deepest existing parent
+pub(super) fn resolve_app_path(dir: &Path, path: &str) -> Option<PathBuf> {
+    let normal = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !normal {
+        return None;
+    }
+    let dir = dir.canonicalize().ok()?;
+    let full = dir.join(path);
+    let mut existing = full.as_path();
+    let resolved = loop {
+        match existing.canonicalize() {
+            Ok(resolved) => break resolved,
+            // a dangling symlink, created through it otherwise
+            Err(_) if existing.symlink_metadata().is_ok() => return None,
+            Err(_) => existing = existing.parent()?,
+        }
+    };
+    if !resolved.starts_with(&dir) {
+        return None;
+    }
+    Some(resolved.join(full.strip_prefix(existing).ok()?))
+}
+
+/// the part of an arg taken as a path, the value of `--opt=value` and `-o/value` included,
+/// bare words count when they exist under the workdir
+fn path_in_arg<'a>(workdir: &Path, arg: &'a str) -> Option<&'a str> {
+    let value = if arg.starts_with('-') {
+        match arg.split_once('=') {
+            Some((_, value)) => value,
+            None => &arg[arg.find('/')?..],
+        }
+    } else {
+        arg
+    };
+    let path_like = value.contains('/')
+        || value.starts_with('~')
+        || value == ".."
+        || workdir.join(value).symlink_metadata().is_ok();
+    path_like.then_some(value)
+}
+
+/// split into program and args, no shell is involved so that the allowed program can't chain others,
+/// the args naming paths must be under the allowed dirs as the workdir
+pub(super) fn split_allowed_cmd(
+    file_dir: &Path,
+    remote_sys: &RemoteSysConfig,
+    workdir: &Path,
+    cmd: &str,
+) -> WSResult<(String, Vec<String>)> {
+    let cmd_denied = || {
+        WSError::from(WsPermissionErr::RemoteCmdNotAllowed {
+            cmd: cmd.to_owned(),
+        })
+    };
+    let mut words = cmd.split_whitespace().map(|w| w.to_owned());
+    let program = words.next().ok_or_else(cmd_denied)?;
+    if !remote_sys.cmd_allowlist.contains(&program) {
+        return Err(cmd_denied());
+    }
+    let args: Vec<String> = words.collect();
+    for arg in &args {
+        if let Some(path) = path_in_arg(workdir, arg) {
+            if resolve_allowed_path(file_dir, &remote_sys.path_allowlist, workdir, path).is_none() {
+                return Err(WsPermissionErr::RemotePathNotAllowed { path: arg.clone() }.into());
+            }
+        }
+    }
+    Ok((program, args))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// removed on drop
+    struct TestDir(PathBuf);
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("ws_sandbox_{}_{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TestDir(dir.canonicalize().unwrap())
+        }
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn remote_sys() -> RemoteSysConfig {
+        RemoteSysConfig {
+            enable: true,
+            cmd_allowlist: vec!["ls".to_owned(), "cat".to_owned()],
+            path_allowlist: vec![PathBuf::from("apps")],
+        }
+    }
+
+    #[test]
+    fn test_remote_cmd_args_checked() {
+        let file_dir = TestDir::new("cmd");
+        let apps = file_dir.path().join("apps");
+        std::fs::create_dir_all(apps.join("app1")).unwrap();
+        std::fs::write(apps.join("app1/app.yaml"), "fns: {}").unwrap();
+        std::fs::write(file_dir.path().join("secret"), "x").unwrap();
+        let cfg = remote_sys();
+        let check = |cmd: &str| split_allowed_cmd(file_dir.path(), &cfg, &apps, cmd);
+        let denied_path = |cmd: &str| match check(cmd) {
+            Err(WSError::WsPermissionErr(WsPermissionErr::RemotePathNotAllowed { path })) => path,
+            res => panic!("{} not denied by path: {:?}", cmd, res),
+        };
+
+        assert_eq!(
+            check("cat app1/app.yaml").unwrap(),
+            ("cat".to_owned(), vec!["app1/app.yaml".to_owned()])
+        );
+        assert!(check("ls -la").is_ok());
+        assert!(check("ls app1").is_ok());
+        assert!(matches!(
+            check("rm app1"),
+            Err(WSError::WsPermissionErr(
+                WsPermissionErr::RemoteCmdNotAllowed { .. }
+            ))
+        ));
+        assert_eq!(denied_path("cat /etc/shadow"), "/etc/shadow");
+        assert_eq!(denied_path("ls .."), "..");
+        assert_eq!(denied_path("cat ../secret"), "../secret");
+        assert_eq!(denied_path("ls --color=/root"), "--color=/root");
+        assert_eq!(denied_path("ls -I/root"), "-I/root");
+    }
+
+    #[test]
+    fn test_remote_relative_path_under_file_dir() {
+        let file_dir = TestDir::new("rel");
+        std::fs::create_dir_all(file_dir.path().join("apps/app1")).unwrap();
+        let allowlist = vec![PathBuf::from("apps")];
+        // relative to the file dir, not the cwd of the process
+        assert!(
+            resolve_allowed_path(file_dir.path(), &allowlist, file_dir.path(), "apps/app1")
+                .is_some()
+        );
+        assert!(
+            resolve_allowed_path(file_dir.path(), &allowlist, file_dir.path(), "apps/../")
+                .is_none()
+        );
+        assert!(resolve_allowed_path(file_dir.path(), &allowlist, file_dir.path(), "/").is_none());
+    }
+    #[test]
+    fn test_app_path_in_sandbox() {
+        let file_dir = TestDir::new("app");
+        let sandbox = file_dir.path().join("sandbox/app1");
+        std::fs::create_dir_all(sandbox.join("data")).unwrap();
+        std::fs::write(sandbox.join("data/a.txt"), "a").unwrap();
+        std::fs::write(file_dir.path().join("secret"), "x").unwrap();
+
+        assert_eq!(
+            resolve_app_path(&sandbox, "data/a.txt").unwrap(),
+            sandbox.join("data/a.txt")
+        );
+        assert_eq!(
+            resolve_app_path(&sandbox, "./data/new/b.txt").unwrap(),
+            sandbox.join("data/new/b.txt")
+        );
+        assert!(resolve_app_path(&sandbox, "..").is_none());
+        assert!(resolve_app_path(&sandbox, "data/../../../secret").is_none());
+        assert!(resolve_app_path(&sandbox, "/etc/passwd").is_none());
+        assert!(resolve_app_path(&sandbox, "").is_some());
+    }
+
+    #[test]
+    fn test_app_path_symlinks() {
+        let file_dir = TestDir::new("link");
+        let sandbox = file_dir.path().join("sandbox/app1");
+        std::fs::create_dir_all(sandbox.join("data")).unwrap();
+        std::fs::write(file_dir.path().join("secret"), "x").unwrap();
+        let link = |target: &Path, name: &str| {
+            std::os::unix::fs::symlink(target, sandbox.join(name)).unwrap()
+        };
+        link(&file_dir.path().join("secret"), "secret_link");
+        link(file_dir.path(), "dir_link");
+        link(&file_dir.path().join("missing"), "dangling_link");
+        link(&sandbox.join("data"), "data_link");
+
+        assert!(resolve_app_path(&sandbox, "secret_link").is_none());
+        assert!(resolve_app_path(&sandbox, "dir_link/secret").is_none());
+        assert!(resolve_app_path(&sandbox, "dir_link/new.txt").is_none());
+        assert!(resolve_app_path(&sandbox, "dangling_link").is_none());
+        assert!(resolve_app_path(&sandbox, "dangling_link/new.txt").is_none());
+        // the ones staying in the sandbox are fine
+        assert_eq!(
+            resolve_app_path(&sandbox, "data_link/new.txt").unwrap(),
+            sandbox.join("data/new.txt")
+        );
+    }
+}
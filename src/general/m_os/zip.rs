@@ -2,7 +2,7 @@ use super::OperatingSystem;
 use crate::result::{WSError, WSResult, WsIoErr};
 use std::{
     fs::{self, File},
-    io::{self, Cursor, Read, Seek, Write},
+    io::{self, Cursor, Seek, Write},
     os::unix::fs::PermissionsExt,
     path::Path,
 };
@@ -29,6 +29,28 @@ impl OperatingSystem {
         Ok(())
     }
 
+    /// same as `unzip_data_2_path`, but read the zip from file instead of memory
+    pub fn unzip_file_2_path(
+        &self,
+        p: impl AsRef<Path>,
+        zip_file: impl AsRef<Path>,
+    ) -> WSResult<()> {
+        let p = p.as_ref();
+        if p.exists() {
+            fs::remove_dir_all(p).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+        }
+        fs::create_dir_all(p).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+        let f = File::open(zip_file).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+        match zip_extract::extract(f, &p, false) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(WsIoErr::Zip(e).into());
+            }
+        }
+
+        Ok(())
+    }
+
     // pub fn zip_dir_2_data(&self, p: impl AsRef<Path>) -> WSResult<Vec<u8>> {
     //     let p = p.as_ref();
     //     let mut data = Vec::new();
@@ -59,7 +81,6 @@ impl OperatingSystem {
         //     .unix_permissions(0o755);
 
         let prefix = Path::new(prefix);
-        let mut buffer = Vec::new();
         for entry in it {
             let path = entry.path();
             let name = path.strip_prefix(prefix).unwrap();
@@ -82,13 +103,7 @@ impl OperatingSystem {
                 zip.start_file(path_as_string, options)
                     .map_err(|e| WSError::from(WsIoErr::Zip2(e)))?;
                 let mut f = File::open(path).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
-
-                let _ = f
-                    .read_to_end(&mut buffer)
-                    .map_err(|e| WSError::from(WsIoErr::Io(e)))?;
-                zip.write_all(&buffer)
-                    .map_err(|e| WSError::from(WsIoErr::Io(e)))?;
-                buffer.clear();
+                let _ = io::copy(&mut f, &mut zip).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
             } else if !name.as_os_str().is_empty() {
                 // Only if not root! Avoids path spec / warning
                 // and mapname conversion failed error on unzip
@@ -101,16 +116,20 @@ impl OperatingSystem {
         Ok(())
     }
 
-    pub fn zip_dir_2_data(
+    /// zip to `dst` on disk, so that a big dir is never held in memory
+    pub fn zip_dir_2_file(
         &self,
         src_dir: &Path,
         method: zip::CompressionMethod,
-    ) -> WSResult<Vec<u8>> {
+        dst: &Path,
+    ) -> WSResult<()> {
         if !Path::new(src_dir).is_dir() {
             return Err(WsIoErr::Zip2(ZipError::FileNotFound).into());
         }
-
-        let mut data = Vec::new();
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
+        }
+        let f = File::create(dst).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
 
         let walkdir = WalkDir::new(src_dir);
         let it = walkdir.into_iter();
@@ -118,10 +137,8 @@ impl OperatingSystem {
         Self::zip_dir(
             &mut it.filter_map(|e| e.ok()),
             src_dir,
-            io::Cursor::new(&mut data),
+            io::BufWriter::new(f),
             method,
-        )?;
-
-        Ok(data)
+        )
     }
 }
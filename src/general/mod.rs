@@ -1,7 +1,9 @@
 pub mod kv_interface;
 pub mod m_appmeta_manager;
 pub mod m_data_general;
+pub mod m_fn_logs;
 pub mod m_kv_store_engine;
+pub mod m_membership;
 pub mod m_metric_publisher;
 pub mod m_os;
 pub mod network;
@@ -4,7 +4,7 @@ use crate::{
         self, AddServiceReq, AddServiceResp, ApiHandler, DeleteServiceReq, DeleteServiceResp,
         GetServiceListResp, RunServiceActionReq, RunServiceActionResp,
     },
-    logical_module_view_impl,
+    logging, logical_module_view_impl,
     master::m_http_handler::MasterHttpHandler,
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
     util::WithBind,
@@ -12,13 +12,16 @@ use crate::{
 };
 use async_trait::async_trait;
 use axum::{
-    extract::Path,
+    extract::{Extension, Path},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{get, post},
     Router,
 };
 use std::{net::SocketAddr, sync::OnceLock};
 use std::{ops::Deref, sync::atomic::AtomicUsize};
+use subtle::ConstantTimeEq;
 use tower_http::cors::CorsLayer;
 pub type ReqId = usize;
 
@@ -36,6 +39,65 @@ impl LocalReqIdAllocator {
     }
 }
 
+/// granted by the api key of a request, inserted into the request extensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiRole {
+    /// only the public functions
+    Anonymous,
+    Invoke,
+    Admin,
+}
+
+/// management apis, only for the admin keys
+const ADMIN_ROUTE_PREFIXES: &[&str] = &[
+    "/appmgmt/",
+    "/cluster/",
+    "/node/",
+    "/add_service",
+    "/delete_service",
+    "/get_service_list",
+    "/run_service_action",
+];
+
+/// `Authorization: Bearer <key>` or `x-api-key: <key>`,
+/// the calls through master need the latter, clients drop `Authorization` on the redirect to another host.
+/// other schemes of `Authorization`, like the `Basic` added by a proxy, are not taken as the key
+fn request_api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+}
+
+/// compared in constant time and with all the keys, so the timing tells nothing of them
+fn key_matches(keys: &[String], key: &str) -> bool {
+    keys.iter().fold(false, |matched, k| {
+        matched | bool::from(k.as_bytes().ct_eq(key.as_bytes()))
+    })
+}
+
+async fn auth<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let auth = &http_handler_view().p2p().nodes_config.http_auth;
+    let role = if !auth.enabled() {
+        ApiRole::Admin
+    } else {
+        match request_api_key(req.headers()) {
+            None => ApiRole::Anonymous,
+            Some(key) if key_matches(&auth.admin_keys, key) => ApiRole::Admin,
+            Some(key) if key_matches(&auth.invoke_keys, key) => ApiRole::Invoke,
+            Some(_) => return (StatusCode::UNAUTHORIZED, "invalid api key").into_response(),
+        }
+    };
+    let path = req.uri().path();
+    if role != ApiRole::Admin && ADMIN_ROUTE_PREFIXES.iter().any(|p| path.starts_with(p)) {
+        tracing::warn!("denied {:?} request to {}", role, path);
+        return (StatusCode::FORBIDDEN, "admin api key required").into_response();
+    }
+    let _ = req.extensions_mut().insert(role);
+    next.run(req).await
+}
+
 #[async_trait]
 pub trait HttpHandler: LogicalModule {
     fn building_router<'a>(&'a self) -> WithBind<'a, Router>;
@@ -142,7 +204,12 @@ pub async fn start_http_handler(modsref: LogicalModulesRef) {
     let app = app
         // .route("/:app/:fn", post(handler2))
         .route("/:route", post(handler))
+        .route("/node/log_level", get(get_log_level).put(put_log_level))
+        .layer(middleware::from_fn(auth))
         .layer(CorsLayer::permissive());
+    if !view.p2p().nodes_config.http_auth.enabled() {
+        tracing::warn!("no api key configured, http apis are open to anyone");
+    }
 
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
@@ -159,7 +226,29 @@ pub async fn start_http_handler(modsref: LogicalModulesRef) {
 //         .await
 // }
 
-async fn handler(route: Path<String>, body: String) -> impl IntoResponse {
+async fn get_log_level() -> Response {
+    match logging::log_level() {
+        Some(level) => (StatusCode::OK, level).into_response(),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "tracing not started").into_response(),
+    }
+}
+
+/// the body is the new filter directives, reset by restarting the node
+async fn put_log_level(body: String) -> Response {
+    match logging::set_log_level(body.trim()) {
+        Ok(()) => (StatusCode::OK, "ok").into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+async fn handler(
+    route: Path<String>,
+    Extension(role): Extension<ApiRole>,
+    body: String,
+) -> Response {
+    if role < ApiRole::Invoke {
+        return (StatusCode::UNAUTHORIZED, "api key required").into_response();
+    }
     http_handler_view()
         .http_handler()
         .handle_request(route.as_str(), body)
@@ -185,3 +274,60 @@ impl HttpHandlerDispatch {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (k, v) in pairs {
+            let _ = headers.insert(*k, HeaderValue::from_static(v));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_request_api_key() {
+        assert_eq!(request_api_key(&headers(&[])), None);
+        assert_eq!(
+            request_api_key(&headers(&[("authorization", "Bearer k1")])),
+            Some("k1")
+        );
+        assert_eq!(
+            request_api_key(&headers(&[("x-api-key", "k2")])),
+            Some("k2")
+        );
+        // bearer first
+        assert_eq!(
+            request_api_key(&headers(&[
+                ("authorization", "Bearer k1"),
+                ("x-api-key", "k2")
+            ])),
+            Some("k1")
+        );
+        // the other schemes fall back to x-api-key
+        assert_eq!(
+            request_api_key(&headers(&[
+                ("authorization", "Basic dXNlcjpwYXNz"),
+                ("x-api-key", "k2")
+            ])),
+            Some("k2")
+        );
+        assert_eq!(
+            request_api_key(&headers(&[("authorization", "Basic dXNlcjpwYXNz")])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_key_matches() {
+        let keys = vec!["k1".to_owned(), "k2".to_owned()];
+        assert!(key_matches(&keys, "k1"));
+        assert!(key_matches(&keys, "k2"));
+        assert!(!key_matches(&keys, "k"));
+        assert!(!key_matches(&keys, "k12"));
+        assert!(!key_matches(&[], "k1"));
+    }
+}
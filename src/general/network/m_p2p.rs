@@ -12,7 +12,7 @@ use super::{
 };
 use crate::{
     config::NodesConfig,
-    logical_module_view_impl,
+    logging, logical_module_view_impl,
     result::{ErrCvt, WSResult, WsNetworkConnErr, WsNetworkLogicErr},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
@@ -245,15 +245,7 @@ impl From<Bytes> for DispatchPayload {
 
 impl P2PModule {
     pub fn find_peer_id(&self, addr: &SocketAddr) -> Option<NodeID> {
-        self.nodes_config.peers.iter().find_map(
-            |(id, peer)| {
-                if peer.addr == *addr {
-                    Some(*id)
-                } else {
-                    None
-                }
-            },
-        )
+        self.nodes_config.find_peer_id(addr)
     }
     // pub fn listen(&self) -> tokio::sync::broadcast::Receiver<ModuleSignal> {
     //     self.state_trans_tx.subscribe()
@@ -372,16 +364,20 @@ impl P2PModule {
         self.call_rpc_inner::<R, R::Resp>(node_id, req, dur).await
     }
 
+    #[tracing::instrument(name = "rpc", skip_all, fields(node_id = node_id, msg_id = r.msg_id()))]
     async fn call_rpc_inner<REQ, RESP>(
         &self,
         node_id: NodeID,
-        r: REQ,
+        mut r: REQ,
         dur: Duration,
     ) -> WSResult<RESP>
     where
-        REQ: MsgPack,
+        REQ: RPCReq,
         RESP: MsgPack,
     {
+        if let Some(trace_ctx) = r.trace_ctx_mut() {
+            logging::inject_trace_ctx(trace_ctx);
+        }
         // tracing::debug!("call_rpc_inner req{:?}", r);
         // alloc from global
         let taskid: TaskId = self.next_task_id.fetch_add(1, Ordering::Relaxed);
@@ -488,7 +484,7 @@ impl P2PModule {
         }
     }
     pub fn get_addr_by_id(&self, id: NodeID) -> WSResult<SocketAddr> {
-        self.nodes_config.peers.get(&id).map_or_else(
+        self.nodes_config.get_peer(id).map_or_else(
             || Err(WsNetworkLogicErr::InvaidNodeID(id).into()),
             |v| Ok(v.addr),
         )
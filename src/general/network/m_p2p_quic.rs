@@ -14,16 +14,19 @@
 
 use async_trait::async_trait;
 
+use hmac::{Hmac, Mac};
 use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use sha2::Sha256;
 use prost::bytes::Bytes;
 use qp2p::{Connection, ConnectionIncoming, Endpoint, WireMsg};
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     net::{IpAddr, SocketAddr},
     str::FromStr,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
     },
     time::Duration,
     vec,
@@ -33,11 +36,92 @@ use ws_derive::LogicalModule;
 
 use crate::{
     // module_view::P2PQuicNodeLMView,
-    logical_module_view_impl, result::{ErrCvt, WSResult, WsNetworkConnErr, WsSerialErr}, sys::{LogicalModulesRef,BroadcastMsg, BroadcastSender, LogicalModule, LogicalModuleNewArgs, NodeID}, util::JoinHandleWrapper
+    config::NodeConfig, general::m_membership::Membership, logical_module_view_impl, result::{ErrCvt, WSResult, WsNetworkConnErr, WsSerialErr}, sys::{LogicalModulesRef,BroadcastMsg, BroadcastSender, LogicalModule, LogicalModuleNewArgs, NodeID}, util::JoinHandleWrapper
 };
 
 use super::m_p2p::{MsgId, P2PKernel, P2PModule, TaskId};
 
+// traffic is encrypted by the tls of quic, the peers are authenticated by
+// proving the cluster secret in the handshake before any msg is dispatched.
+// qp2p neither verifies the self-signed certs nor exposes the tls session to bind to,
+// so a relay could forward the handshake. Each msg after it is tagged with the key
+// derived from the handshake, which the relay doesn't know.
+type HmacSha256 = Hmac<Sha256>;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const HANDSHAKE_NONCE_LEN: usize = 32;
+const HANDSHAKE_ROLE_DIALER: &[u8] = b"dialer";
+const HANDSHAKE_ROLE_ACCEPTOR: &[u8] = b"acceptor";
+const HANDSHAKE_OK: &[u8] = b"ok";
+const SESSION_KEY_LABEL: &[u8] = b"session";
+/// msgs arrive out of order on the uni streams, the seqs below the window are rejected
+const REPLAY_WINDOW: usize = 4096;
+
+/// the key derived in the handshake of one connection, tags the msgs of both directions
+struct MsgAuth {
+    key: [u8; 32],
+    send_role: &'static [u8],
+    recv_role: &'static [u8],
+    next_send_seq: AtomicU64,
+}
+
+impl MsgAuth {
+    fn tag_mac(&self, role: &[u8], seq: &[u8], payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac takes key of any size");
+        mac.update(role);
+        mac.update(seq);
+        mac.update(payload);
+        mac
+    }
+    /// returns the seq and tag to send along
+    fn sign(&self, payload: &[u8]) -> (Bytes, Bytes) {
+        let seq = self
+            .next_send_seq
+            .fetch_add(1, Ordering::Relaxed)
+            .to_be_bytes();
+        let tag = self.tag_mac(self.send_role, &seq, payload).finalize();
+        (
+            Bytes::copy_from_slice(&seq),
+            Bytes::copy_from_slice(&tag.into_bytes()),
+        )
+    }
+    fn verify(&self, seq: &[u8], tag: &[u8], payload: &[u8], window: &mut ReplayWindow) -> bool {
+        let Ok(seq_bytes) = <[u8; 8]>::try_from(seq) else {
+            return false;
+        };
+        self.tag_mac(self.recv_role, seq, payload)
+            .verify_slice(tag)
+            .is_ok()
+            && window.accept(u64::from_be_bytes(seq_bytes))
+    }
+}
+
+/// the seqs received within the window, owned by the receiving task of the connection
+#[derive(Default)]
+struct ReplayWindow {
+    floor: u64,
+    seen: BTreeSet<u64>,
+}
+
+impl ReplayWindow {
+    fn accept(&mut self, seq: u64) -> bool {
+        if seq < self.floor || !self.seen.insert(seq) {
+            return false;
+        }
+        if self.seen.len() > REPLAY_WINDOW {
+            let oldest = self.seen.pop_first().unwrap();
+            self.floor = oldest + 1;
+        }
+        true
+    }
+}
+
+/// an authenticated connection
+struct PeerConn {
+    conn: Connection,
+    auth: Arc<MsgAuth>,
+}
+
 // #[derive(Default, Ord, PartialEq, PartialOrd, Eq, Clone, Copy)]
 // struct XId(pub [u8; 32]);
 
@@ -48,13 +132,15 @@ struct P2PQuicNodeLocked {
 struct P2PQuicNodeShared {
     locked: Mutex<P2PQuicNodeLocked>,
     btx: BroadcastSender,
+    /// set on start, used to dial the peers joined later
+    endpoint: OnceLock<Endpoint>,
     // shared_connection_map: tokio::sync::Mutex<HashMap<SocketAddr, ConnectionStuff>>,
     peer_connections: RwLock<
         HashMap<
             SocketAddr,
             Arc<(
                 // all the active connections to this peer
-                tokio::sync::RwLock<Vec<Connection>>,
+                tokio::sync::RwLock<Vec<PeerConn>>,
                 // the round robin index for the above vector
                 AtomicUsize,
                 // the number of active connections to this peer, avoid locking the above vector to call len()
@@ -81,6 +167,7 @@ impl P2PQuicNodeShared {
 
 logical_module_view_impl!(View);
 logical_module_view_impl!(View, p2p, P2PModule);
+logical_module_view_impl!(View, membership, Membership);
 
 #[derive(LogicalModule)]
 pub struct P2PQuicNode {
@@ -105,6 +192,7 @@ impl LogicalModule for P2PQuicNode {
             shared: P2PQuicNodeShared {
                 btx: args.btx,
                 locked: Mutex::new(P2PQuicNodeLocked { sub_tasks: vec![] }),
+                endpoint: OnceLock::new(),
                 peer_connections: HashMap::new().into(),
             }
             .into(),
@@ -129,48 +217,19 @@ impl LogicalModule for P2PQuicNode {
             })?;
 
         let shared = self.shared.clone();
+        let _ = shared.endpoint.set(endpoint.clone());
 
         let mut net_tasks: Vec<JoinHandleWrapper> = vec![];
 
-        for (n, n_config) in &self.p2p_base().nodes_config.peers {
-            let endpoint = endpoint.clone();
-            let addr = n_config.addr;
-            let shared = shared.clone();
-            let view = self.logical_modules_view.clone();
-            let n = n.clone();
+        for (n, n_config) in self.p2p_base().nodes_config.peers() {
             net_tasks.push(
-                tokio::spawn(async move {
-                    loop {
-                        tracing::info!("try to connect to {}", n);
-                        let res = endpoint.connect_to(&addr).await;
-                        match res {
-                            Ok((connection, incoming)) => {
-                                tracing::info!("connected to {}", addr);
-                                let _=connection.send((Bytes::new(),Bytes::new(), Bytes::from(this_addr.to_string()))).await;
-                                // send self addr
-                                handle_connection(
-                                    addr,
-                                    &view,
-                                    shared.clone(),
-                                    &endpoint,
-                                    connection,
-                                    incoming,
-                                )
-                                .await;
-                                // tracing::info!("handled conflict_connection {}", addr);
-                            }
-                            Err(e) => {
-                                tracing::warn!(
-                                    "connect to {} failed, error: {:?}, will retry",
-                                    addr,
-                                    e
-                                );
-                                
-                            }
-                        }
-                        tokio::time::sleep(Duration::from_secs(10)).await;
-                    }
-                })
+                spawn_dial_task(
+                    n,
+                    n_config.addr,
+                    &self.logical_modules_view,
+                    shared.clone(),
+                    endpoint.clone(),
+                )
                 .into(),
             );
         }
@@ -184,30 +243,9 @@ impl LogicalModule for P2PQuicNode {
             loop {
                 tokio::select! {
                     next_incoming= incoming_conns.next() => {
-                        if let Some((connection, mut incoming)) = next_incoming {
-                            let res = incoming.next().await;
-                            
-                            match res {
-                                Ok(msg) => {
-                                    if let Some(WireMsg((_head, _, bytes))) = msg {
-                                        let addr=String::from_utf8(bytes.to_vec()).unwrap().parse::<SocketAddr>().unwrap();
-                                        // tracing::info!("recv connect from {}", addr);
-                                        if view.p2p().find_peer_id(&addr).is_none(){
-                                            // tracing::warn!("recv connect from unknown peer {}", addr);
-                                            continue;
-                                        }
-                                        // handle_conflict_connection(&view,&shared, &endpoint, connection, incoming);
-                                        new_handle_connection_task(addr,&view, shared.clone(), endpoint.clone(), connection, incoming);
-                                    }else{
-                                        tracing::warn!("didn't recv head"); 
-                                        continue;   
-                                    }
-                                }
-                                Err(err)=>{
-                                    tracing::info!("recv head error {:?}",err);
-                                    continue;
-                                }
-                            }
+                        if let Some((connection, incoming)) = next_incoming {
+                            // authenticated in the task, not blocking the others
+                            new_handle_connection_task(&view, shared.clone(), endpoint.clone(), connection, incoming);
                         }else{
                             // no more connections, system shutdown
                             let _ =shared.btx.send(BroadcastMsg::SysEnd).unwrap_or_else(|err|{
@@ -298,13 +336,212 @@ impl LogicalModule for P2PQuicNode {
 //     let res=endpoint.connect_to(&addr).await
 // }
 
+/// keep connecting to the peer until it leaves the cluster
+fn spawn_dial_task(
+    n: NodeID,
+    addr: SocketAddr,
+    view: &View,
+    shared: Arc<P2PQuicNodeShared>,
+    endpoint: Endpoint,
+) -> JoinHandle<()> {
+    let view = view.clone();
+    tokio::spawn(async move {
+        // the peers not knowing us yet admit us by this
+        let handshake = Bytes::from(bincode::serialize(&view.p2p().nodes_config.this).unwrap());
+        loop {
+            if view.p2p().find_peer_id(&addr) != Some(n) {
+                tracing::info!("stop connecting to {}, not a member any more", n);
+                break;
+            }
+            tracing::info!("try to connect to {}", n);
+            let res = endpoint.connect_to(&addr).await;
+            match res {
+                Ok((connection, mut incoming)) => {
+                    tracing::info!("connected to {}", addr);
+                    match dial_handshake(&view, &handshake, addr, &connection, &mut incoming).await {
+                        Ok(auth) => {
+                            handle_connection(
+                                addr,
+                                &view,
+                                shared.clone(),
+                                &endpoint,
+                                PeerConn { conn: connection, auth: Arc::new(auth) },
+                                incoming,
+                            )
+                            .await;
+                        }
+                        Err(e) => {
+                            tracing::warn!("handshake with {} failed: {}", n, e);
+                            connection.close(Some("handshake failed".to_owned()));
+                        }
+                    }
+                    // tracing::info!("handled conflict_connection {}", addr);
+                }
+                Err(e) => {
+                    tracing::warn!("connect to {} failed, error: {:?}, will retry", addr, e);
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+    })
+}
+
+/// only master admits new members, the others learn them from master,
+/// `remote_addr` is where the connection comes from, not the one claimed in hello
+fn admit_unknown_peer(view: &View, remote_addr: SocketAddr, head: &[u8]) -> bool {
+    let Ok((id, config)) = bincode::deserialize::<(NodeID, NodeConfig)>(head) else {
+        return false;
+    };
+    if config.addr != remote_addr {
+        tracing::warn!("peer {} claims addr {} but connects from {}", id, config.addr, remote_addr);
+        return false;
+    }
+    view.membership().admit_node(id, config)
+}
+
+/// the addrs of both ends as each side sees them, a relay in between makes them differ
+struct HandshakeAddrs {
+    dialer: SocketAddr,
+    acceptor: SocketAddr,
+}
+
+/// hmac with the cluster secret over the nonces and addrs of both sides,
+/// the role keeps one side's proof from being reflected as the other's
+fn handshake_mac(
+    view: &View,
+    role: &[u8],
+    nonce_1: &[u8],
+    nonce_2: &[u8],
+    addrs: &HandshakeAddrs,
+) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(view.p2p().nodes_config.cluster_secret.as_bytes())
+        .expect("hmac takes key of any size");
+    mac.update(role);
+    mac.update(nonce_1);
+    mac.update(nonce_2);
+    mac.update(addrs.dialer.to_string().as_bytes());
+    mac.update(addrs.acceptor.to_string().as_bytes());
+    mac
+}
+
+fn new_msg_auth(view: &View, nonce_d: &[u8], nonce_a: &[u8], is_dialer: bool) -> MsgAuth {
+    let mut mac = HmacSha256::new_from_slice(view.p2p().nodes_config.cluster_secret.as_bytes())
+        .expect("hmac takes key of any size");
+    mac.update(SESSION_KEY_LABEL);
+    mac.update(nonce_d);
+    mac.update(nonce_a);
+    let (send_role, recv_role) = if is_dialer {
+        (HANDSHAKE_ROLE_DIALER, HANDSHAKE_ROLE_ACCEPTOR)
+    } else {
+        (HANDSHAKE_ROLE_ACCEPTOR, HANDSHAKE_ROLE_DIALER)
+    };
+    MsgAuth {
+        key: mac.finalize().into_bytes().into(),
+        send_role,
+        recv_role,
+        next_send_seq: AtomicU64::new(0),
+    }
+}
+
+fn new_handshake_nonce() -> Bytes {
+    let mut nonce = [0u8; HANDSHAKE_NONCE_LEN];
+    rand::thread_rng().fill(&mut nonce);
+    Bytes::copy_from_slice(&nonce)
+}
+
+async fn recv_handshake_msg(incoming: &mut ConnectionIncoming) -> Result<(Bytes, Bytes, Bytes), String> {
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, incoming.next()).await {
+        Err(_) => Err("timeout".to_owned()),
+        Ok(Err(e)) => Err(format!("{:?}", e)),
+        Ok(Ok(None)) => Err("connection closed".to_owned()),
+        Ok(Ok(Some(WireMsg(msg)))) => Ok(msg),
+    }
+}
+
+async fn send_handshake_msg(connection: &Connection, msg: (Bytes, Bytes, Bytes)) -> Result<(), String> {
+    connection.send(msg).await.map_err(|e| format!("{:?}", e))
+}
+
+/// dialer: hello(this, nonce_d, addr) -> challenge(nonce_a, mac_a) -> proof(mac_d) -> ok
+async fn dial_handshake(
+    view: &View,
+    this: &Bytes,
+    dialed: SocketAddr,
+    connection: &Connection,
+    incoming: &mut ConnectionIncoming,
+) -> Result<MsgAuth, String> {
+    let nonce_d = new_handshake_nonce();
+    let addrs = HandshakeAddrs {
+        dialer: view.p2p().nodes_config.this.1.addr,
+        acceptor: dialed,
+    };
+    let addr = addrs.dialer.to_string();
+    send_handshake_msg(connection, (this.clone(), nonce_d.clone(), Bytes::from(addr))).await?;
+
+    let (nonce_a, mac_a, _) = recv_handshake_msg(incoming).await?;
+    handshake_mac(view, HANDSHAKE_ROLE_ACCEPTOR, &nonce_d, &nonce_a, &addrs)
+        .verify_slice(&mac_a)
+        .map_err(|_| "peer doesn't know the cluster secret, or is relayed".to_owned())?;
+    let mac_d = handshake_mac(view, HANDSHAKE_ROLE_DIALER, &nonce_a, &nonce_d, &addrs).finalize();
+    send_handshake_msg(connection, (Bytes::copy_from_slice(&mac_d.into_bytes()), Bytes::new(), Bytes::new())).await?;
+
+    let (ok, _, _) = recv_handshake_msg(incoming).await?;
+    if &ok[..] != HANDSHAKE_OK {
+        return Err("not accepted by peer".to_owned());
+    }
+    Ok(new_msg_auth(view, &nonce_d, &nonce_a, true))
+}
+
+/// acceptor, return the addr of the authenticated and known peer,
+/// which is the one the connection comes from
+async fn accept_handshake(
+    view: &View,
+    connection: &Connection,
+    incoming: &mut ConnectionIncoming,
+) -> Result<(SocketAddr, MsgAuth), String> {
+    let remote_addr = connection.remote_address();
+    let (head, nonce_d, claimed) = recv_handshake_msg(incoming).await?;
+    let claimed = String::from_utf8(claimed.to_vec())
+        .ok()
+        .and_then(|addr| addr.parse::<SocketAddr>().ok())
+        .ok_or_else(|| "invalid addr in hello".to_owned())?;
+    if nonce_d.len() != HANDSHAKE_NONCE_LEN {
+        return Err("invalid nonce in hello".to_owned());
+    }
+    // peers dial from the endpoint they listen on
+    if claimed != remote_addr {
+        return Err(format!("claims addr {} but connects from {}", claimed, remote_addr));
+    }
+    let addrs = HandshakeAddrs {
+        dialer: remote_addr,
+        acceptor: view.p2p().nodes_config.this.1.addr,
+    };
+
+    let nonce_a = new_handshake_nonce();
+    let mac_a = handshake_mac(view, HANDSHAKE_ROLE_ACCEPTOR, &nonce_d, &nonce_a, &addrs).finalize();
+    send_handshake_msg(connection, (nonce_a.clone(), Bytes::copy_from_slice(&mac_a.into_bytes()), Bytes::new())).await?;
+
+    let (mac_d, _, _) = recv_handshake_msg(incoming).await?;
+    handshake_mac(view, HANDSHAKE_ROLE_DIALER, &nonce_a, &nonce_d, &addrs)
+        .verify_slice(&mac_d)
+        .map_err(|_| format!("{} doesn't know the cluster secret, or is relayed", remote_addr))?;
+
+    // only the authenticated ones could join
+    if view.p2p().find_peer_id(&remote_addr).is_none()
+        && !admit_unknown_peer(view, remote_addr, &head)
+    {
+        return Err(format!("{} is not a member", remote_addr));
+    }
+    send_handshake_msg(connection, (Bytes::from_static(HANDSHAKE_OK), Bytes::new(), Bytes::new())).await?;
+    Ok((remote_addr, new_msg_auth(view, &nonce_d, &nonce_a, false)))
+}
+
 fn new_handle_connection_task(
-    remote_addr: SocketAddr,
     view: &View,
     shared: Arc<P2PQuicNodeShared>,
     endpoint: Endpoint,
     connection: Connection,
-    incoming: ConnectionIncoming,
+    mut incoming: ConnectionIncoming,
 ) {
     let view = view.clone();
     shared
@@ -313,7 +550,17 @@ fn new_handle_connection_task(
         .lock()
         .sub_tasks
         .push(tokio::spawn(async move {
-            handle_connection(remote_addr,&view, shared, &endpoint, connection, incoming).await;
+            // nothing is dispatched before the peer is authenticated
+            let (remote_addr, auth) = match accept_handshake(&view, &connection, &mut incoming).await {
+                Ok(res) => res,
+                Err(e) => {
+                    tracing::warn!("reject connection from {}: {}", connection.remote_address(), e);
+                    connection.close(Some("handshake failed".to_owned()));
+                    return;
+                }
+            };
+            let conn = PeerConn { conn: connection, auth: Arc::new(auth) };
+            handle_connection(remote_addr,&view, shared, &endpoint, conn, incoming).await;
         }));
 }
 
@@ -324,7 +571,7 @@ async fn handle_connection(
     view: &View,
     shared: Arc<P2PQuicNodeShared>,
     _endpoint: &Endpoint,
-    connection: Connection,
+    connection: PeerConn,
     mut incoming: ConnectionIncoming,
 ) {
     println!("\n---");
@@ -332,13 +579,11 @@ async fn handle_connection(
     println!("---\n");
 
 
-    let remote_id = view.p2p().find_peer_id(&remote_addr).unwrap_or_else(|| {
-        panic!(
-            "remote_addr {:?} not found in peer_id_map {:?}",
-            remote_addr,
-            view.p2p().nodes_config
-        );
-    });
+    let Some(remote_id) = view.p2p().find_peer_id(&remote_addr) else {
+        tracing::warn!("remote_addr {:?} is not a member any more", remote_addr);
+        connection.conn.close(Some("not a member".to_owned()));
+        return;
+    };
 
     shared.reserve_peer_conn(remote_addr).await;
     let peer_conns = shared
@@ -347,7 +592,9 @@ async fn handle_connection(
         .get(&remote_addr)
         .unwrap()
         .clone();
-    let conn_id = connection.id();
+    let conn_id = connection.conn.id();
+    let auth = connection.auth.clone();
+    let mut replay_window = ReplayWindow::default();
     peer_conns.0.write().await.push(connection);
     let _ = peer_conns.2.fetch_add(1, Ordering::Relaxed);
 
@@ -355,8 +602,20 @@ async fn handle_connection(
         let res = incoming.next().await;
         match res {
             Ok(msg) => {
-                if let Some(WireMsg((_, _, mut bytes))) = msg {
+                if let Some(WireMsg((seq, tag, mut bytes))) = msg {
+                    if !auth.verify(&seq, &tag, &bytes, &mut replay_window) {
+                        tracing::warn!("drop msg from {} with invalid or replayed tag", remote_addr);
+                        continue;
+                    }
+                    if bytes.is_empty() {
+                        tracing::warn!("incoming empty msg");
+                        continue;
+                    }
                     let headlen=bytes.split_to(1)[0];
+                    if bytes.len() < headlen as usize {
+                        tracing::warn!("incoming msg shorter than its head");
+                        continue;
+                    }
                     let head=bytes.split_to(headlen as usize);
                     match deserialize_msg_id_task_id(&head) {
                         Ok((msg_id, task_id)) => {
@@ -379,7 +638,7 @@ async fn handle_connection(
         }
     }
 
-    peer_conns.0.write().await.retain(|v| v.id() != conn_id);
+    peer_conns.0.write().await.retain(|v| v.conn.id() != conn_id);
     let _ = peer_conns.2.fetch_sub(1, Ordering::Relaxed);
 
     // loop over incoming messages
@@ -404,6 +663,33 @@ fn serialize_msg_id_task_id(msg_id: MsgId, task_id: TaskId) -> Vec<u8> {
     head
 }
 
+impl P2PQuicNode {
+    /// dial the peer joined after start
+    pub fn connect_peer(&self, n: NodeID, addr: SocketAddr) {
+        let Some(endpoint) = self.shared.endpoint.get() else {
+            // not started yet, peers known by then are dialed on start
+            return;
+        };
+        let task = spawn_dial_task(
+            n,
+            addr,
+            &self.logical_modules_view,
+            self.shared.clone(),
+            endpoint.clone(),
+        );
+        self.shared.locked.lock().sub_tasks.push(task);
+    }
+    /// close the connections to the peer left the cluster
+    pub async fn disconnect_peer(&self, addr: SocketAddr) {
+        let peer_conns = self.shared.peer_connections.write().remove(&addr);
+        if let Some(peer_conns) = peer_conns {
+            for conn in peer_conns.0.write().await.drain(..) {
+                conn.conn.close(Some("left the cluster".to_owned()));
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl P2PKernel for P2PQuicNode {
     async fn send_for_response(&self, _nodeid: NodeID, _req_data: Vec<u8>) -> WSResult<Vec<u8>> {
@@ -442,10 +728,13 @@ impl P2PKernel for P2PQuicNode {
                     Bytes::from(v)
                 };
 
-                if let Err(err) = reading_conns[idx]
+                let peer_conn = &reading_conns[idx];
+                let (seq, tag) = peer_conn.auth.sign(&bytes);
+                if let Err(err) = peer_conn
+                    .conn
                     .send((
-                        Bytes::new(),
-                        Bytes::new(),
+                        seq,
+                        tag,
                         bytes,
                         // Bytes::new(),
                     ))
@@ -518,3 +807,47 @@ impl P2PKernel for P2PQuicNode {
 
 //     Ok(())
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn auth_pair() -> (MsgAuth, MsgAuth) {
+        let new = |send_role, recv_role| MsgAuth {
+            key: [7; 32],
+            send_role,
+            recv_role,
+            next_send_seq: AtomicU64::new(0),
+        };
+        (
+            new(HANDSHAKE_ROLE_DIALER, HANDSHAKE_ROLE_ACCEPTOR),
+            new(HANDSHAKE_ROLE_ACCEPTOR, HANDSHAKE_ROLE_DIALER),
+        )
+    }
+
+    #[test]
+    fn test_msg_auth() {
+        let (dialer, acceptor) = auth_pair();
+        let mut window = ReplayWindow::default();
+        let (seq_1, tag_1) = dialer.sign(b"msg1");
+        let (seq_2, tag_2) = dialer.sign(b"msg2");
+        // out of order is fine, replay and tampering are not
+        assert!(acceptor.verify(&seq_2, &tag_2, b"msg2", &mut window));
+        assert!(acceptor.verify(&seq_1, &tag_1, b"msg1", &mut window));
+        assert!(!acceptor.verify(&seq_1, &tag_1, b"msg1", &mut window));
+        let (seq_3, tag_3) = dialer.sign(b"msg3");
+        assert!(!acceptor.verify(&seq_3, &tag_3, b"msg4", &mut window));
+        // reflected back to the sender
+        assert!(!dialer.verify(&seq_3, &tag_3, b"msg3", &mut ReplayWindow::default()));
+    }
+
+    #[test]
+    fn test_replay_window_floor() {
+        let mut window = ReplayWindow::default();
+        for seq in 0..=REPLAY_WINDOW as u64 {
+            assert!(window.accept(seq));
+        }
+        assert!(!window.accept(0));
+        assert!(window.accept(REPLAY_WINDOW as u64 + 1));
+    }
+}
@@ -22,5 +22,11 @@ pub mod proto {
     pub mod remote_sys {
         include!(concat!(env!("OUT_DIR"), "/remote_sys.rs"));
     }
+    pub mod cluster {
+        include!(concat!(env!("OUT_DIR"), "/cluster.rs"));
+    }
+    pub mod fn_log {
+        include!(concat!(env!("OUT_DIR"), "/fn_log.rs"));
+    }
     include!(concat!(env!("OUT_DIR"), "/data.rs"));
 }
@@ -1,4 +1,5 @@
 use downcast_rs::{impl_downcast, Downcast};
+use std::collections::HashMap;
 
 use super::{
     m_p2p::MsgId,
@@ -64,11 +65,34 @@ define_msg_ids!(
     proto::DataVersionRequest,
     proto::DataVersionResponse,
     proto::WriteOneDataRequest,
-    proto::WriteOneDataResponse
+    proto::WriteOneDataResponse,
+    proto::sche::CallFnReq,
+    proto::sche::CallFnResp,
+    proto::WriteDataChunkRequest,
+    proto::WriteDataChunkResponse,
+    proto::DataHoldersRequest,
+    proto::DataHoldersResponse,
+    proto::PullDataRequest,
+    proto::PullDataResponse,
+    proto::DataSetVersionsRequest,
+    proto::DataSetVersionsResponse,
+    proto::cluster::ClusterMembersRequest,
+    proto::cluster::ClusterMembersResponse,
+    proto::cluster::ClusterMembersUpdate,
+    proto::cluster::DrainNodeRequest,
+    proto::cluster::DrainNodeResponse,
+    proto::cluster::LeaveClusterRequest,
+    proto::cluster::LeaveClusterResponse,
+    proto::fn_log::FnLogsReq,
+    proto::fn_log::FnLogsResp
 );
 
 pub trait RPCReq: MsgPack + Default {
     type Resp: MsgPack + Default;
+    /// where the caller's span context is carried, none if not traced
+    fn trace_ctx_mut(&mut self) -> Option<&mut HashMap<String, String>> {
+        None
+    }
 }
 
 impl RPCReq for proto::raft::VoteRequest {
@@ -81,10 +105,16 @@ impl RPCReq for proto::raft::AppendEntriesRequest {
 
 impl RPCReq for proto::sche::DistributeTaskReq {
     type Resp = proto::sche::DistributeTaskResp;
+    fn trace_ctx_mut(&mut self) -> Option<&mut HashMap<String, String>> {
+        Some(&mut self.trace_ctx)
+    }
 }
 
 impl RPCReq for proto::kv::KvRequests {
     type Resp = proto::kv::KvResponses;
+    fn trace_ctx_mut(&mut self) -> Option<&mut HashMap<String, String>> {
+        Some(&mut self.trace_ctx)
+    }
 }
 
 impl RPCReq for proto::remote_sys::GetDirContentReq {
@@ -97,12 +127,54 @@ impl RPCReq for proto::remote_sys::RunCmdReq {
 
 impl RPCReq for proto::DataVersionRequest {
     type Resp = proto::DataVersionResponse;
+    fn trace_ctx_mut(&mut self) -> Option<&mut HashMap<String, String>> {
+        Some(&mut self.trace_ctx)
+    }
 }
 
 impl RPCReq for proto::WriteOneDataRequest {
     type Resp = proto::WriteOneDataResponse;
 }
 
+impl RPCReq for proto::WriteDataChunkRequest {
+    type Resp = proto::WriteDataChunkResponse;
+}
+
+impl RPCReq for proto::DataHoldersRequest {
+    type Resp = proto::DataHoldersResponse;
+}
+
+impl RPCReq for proto::PullDataRequest {
+    type Resp = proto::PullDataResponse;
+}
+
+impl RPCReq for proto::DataSetVersionsRequest {
+    type Resp = proto::DataSetVersionsResponse;
+}
+
+impl RPCReq for proto::cluster::ClusterMembersRequest {
+    type Resp = proto::cluster::ClusterMembersResponse;
+}
+
+impl RPCReq for proto::cluster::DrainNodeRequest {
+    type Resp = proto::cluster::DrainNodeResponse;
+}
+
+impl RPCReq for proto::cluster::LeaveClusterRequest {
+    type Resp = proto::cluster::LeaveClusterResponse;
+}
+
+impl RPCReq for proto::sche::CallFnReq {
+    type Resp = proto::sche::CallFnResp;
+    fn trace_ctx_mut(&mut self) -> Option<&mut HashMap<String, String>> {
+        Some(&mut self.trace_ctx)
+    }
+}
+
+impl RPCReq for proto::fn_log::FnLogsReq {
+    type Resp = proto::fn_log::FnLogsResp;
+}
+
 pub trait KvResponseExt {
     fn new_lock(lock_id: u32) -> KvResponse;
     fn new_common(kvs: Vec<proto::kv::KvPair>) -> KvResponse;
@@ -124,13 +196,13 @@ impl KvResponseExt for KvResponse {
         }
     }
     fn lock_id(&self) -> Option<u32> {
-        match self.resp.as_ref().unwrap() {
+        match self.resp.as_ref()? {
             proto::kv::kv_response::Resp::CommonResp(_) => None,
             proto::kv::kv_response::Resp::LockId(id) => Some(*id),
         }
     }
     fn common_kvs(&self) -> Option<&Vec<proto::kv::KvPair>> {
-        match self.resp.as_ref().unwrap() {
+        match self.resp.as_ref()? {
             proto::kv::kv_response::Resp::CommonResp(resp) => Some(&resp.kvs),
             proto::kv::kv_response::Resp::LockId(_) => None,
         }
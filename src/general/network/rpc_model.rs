@@ -23,7 +23,8 @@ pub trait RpcCustom: Sized + 'static {
 
     fn bind(a: Self::SpawnArgs) -> UnixListener;
     // return true if the id matches remote call pack
-    fn handle_remote_call(conn: &HashValue, id: u8, buf: &[u8]) -> bool;
+    // taskid should be carried back by `send_resp` if the remote call needs a response
+    fn handle_remote_call(conn: &HashValue, id: u8, taskid: u32, buf: &[u8]) -> bool;
     async fn verify(buf: &[u8]) -> Option<HashValue>;
     // fn deserialize(id: u16, buf: &[u8]);
 }
@@ -116,6 +117,30 @@ pub async fn call<Req: ReqMsg>(
     }
 }
 
+/// response to a call started by the remote side, taskid is the one remote carried
+pub async fn send_resp<Resp: MsgIdBind>(resp: Resp, conn: &HashValue, taskid: u32) -> WSResult<()> {
+    let tx = {
+        let conn_map = CONN_MAP.read();
+        match conn_map.get(conn) {
+            None => {
+                return Err(WsRpcErr::ConnectionNotEstablished(conn.clone()).into());
+            }
+            Some(state) => state.tx.clone(),
+        }
+    };
+
+    let mut buf = BytesMut::with_capacity(resp.encoded_len() + 8);
+    buf.put_i32(resp.encoded_len() as i32);
+    buf.put_i32(taskid as i32);
+    resp.encode(&mut buf).unwrap();
+
+    tracing::debug!("send resp: {:?} with len: {}", resp, buf.len() - 8);
+    if tx.send(buf.into()).await.is_err() {
+        return Err(WsRpcErr::ConnectionNotEstablished(conn.clone()).into());
+    }
+    Ok(())
+}
+
 // pub enum ConnState {
 //     Connecting,
 //     Connected(tokio::sync::mpsc::Sender<()>),
@@ -257,7 +282,7 @@ pub(super) mod listen_task_ext {
                     return;
                 }
 
-                if !R::handle_remote_call(&conn, msg_id, &buf[..msg_len]) {
+                if !R::handle_remote_call(&conn, msg_id, taskid, &buf[..msg_len]) {
                     // call back
                     let Some(cb) = CALL_MAP.write().remove(&taskid) else {
                         tracing::warn!(
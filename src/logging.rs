@@ -0,0 +1,256 @@
+use crate::{
+    config::{LogRotation, NodesConfig},
+    sys::NodeID,
+};
+use axum::http::HeaderMap;
+use opentelemetry::{propagation::TextMapPropagator, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+use std::{collections::HashMap, sync::OnceLock};
+use tracing::Subscriber;
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, registry::LookupSpan, reload, util::SubscriberInitExt, EnvFilter,
+    Layer, Registry,
+};
+
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// w3c trace context headers, also the keys in the `trace_ctx` of the rpcs
+const TRACE_HEADERS: &[&str] = &["traceparent", "tracestate"];
+
+/// keep it until exit, or the buffered lines and spans are lost
+pub struct LogGuard {
+    _file: Option<WorkerGuard>,
+    otlp: bool,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        if self.otlp {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+pub fn start_tracing(config: &NodesConfig) -> LogGuard {
+    let log = &config.log;
+    let filter = EnvFilter::try_new(&log.level).unwrap_or_else(|e| {
+        panic!("invalid log level {:?}, err: {}", log.level, e);
+    });
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = FILTER_HANDLE.set(handle);
+
+    let stdout_layer = if log.json {
+        fmt::layer().json().boxed()
+    } else {
+        fmt::layer().boxed()
+    };
+
+    let mut file_guard = None;
+    let file_layer = log.file.as_ref().map(|file| {
+        let rotation = match file.rotation {
+            LogRotation::Minutely => rolling::Rotation::MINUTELY,
+            LogRotation::Hourly => rolling::Rotation::HOURLY,
+            LogRotation::Daily => rolling::Rotation::DAILY,
+            LogRotation::Never => rolling::Rotation::NEVER,
+        };
+        let prefix = file
+            .prefix
+            .clone()
+            .unwrap_or_else(|| format!("node{}.log", config.this_node()));
+        let mut builder = rolling::Builder::new()
+            .rotation(rotation)
+            .filename_prefix(prefix);
+        if let Some(max_files) = file.max_files {
+            builder = builder.max_log_files(max_files);
+        }
+        let appender = builder.build(&file.dir).unwrap_or_else(|e| {
+            panic!("create log file in {:?} failed, err: {}", file.dir, e);
+        });
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        file_guard = Some(guard);
+        if log.json {
+            fmt::layer().json().with_writer(writer).boxed()
+        } else {
+            fmt::layer().with_ansi(false).with_writer(writer).boxed()
+        }
+    });
+
+    let otlp_layer = log
+        .otlp_endpoint
+        .as_ref()
+        .map(|endpoint| otlp_layer(endpoint, config.this_node()));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stdout_layer)
+        .with(file_layer)
+        .with(otlp_layer)
+        .init();
+    LogGuard {
+        _file: file_guard,
+        otlp: log.otlp_endpoint.is_some(),
+    }
+}
+
+/// spans are sent in batches to `{endpoint}/v1/traces`
+fn otlp_layer<S>(endpoint: &str, this_node: NodeID) -> OpenTelemetryLayer<S, trace::Tracer>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(Resource::new(vec![
+            KeyValue::new("service.name", "waverless"),
+            KeyValue::new("service.instance.id", format!("node{}", this_node)),
+        ])))
+        .install_batch(runtime::Tokio)
+        .unwrap_or_else(|e| {
+            panic!("start otlp exporter to {} failed, err: {}", endpoint, e);
+        });
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
+
+/// the filter directives in use
+pub fn log_level() -> Option<String> {
+    FILTER_HANDLE.get()?.with_current(|f| f.to_string()).ok()
+}
+
+/// change the filter directives of the running node, like `info,wasm_serverless::worker=debug`
+pub fn set_log_level(directives: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    let Some(handle) = FILTER_HANDLE.get() else {
+        return Err("tracing not started".to_owned());
+    };
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    tracing::info!("log level changed to {}", directives);
+    Ok(())
+}
+
+/// carry the current span context to the remote node
+pub fn inject_trace_ctx(trace_ctx: &mut HashMap<String, String>) {
+    trace_ctx.clear();
+    let cx = tracing::Span::current().context();
+    TraceContextPropagator::new().inject_context(&cx, trace_ctx);
+}
+
+/// continue the trace of the remote caller, nothing changes if it's not traced
+pub fn set_remote_parent(span: &tracing::Span, trace_ctx: &HashMap<String, String>) {
+    if trace_ctx.is_empty() {
+        return;
+    }
+    span.set_parent(TraceContextPropagator::new().extract(trace_ctx));
+}
+
+/// a redirected client doesn't carry the headers set by us,
+/// so the context of the current span goes with the query of the url
+pub fn url_with_trace_ctx(url: &str) -> String {
+    let mut trace_ctx = HashMap::new();
+    inject_trace_ctx(&mut trace_ctx);
+    if trace_ctx.is_empty() {
+        return url.to_owned();
+    }
+    let Ok(mut url) = reqwest::Url::parse(url) else {
+        return url.to_owned();
+    };
+    for k in TRACE_HEADERS {
+        if let Some(v) = trace_ctx.get(*k) {
+            let _ = url.query_pairs_mut().append_pair(k, v);
+        }
+    }
+    url.into()
+}
+
+/// the trace context sent by a http client, in the headers or the query of a redirect
+pub fn trace_ctx_from_request(
+    headers: &HeaderMap,
+    query: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    let from_headers: HashMap<String, String> = TRACE_HEADERS
+        .iter()
+        .filter_map(|k| {
+            let v = headers.get(*k)?.to_str().ok()?;
+            Some((k.to_string(), v.to_owned()))
+        })
+        .collect();
+    if !from_headers.is_empty() {
+        return from_headers;
+    }
+    TRACE_HEADERS
+        .iter()
+        .filter_map(|k| Some((k.to_string(), query.get(*k)?.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Bytes, http::StatusCode, routing::post, Router};
+    use std::{net::TcpListener, time::Duration};
+    use tokio::sync::mpsc;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_otlp_spans_exported() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let collector = Router::new().route(
+            "/v1/traces",
+            post(move |body: Bytes| async move {
+                let _ = tx.send(body);
+                StatusCode::OK
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let _collector = tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(collector.into_make_service()),
+        );
+
+        let subscriber = Registry::default().with(otlp_layer(&endpoint, 1));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("otlp_test_span");
+            let _enter = span.enter();
+            let url = url_with_trace_ctx("http://127.0.0.1:2501/app/func");
+            assert!(url.contains("traceparent="), "{}", url);
+        });
+        // flushes the batch
+        tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider)
+            .await
+            .unwrap();
+
+        let body = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("no spans exported")
+            .unwrap();
+        assert!(body
+            .windows(b"otlp_test_span".len())
+            .any(|w| w == b"otlp_test_span"));
+    }
+
+    #[test]
+    fn test_trace_ctx_from_request() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let query = HashMap::from([("traceparent".to_owned(), traceparent.to_owned())]);
+        let ctx = trace_ctx_from_request(&HeaderMap::new(), &query);
+        assert_eq!(ctx.get("traceparent").unwrap(), traceparent);
+
+        // the headers of the client go first
+        let mut headers = HeaderMap::new();
+        let _ = headers.insert(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+                .parse()
+                .unwrap(),
+        );
+        let ctx = trace_ctx_from_request(&headers, &query);
+        assert_ne!(ctx.get("traceparent").unwrap(), traceparent);
+    }
+}
@@ -1,20 +1,24 @@
-use std::cell::RefCell;
 use std::collections::HashSet;
 use std::time::Duration;
 
-use crate::general::m_data_general::{DataGeneral, DataSetMeta};
+use crate::general::m_data_general::{send_version_resp, DataGeneral, DataSetMeta, DataVersion};
 use crate::general::m_kv_store_engine::{KeyTypeDataSetMeta, KvStoreEngine};
 use crate::general::network::m_p2p::{P2PModule, RPCHandler, RPCResponsor};
-use crate::general::network::proto::{self, DataVersionRequest, DataVersionResponse};
+use crate::general::network::proto::{
+    self, data_set_versions_response::DataSetVersion, DataHoldersRequest, DataHoldersResponse,
+    DataModeDistribute, DataSetVersionsRequest, DataSetVersionsResponse, DataVersionPhase,
+    DataVersionRequest, DataVersionResponse,
+};
 use crate::result::WSResult;
-use crate::sys::LogicalModulesRef;
+use crate::sys::{LogicalModulesRef, NodeID};
 use crate::util::JoinHandleWrapper;
 use crate::{
     general::network::http_handler::HttpHandler,
-    logical_module_view_impl,
+    logging, logical_module_view_impl,
     sys::{LogicalModule, LogicalModuleNewArgs},
 };
 use async_trait::async_trait;
+use tracing::Instrument;
 use ws_derive::LogicalModule;
 
 logical_module_view_impl!(DataMasterView);
@@ -28,6 +32,9 @@ logical_module_view_impl!(DataMasterView, kv_store_engine, KvStoreEngine);
 pub struct DataMaster {
     view: DataMasterView,
     rpc_handler: RPCHandler<proto::DataVersionRequest>,
+    rpc_handler_data_holders: RPCHandler<proto::DataHoldersRequest>,
+    rpc_handler_data_set_versions: RPCHandler<proto::DataSetVersionsRequest>,
+    require_lock: tokio::sync::Mutex<()>,
 }
 
 #[async_trait]
@@ -38,6 +45,9 @@ impl LogicalModule for DataMaster {
     {
         Self {
             rpc_handler: RPCHandler::new(),
+            rpc_handler_data_holders: RPCHandler::new(),
+            rpc_handler_data_set_versions: RPCHandler::new(),
+            require_lock: tokio::sync::Mutex::new(()),
             view: DataMasterView::new(args.logical_modules_ref.clone()),
             // view: DataMasterView::new(args.logical_modules_ref.clone()),
         }
@@ -50,11 +60,40 @@ impl LogicalModule for DataMaster {
         tracing::info!("start as master");
         let view = self.view.clone();
         self.rpc_handler
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let span = tracing::info_span!("data_version", data = %req.unique_id);
+                logging::set_remote_parent(&span, &req.trace_ctx);
+                let _ = tokio::spawn(
+                    async move {
+                        view.data_master()
+                            .rpc_handler_dataversion(responsor, req)
+                            .await
+                    }
+                    .instrument(span),
+                );
+
+                Ok(())
+            });
+        let view = self.view.clone();
+        self.rpc_handler_data_holders
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    view.data_master()
+                        .rpc_handler_data_holders(responsor, req)
+                        .await
+                });
+
+                Ok(())
+            });
+        let view = self.view.clone();
+        self.rpc_handler_data_set_versions
             .regist(self.view.p2p(), move |responsor, req| {
                 let view = view.clone();
                 let _ = tokio::spawn(async move {
                     view.data_master()
-                        .rpc_handler_dataversion(responsor, req)
+                        .rpc_handler_data_set_versions(responsor, req)
                         .await
                 });
 
@@ -70,152 +109,261 @@ impl DataMaster {
         responsor: RPCResponsor<DataVersionRequest>,
         req: DataVersionRequest,
     ) -> WSResult<()> {
+        // version bumps are serialized, so that a prepared version won't be taken by another
+        let _require_guard = self.require_lock.lock().await;
+
         // ## check version
         tracing::debug!("check version for data({})", req.unique_id);
-        let v = self
+        let old = self
             .view
             .kv_store_engine()
             .get(KeyTypeDataSetMeta(req.unique_id.as_bytes()));
-        // ##  update version local
+        let version = old.as_ref().map_or(1, |old| old.version + 1);
+        // the ones not alive catch up by reconciling when they are back
+        let peers = self.view.p2p().nodes_config.alive_peer_ids();
+
+        // ## phase 1, prepare on peers, nothing changed yet
+        let prepared = self
+            .send_version_phase(&req, version, DataVersionPhase::Prepare, peers.clone())
+            .await;
+        let (accepted, failed) = split_prepared(prepared);
+        if !failed.is_empty() {
+            tracing::warn!(
+                "prepare version({}) for data({}) failed, abort, errs: {:?}",
+                version,
+                req.unique_id,
+                failed
+            );
+            for (n, res) in self
+                .send_version_phase(&req, version, DataVersionPhase::Abort, accepted)
+                .await
+            {
+                if let Err(e) = res {
+                    tracing::warn!("abort version({}) on node {} failed: {}", version, n, e);
+                }
+            }
+            let resp = DataVersionResponse {
+                version: old.map_or(0, |old| old.version),
+                error: format!("prepare version {} failed, {}", version, failed.join(", ")),
+            };
+            send_version_resp(&responsor, resp).await;
+            return Ok(());
+        }
+
+        // ## phase 2, update version local and commit on peers
         tracing::debug!("update version local for data({})", req.unique_id);
-        let setmeta = RefCell::new(Some(DataSetMeta {
-            version: 1,
+        if let Some(old) = &old {
+            let on_demand = req.data_metas.first().map_or(false, |meta| {
+                meta.distribute != DataModeDistribute::BroadcastRough as i32
+            });
+            if req.data_metas.is_empty() {
+                self.view
+                    .data_general()
+                    .drop_deleted_data(&req.unique_id, old.data_metas.len());
+            } else if on_demand {
+                // master may not be chosen to hold the new version
+                self.view
+                    .data_general()
+                    .drop_data_items(&req.unique_id, old.data_metas.len());
+            }
+        }
+        let v = DataSetMeta {
+            version,
             data_metas: req.data_metas.iter().map(|v| v.clone().into()).collect(),
             synced_nodes: HashSet::new(),
-        }));
-        let v = v.map_or_else(
-            || setmeta.borrow_mut().take().unwrap(),
-            |set_meta| {
-                let mut replace = setmeta.borrow_mut().take().unwrap();
-                replace.version = set_meta.version + 1;
-                replace
-            },
-        );
+        };
         self.view
             .kv_store_engine()
             .set(KeyTypeDataSetMeta(req.unique_id.as_bytes()), &v);
         self.view.kv_store_engine().flush();
+        self.view.data_general().invalidate_mem_cache(
+            &req.unique_id,
+            v.data_metas.len(),
+            Some(v.version),
+        );
 
-        // update version peers
-        let mut call_tasks = vec![];
-        for p in &self.view.p2p().nodes_config.peers {
-            let view = self.view.clone();
-            let mut req = req.clone();
-            let node = *p.0;
-            req.version = v.version;
-            let call_task = tokio::spawn(async move {
-                let p2p = view.p2p();
-                tracing::debug!(
-                    "updating version for data({}) to node: {}",
+        let mut commit_failed = vec![];
+        for (n, res) in self
+            .send_version_phase(&req, version, DataVersionPhase::Commit, peers)
+            .await
+        {
+            if let Err(e) = res {
+                tracing::warn!(
+                    "commit version({}) of data({}) on node {} failed: {}",
+                    version,
                     req.unique_id,
-                    node
+                    n,
+                    e
                 );
-                (
-                    view.data_general()
-                        .rpc_call_data_version
-                        .call(p2p, node, req, Some(Duration::from_secs(60)))
-                        .await,
-                    node,
-                )
-            });
-            call_tasks.push(call_task);
+                commit_failed.push(n);
+            }
         }
-        let mut cancel = false;
-        for t in call_tasks {
-            if !cancel {
-                let (res, n) = t.await.unwrap();
-
-                match res {
-                    Err(e) => {
-                        tracing::warn!(
-                            "one node {} keep up version data failed, abort broadcast, err:{:?}",
-                            n,
-                            e
-                        );
-                        cancel = true;
-                    }
-                    Ok(ok) => {
-                        tracing::debug!(
-                            "update version for data({}) to node: {}",
-                            req.unique_id,
-                            n
-                        );
-                        if ok.version != v.version {
-                            tracing::warn!(
-                                "one node keep up failed, abort broadcast, remote version:{}, cur version:{}",
-                                ok.version,v.version
-                            );
-                            cancel = true;
-                        }
-                    }
+        // the version is taken on master, let the failed ones catch up now
+        // instead of waiting for their next periodic reconcile
+        if !commit_failed.is_empty() {
+            for (n, res) in self
+                .send_version_phase(&req, version, DataVersionPhase::Reconcile, commit_failed)
+                .await
+            {
+                if let Err(e) = res {
+                    tracing::warn!("trigger reconcile on node {} failed: {}", n, e);
                 }
-            } else {
-                t.abort();
             }
         }
-        if cancel {
-            return Ok(());
-        }
         tracing::debug!(
             "data:{} version:{} require done, followers are waiting for new data",
             req.unique_id,
             v.version
         );
-        responsor
-            .send_resp(DataVersionResponse { version: v.version })
-            .await;
+        let resp = DataVersionResponse {
+            version: v.version,
+            error: "".to_owned(),
+        };
+        send_version_resp(&responsor, resp).await;
         Ok(())
     }
+    /// send the version in `phase` to the nodes in parallel
+    async fn send_version_phase(
+        &self,
+        req: &DataVersionRequest,
+        version: DataVersion,
+        phase: DataVersionPhase,
+        nodes: Vec<NodeID>,
+    ) -> Vec<(NodeID, Result<(), String>)> {
+        let mut call_tasks = vec![];
+        for node in nodes {
+            let view = self.view.clone();
+            let mut req = req.clone();
+            req.version = version;
+            req.phase = phase as i32;
+            let call_task = tokio::spawn(
+                async move {
+                    tracing::debug!(
+                        "{:?} version({}) for data({}) to node: {}",
+                        phase,
+                        req.version,
+                        req.unique_id,
+                        node
+                    );
+                    view.data_general()
+                        .rpc_call_data_version
+                        .call(view.p2p(), node, req, Some(Duration::from_secs(30)))
+                        .await
+                }
+                .in_current_span(),
+            );
+            call_tasks.push((node, call_task));
+        }
+        let mut res = vec![];
+        for (node, t) in call_tasks {
+            let node_res = match t.await {
+                Err(e) => Err(format!("call task failed: {:?}", e)),
+                Ok(Err(e)) => Err(format!("{:?}", e)),
+                Ok(Ok(resp)) => check_phase_resp(version, resp),
+            };
+            res.push((node, node_res));
+        }
+        res
+    }
     async fn rpc_handler_dataversion_synced_on_node(
         &self,
         responsor: RPCResponsor<DataVersionRequest>,
         req: DataVersionRequest,
     ) -> WSResult<()> {
-        // 1. check version
-        let v = self
+        // 1. check version & update
+        let node = responsor.node_id();
+        let res = self
             .view
-            .kv_store_engine()
-            .get(KeyTypeDataSetMeta(req.unique_id.as_bytes()));
-        let mut v = if let Some(v) = v {
-            if v.version != req.version {
-                responsor
-                    .send_resp(DataVersionResponse { version: v.version })
-                    .await;
+            .data_general()
+            .update_data_set_meta(&req.unique_id, |v| {
+                if v.version != req.version {
+                    return Err(v.version);
+                }
+                if !v.synced_nodes.insert(node) {
+                    tracing::warn!("!!!! node repeated sync, check for bug");
+                }
+                Ok(v.version)
+            })
+            // 0 means data not found
+            .unwrap_or(Err(0));
+        let version = match res {
+            Ok(version) => version,
+            Err(version) => {
+                let resp = DataVersionResponse {
+                    version,
+                    error: "version not match".to_owned(),
+                };
+                send_version_resp(&responsor, resp).await;
                 tracing::warn!(
                     "version not match for data({}), cur: {}",
                     req.unique_id,
-                    v.version
+                    version
                 );
                 return Ok(());
             }
-            v
-        } else {
-            responsor
-                .send_resp(DataVersionResponse { version: 0 })
-                .await;
-            tracing::warn!("version not match for data({}), cur: {}", req.unique_id, 0);
-            return Ok(());
         };
-        // ## update
-        if !v.synced_nodes.insert(responsor.node_id()) {
-            tracing::warn!("!!!! node repeated sync, check for bug");
-        }
-        // write back
-        self.view
-            .kv_store_engine()
-            .set(KeyTypeDataSetMeta(req.unique_id.as_bytes()), &v);
-        self.view.kv_store_engine().flush();
         tracing::debug!(
             "synced version({}) of data({}) on node{}",
-            v.version,
+            version,
             req.unique_id,
-            responsor.node_id()
+            node
         );
-        responsor
-            .send_resp(DataVersionResponse { version: v.version })
-            .await;
+        let resp = DataVersionResponse {
+            version,
+            error: "".to_owned(),
+        };
+        send_version_resp(&responsor, resp).await;
         Ok(())
     }
+    async fn rpc_handler_data_holders(
+        &self,
+        responsor: RPCResponsor<DataHoldersRequest>,
+        req: DataHoldersRequest,
+    ) {
+        let resp = match self
+            .view
+            .kv_store_engine()
+            .get(KeyTypeDataSetMeta(req.unique_id.as_bytes()))
+        {
+            None => DataHoldersResponse {
+                version: 0,
+                nodes: vec![],
+                data_metas: vec![],
+            },
+            Some(meta) => DataHoldersResponse {
+                version: meta.version,
+                nodes: meta
+                    .synced_nodes
+                    .into_iter()
+                    .filter(|n| self.view.p2p().nodes_config.is_alive(*n))
+                    .collect(),
+                data_metas: meta.data_metas.into_iter().map(|v| v.into()).collect(),
+            },
+        };
+        if let Err(e) = responsor.send_resp(resp).await {
+            tracing::warn!("send data holders resp failed: {:?}", e);
+        }
+    }
+    async fn rpc_handler_data_set_versions(
+        &self,
+        responsor: RPCResponsor<DataSetVersionsRequest>,
+        _req: DataSetVersionsRequest,
+    ) {
+        let sets = self
+            .view
+            .kv_store_engine()
+            .scan_data_set_metas()
+            .into_iter()
+            .map(|(unique_id, meta)| DataSetVersion {
+                unique_id,
+                version: meta.version,
+                data_metas: meta.data_metas.into_iter().map(|v| v.into()).collect(),
+            })
+            .collect();
+        if let Err(e) = responsor.send_resp(DataSetVersionsResponse { sets }).await {
+            tracing::warn!("send data set versions resp failed: {:?}", e);
+        }
+    }
     async fn rpc_handler_dataversion(
         &self,
         responsor: RPCResponsor<DataVersionRequest>,
@@ -231,3 +379,66 @@ impl DataMaster {
         Ok(())
     }
 }
+
+/// the nodes accepted the prepare, to be aborted if any of the others failed, and the failures
+fn split_prepared(prepared: Vec<(NodeID, Result<(), String>)>) -> (Vec<NodeID>, Vec<String>) {
+    let mut accepted = vec![];
+    let mut failed = vec![];
+    for (n, res) in prepared {
+        match res {
+            Ok(()) => accepted.push(n),
+            Err(e) => failed.push(format!("node {}: {}", n, e)),
+        }
+    }
+    (accepted, failed)
+}
+
+/// done on the node only when it's at the version
+fn check_phase_resp(version: DataVersion, resp: DataVersionResponse) -> Result<(), String> {
+    if !resp.error.is_empty() {
+        return Err(resp.error);
+    }
+    if resp.version != version {
+        return Err(format!(
+            "remote version:{}, cur version:{}",
+            resp.version, version
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_prepared() {
+        let (accepted, failed) = split_prepared(vec![
+            (1, Ok(())),
+            (2, Err("timeout".to_owned())),
+            (3, Ok(())),
+        ]);
+        // the accepted ones are rolled back
+        assert_eq!(accepted, vec![1, 3]);
+        assert_eq!(failed, vec!["node 2: timeout".to_owned()]);
+
+        let (accepted, failed) = split_prepared(vec![(1, Ok(())), (2, Ok(()))]);
+        assert_eq!(accepted, vec![1, 2]);
+        assert!(failed.is_empty());
+    }
+
+    #[test]
+    fn test_check_phase_resp() {
+        let resp = |version, error: &str| DataVersionResponse {
+            version,
+            error: error.to_owned(),
+        };
+        assert_eq!(check_phase_resp(2, resp(2, "")), Ok(()));
+        assert_eq!(
+            check_phase_resp(2, resp(3, "expired version")),
+            Err("expired version".to_owned())
+        );
+        // a stale version on the node
+        assert!(check_phase_resp(2, resp(1, "")).is_err());
+    }
+}
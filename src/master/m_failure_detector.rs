@@ -0,0 +1,243 @@
+//! Node liveness judged by the RscMetric heartbeats reported every `metric.report_interval_ms`.
+//!
+//! A node without heartbeat for a while turns suspect and then dead, both are
+//! excluded from scheduling and data broadcasts until the heartbeat comes back.
+
+use crate::{
+    config::NodeHealth,
+    general::{m_membership::Membership, network::m_p2p::P2PModule},
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
+    util::JoinHandleWrapper,
+};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use ws_derive::LogicalModule;
+
+/// at least, and 3 or 10 report intervals when they are longer
+const SUSPECT_AFTER: Duration = Duration::from_secs(3);
+const DEAD_AFTER: Duration = Duration::from_secs(10);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct NodeHealthEvent {
+    pub node: NodeID,
+    pub from: NodeHealth,
+    pub to: NodeHealth,
+}
+
+impl NodeHealthEvent {
+    pub fn is_recovery(&self) -> bool {
+        self.to == NodeHealth::Alive
+    }
+}
+
+logical_module_view_impl!(FailureDetectorView);
+logical_module_view_impl!(FailureDetectorView, p2p, P2PModule);
+logical_module_view_impl!(FailureDetectorView, membership, Membership);
+logical_module_view_impl!(
+    FailureDetectorView,
+    failure_detector,
+    Option<FailureDetector>
+);
+
+#[derive(LogicalModule)]
+pub struct FailureDetector {
+    view: FailureDetectorView,
+    last_heartbeat: Mutex<HashMap<NodeID, Instant>>,
+    events: tokio::sync::broadcast::Sender<NodeHealthEvent>,
+}
+
+#[async_trait]
+impl LogicalModule for FailureDetector {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        let (events, _) = tokio::sync::broadcast::channel(64);
+        Self {
+            view: FailureDetectorView::new(args.logical_modules_ref.clone()),
+            last_heartbeat: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let view = self.view.clone();
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(CHECK_INTERVAL).await;
+                view.failure_detector().check(Instant::now());
+            }
+        }))])
+    }
+}
+
+impl FailureDetector {
+    /// health transitions, including the recoveries
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<NodeHealthEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn on_heartbeat(&self, node: NodeID) {
+        let _ = self.last_heartbeat.lock().insert(node, Instant::now());
+        // recover at once, no need to wait for the next check
+        if !self.view.p2p().nodes_config.is_alive(node) {
+            self.transit(node, NodeHealth::Alive);
+        }
+    }
+
+    fn check(&self, now: Instant) {
+        let nodes_config = &self.view.p2p().nodes_config;
+        let judged = judge_peers(
+            &mut self.last_heartbeat.lock(),
+            nodes_config.peer_ids(),
+            nodes_config.metric.report_interval(),
+            now,
+        );
+        for (n, health) in judged {
+            self.transit(n, health);
+        }
+    }
+
+    fn transit(&self, node: NodeID, to: NodeHealth) {
+        let from = self.view.p2p().nodes_config.set_node_health(node, to);
+        if from == to {
+            return;
+        }
+        let event = NodeHealthEvent { node, from, to };
+        if event.is_recovery() {
+            tracing::info!("node {} recovered from {:?}", node, from);
+        } else {
+            tracing::warn!("node {} turns {:?} from {:?}", node, to, from);
+        }
+        // no receiver is fine
+        let _ = self.events.send(event);
+        let view = self.view.clone();
+        let _ = tokio::spawn(async move {
+            view.membership().broadcast_members().await;
+        });
+    }
+}
+
+/// the health of the peers by their last heartbeats, the ones gone are dropped
+fn judge_peers(
+    last_heartbeat: &mut HashMap<NodeID, Instant>,
+    peers: Vec<NodeID>,
+    report_interval: Duration,
+    now: Instant,
+) -> Vec<(NodeID, NodeHealth)> {
+    let suspect_after = SUSPECT_AFTER.max(report_interval * 3);
+    let dead_after = DEAD_AFTER.max(report_interval * 10);
+    last_heartbeat.retain(|n, _| peers.contains(n));
+    peers
+        .into_iter()
+        .map(|n| {
+            // the ones never reported are given a full period since first seen
+            let elapsed = now - *last_heartbeat.entry(n).or_insert(now);
+            let health = if elapsed > dead_after {
+                NodeHealth::Dead
+            } else if elapsed > suspect_after {
+                NodeHealth::Suspect
+            } else {
+                NodeHealth::Alive
+            };
+            (n, health)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(500);
+
+    fn judge_at(
+        last_heartbeat: &mut HashMap<NodeID, Instant>,
+        now: Instant,
+    ) -> Vec<(NodeID, NodeHealth)> {
+        judge_peers(last_heartbeat, vec![1], INTERVAL, now)
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let start = Instant::now();
+        let mut last_heartbeat = HashMap::new();
+        // first seen
+        assert_eq!(
+            judge_at(&mut last_heartbeat, start),
+            vec![(1, NodeHealth::Alive)]
+        );
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(
+            judge_at(&mut last_heartbeat, at(3)),
+            vec![(1, NodeHealth::Alive)]
+        );
+        assert_eq!(
+            judge_at(&mut last_heartbeat, at(4)),
+            vec![(1, NodeHealth::Suspect)]
+        );
+        assert_eq!(
+            judge_at(&mut last_heartbeat, at(10)),
+            vec![(1, NodeHealth::Suspect)]
+        );
+        assert_eq!(
+            judge_at(&mut last_heartbeat, at(11)),
+            vec![(1, NodeHealth::Dead)]
+        );
+    }
+
+    #[test]
+    fn test_thresholds_long_interval() {
+        let start = Instant::now();
+        let mut last_heartbeat = HashMap::from([(1, start)]);
+        let interval = Duration::from_secs(2);
+        let judge = |last_heartbeat: &mut HashMap<NodeID, Instant>, secs| {
+            judge_peers(
+                last_heartbeat,
+                vec![1],
+                interval,
+                start + Duration::from_secs(secs),
+            )
+        };
+        assert_eq!(judge(&mut last_heartbeat, 6), vec![(1, NodeHealth::Alive)]);
+        assert_eq!(
+            judge(&mut last_heartbeat, 7),
+            vec![(1, NodeHealth::Suspect)]
+        );
+        assert_eq!(judge(&mut last_heartbeat, 21), vec![(1, NodeHealth::Dead)]);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let start = Instant::now();
+        let mut last_heartbeat = HashMap::from([(1, start)]);
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(
+            judge_at(&mut last_heartbeat, at(20)),
+            vec![(1, NodeHealth::Dead)]
+        );
+        // what on_heartbeat records
+        let _ = last_heartbeat.insert(1, at(20));
+        assert_eq!(
+            judge_at(&mut last_heartbeat, at(21)),
+            vec![(1, NodeHealth::Alive)]
+        );
+    }
+
+    #[test]
+    fn test_gone_peers_dropped() {
+        let now = Instant::now();
+        let mut last_heartbeat = HashMap::from([(1, now), (2, now)]);
+        assert_eq!(
+            judge_peers(&mut last_heartbeat, vec![1], INTERVAL, now),
+            vec![(1, NodeHealth::Alive)]
+        );
+        assert!(!last_heartbeat.contains_key(&2));
+    }
+}
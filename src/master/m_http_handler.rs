@@ -14,7 +14,7 @@ use crate::{
         http_handler::{self, HttpHandler},
         m_p2p::P2PModule,
     },
-    logical_module_view_impl,
+    logging, logical_module_view_impl,
     result::WSResult,
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
     util::{JoinHandleWrapper, WithBind},
@@ -100,8 +100,11 @@ impl HttpHandler for MasterHttpHandler {
         if app == "metrics" {
             return self.handle_prometheus();
         }
+        self.view.metric_observor().record_request(app);
         // 选择节点
-        let node = self.view.master().handle_http_schedule(app).await;
+        let Some(node) = self.view.master().handle_http_schedule(app).await else {
+            return (StatusCode::SERVICE_UNAVAILABLE, "no alive worker").into_response();
+        };
 
         // if self.view.p2p().nodes_config.this.0 == node {
         //     // println!("run");
@@ -121,13 +124,7 @@ impl HttpHandler for MasterHttpHandler {
         //     StatusCode::OK.into_response()
         // } else {
         // 转发
-        let target_node = self
-            .view
-            .p2p()
-            .nodes_config
-            .peers
-            .get(&(node as u32))
-            .unwrap();
+        let target_node = self.view.p2p().nodes_config.get_nodeconfig(node as u32);
 
         let url = target_node.http_url();
         let url = if url.ends_with('/') {
@@ -137,7 +134,7 @@ impl HttpHandler for MasterHttpHandler {
             // 否则，返回原URL
             &url
         };
-        let target_path = format!("{}/{}", url, app);
+        let target_path = logging::url_with_trace_ctx(&format!("{}/{}", url, app));
 
         // target_node.set_port(target_node.port() + 1);
         tracing::debug!("redirect to {}", target_path);
@@ -116,33 +116,33 @@ impl TargetNode {
 }
 
 impl Master {
-    pub fn schedule(&self, wl: ScheduleWorkload) -> TargetNode {
+    /// none if no worker is alive
+    pub fn schedule(&self, wl: ScheduleWorkload) -> Option<TargetNode> {
         match wl {
-            ScheduleWorkload::JavaAppConstruct => {
-                let workers = self.view.p2p().nodes_config.get_worker_nodes();
-                // random select one
-                let mut rng = rand::thread_rng();
-                let idx = rng.gen_range(0..workers.len());
-                TargetNode(*workers.iter().nth(idx).unwrap())
-            }
+            ScheduleWorkload::JavaAppConstruct => self.select_node().map(TargetNode),
         }
     }
-    pub async fn handle_http_schedule(&self, _app: &str) -> NodeID {
+    pub async fn handle_http_schedule(&self, _app: &str) -> Option<NodeID> {
         self.select_node()
     }
     pub async fn schedule_one_trigger(&self, app: String, func: String, trigger_data: Trigger) {
+        let Some(node) = self.select_node() else {
+            tracing::error!("schedule_one_trigger err: no alive worker");
+            return;
+        };
         match self
             .view
             .master()
             .rpc_caller_distribute_task
             .call(
                 self.view.p2p(),
-                self.select_node(),
+                node,
                 DistributeTaskReq {
                     app,
                     func,
                     task_id: 0, // TODO: Context task id for one request
                     trigger: Some(trigger_data),
+                    trace_ctx: Default::default(),
                 },
                 Duration::from_secs(60).into(),
             )
@@ -154,10 +154,14 @@ impl Master {
             }
         }
     }
-    fn select_node(&self) -> NodeID {
+    /// random one of the alive workers
+    fn select_node(&self) -> Option<NodeID> {
         let workers = self.view.p2p().nodes_config.get_worker_nodes();
+        if workers.is_empty() {
+            return None;
+        }
         let mut rng = rand::thread_rng();
         let idx = rng.gen_range(0..workers.len());
-        workers.iter().nth(idx).unwrap().clone()
+        workers.iter().nth(idx).cloned()
     }
 }
@@ -4,18 +4,25 @@ use std::{
 };
 
 use async_trait::async_trait;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json,
+};
 use parking_lot::RwLock;
+use serde::Serialize;
 use tokio::sync::Notify;
+use tracing::Instrument;
 use ws_derive::LogicalModule;
 
 use crate::{
     general::{
-        m_appmeta_manager::{
-            fn_event::{EventTriggerInfo},
-            AppMetaManager,
-        },
+        m_appmeta_manager::{fn_event::EventTriggerInfo, split_shared_kv_key, AppMetaManager},
         m_kv_store_engine::{KeyTypeKv, KeyTypeKvPosition, KvStoreEngine},
         network::{
+            http_handler::HttpHandler,
             m_p2p::{P2PModule, RPCHandler, RPCResponsor, TaskId},
             msg_pack::KvResponseExt,
             proto::{
@@ -24,8 +31,8 @@ use crate::{
             },
         },
     },
-    logical_module_view_impl,
-    result::WSResult,
+    logging, logical_module_view_impl,
+    result::{WSError, WSResult, WsFuncError},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
 };
@@ -38,6 +45,34 @@ logical_module_view_impl!(MasterKvView, appmeta_manager, AppMetaManager);
 logical_module_view_impl!(MasterKvView, master, Option<Master>);
 logical_module_view_impl!(MasterKvView, master_kv, Option<MasterKv>);
 logical_module_view_impl!(MasterKvView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(MasterKvView, http_handler, Box<dyn HttpHandler>);
+
+lazy_static::lazy_static! {
+    static ref VIEW: std::sync::OnceLock<MasterKvView> = std::sync::OnceLock::new();
+}
+
+/// the kv namespace usage of an app
+#[derive(Debug, Serialize)]
+pub struct AppKvStats {
+    pub app: String,
+    pub keys: usize,
+    pub bytes: usize,
+}
+
+/// keys are in the namespace of the calling app, except the shared `@owner/key`
+fn kv_namespace<'a>(app: &'a str, key: &'a [u8]) -> (&'a str, &'a [u8]) {
+    split_shared_kv_key(key).unwrap_or((app, key))
+}
+
+/// the lock key covering the namespace
+fn lock_key(app: &str, key: &[u8]) -> Vec<u8> {
+    let (app, key) = kv_namespace(app, key);
+    let mut lock_key = Vec::with_capacity(app.len() + 1 + key.len());
+    lock_key.extend_from_slice(app.as_bytes());
+    lock_key.push(b'/');
+    lock_key.extend_from_slice(key);
+    lock_key
+}
 
 #[derive(LogicalModule)]
 pub struct MasterKv {
@@ -65,14 +100,32 @@ impl LogicalModule for MasterKv {
             kv_ope_notify: RwLock::new(HashMap::new()),
         }
     }
+    async fn init(&self) -> WSResult<()> {
+        let _ = VIEW.get_or_init(|| self.view.clone());
+        let mut router = self.view.http_handler().building_router();
+        let take = router.option_mut().take().unwrap();
+        let take = take
+            .route(
+                "/appmgmt/kv/:app",
+                get(http_app_kv_stats).delete(http_drop_app_kv),
+            )
+            .route("/appmgmt/app/:app", delete(http_delete_app));
+        let _ = router.option_mut().replace(take);
+        Ok(())
+    }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
         let view = self.view.clone();
         self.rpc_handler
             .regist(self.view.p2p(), move |responsor, reqs| {
                 let view = view.clone();
-                let _ = tokio::spawn(async move {
-                    view.master_kv().handle_kv_requests(reqs, responsor).await;
-                });
+                let span = tracing::info_span!("kv_requests", app = %reqs.app, func = %reqs.func);
+                logging::set_remote_parent(&span, &reqs.trace_ctx);
+                let _ = tokio::spawn(
+                    async move {
+                        view.master_kv().handle_kv_requests(reqs, responsor).await;
+                    }
+                    .instrument(span),
+                );
                 Ok(())
             });
 
@@ -80,7 +133,54 @@ impl LogicalModule for MasterKv {
     }
 }
 
+async fn http_app_kv_stats(Path(app): Path<String>) -> Response {
+    Json(VIEW.get().unwrap().master_kv().app_kv_stats(&app)).into_response()
+}
+
+async fn http_drop_app_kv(Path(app): Path<String>) -> Response {
+    let dropped = VIEW.get().unwrap().master_kv().drop_app_kv(&app);
+    (StatusCode::OK, format!("dropped {} keys", dropped)).into_response()
+}
+
+/// the app and its kv go together
+async fn http_delete_app(Path(app): Path<String>) -> Response {
+    let view = VIEW.get().unwrap();
+    match view.appmeta_manager().delete_app(&app).await {
+        Ok(()) => {
+            let dropped = view.master_kv().drop_app_kv(&app);
+            (
+                StatusCode::OK,
+                format!("app deleted, dropped {} kv keys", dropped),
+            )
+                .into_response()
+        }
+        Err(WSError::WsFuncError(WsFuncError::AppNotFound { .. })) => {
+            (StatusCode::NOT_FOUND, "app not found").into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("delete app failed: {:?}", e),
+        )
+            .into_response(),
+    }
+}
+
 impl MasterKv {
+    pub fn app_kv_stats(&self, app: &str) -> AppKvStats {
+        let (keys, bytes) = self.view.kv_store_engine().app_kv_usage(app);
+        AppKvStats {
+            app: app.to_owned(),
+            keys,
+            bytes,
+        }
+    }
+    /// when the app is deleted, return the dropped key count
+    pub fn drop_app_kv(&self, app: &str) -> usize {
+        let dropped = self.view.kv_store_engine().drop_app_kv(app);
+        self.view.kv_store_engine().flush();
+        tracing::info!("dropped kv namespace of app {}, {} keys", app, dropped);
+        dropped
+    }
     // for each operation, find it's sub-trigger func
     async fn collect_event_infos(
         &self,
@@ -147,12 +247,15 @@ impl MasterKv {
             }
             kv_responses.responses.push(match req.op.unwrap() {
                 proto::kv::kv_request::Op::Set(set) => {
-                    self.handle_kv_set(set, responsor.node_id()).await
+                    self.handle_kv_set(&reqs.app, set, responsor.node_id())
+                        .await
+                }
+                proto::kv::kv_request::Op::Get(get) => self.handle_kv_get(&reqs.app, get).await,
+                proto::kv::kv_request::Op::Delete(delete) => {
+                    self.handle_kv_delete(&reqs.app, delete).await
                 }
-                proto::kv::kv_request::Op::Get(get) => self.handle_kv_get(get).await,
-                proto::kv::kv_request::Op::Delete(delete) => self.handle_kv_delete(delete).await,
                 proto::kv::kv_request::Op::Lock(lock) => {
-                    self.handle_kv_lock(lock, responsor.node_id(), responsor.task_id())
+                    self.handle_kv_lock(&reqs.app, lock, responsor.node_id(), responsor.task_id())
                         .await
                 } // notify sub tasks to run because data's persisted
             });
@@ -177,17 +280,19 @@ impl MasterKv {
     }
     async fn handle_kv_set(
         &self,
+        app: &str,
         set: proto::kv::kv_request::KvPutRequest,
         _from: NodeID,
     ) -> KvResponse {
         tracing::debug!("handle_kv_set:{:?}", set.kv.as_ref().map(|v| &v.key));
 
         if let Some(kv) = set.kv {
+            let (app, key) = kv_namespace(app, &kv.key);
             self.view
                 .kv_store_engine()
-                .set(KeyTypeKv(&kv.key), &kv.value);
+                .set(KeyTypeKv { app, key }, &kv.value);
             self.view.kv_store_engine().set(
-                KeyTypeKvPosition(&kv.key),
+                KeyTypeKvPosition { app, key },
                 &self.view.p2p().nodes_config.this_node(),
             );
 
@@ -196,14 +301,15 @@ impl MasterKv {
 
         KvResponse::new_common(vec![])
     }
-    async fn handle_kv_get(&self, get: proto::kv::kv_request::KvGetRequest) -> KvResponse {
+    async fn handle_kv_get(
+        &self,
+        app: &str,
+        get: proto::kv::kv_request::KvGetRequest,
+    ) -> KvResponse {
         tracing::debug!("handle_kv_get:{:?}", get);
         let mut kvs = vec![];
-        if let Some(v) = self
-            .view
-            .kv_store_engine()
-            .get(KeyTypeKv(&get.range.as_ref().unwrap().start))
-        {
+        let (app, key) = kv_namespace(app, &get.range.as_ref().unwrap().start);
+        if let Some(v) = self.view.kv_store_engine().get(KeyTypeKv { app, key }) {
             kvs.push(proto::kv::KvPair {
                 key: get.range.unwrap().start,
                 value: v.clone(),
@@ -211,18 +317,21 @@ impl MasterKv {
         }
         KvResponse::new_common(kvs)
     }
-    async fn handle_kv_delete(&self, delete: proto::kv::kv_request::KvDeleteRequest) -> KvResponse {
+    async fn handle_kv_delete(
+        &self,
+        app: &str,
+        delete: proto::kv::kv_request::KvDeleteRequest,
+    ) -> KvResponse {
         tracing::debug!("handle_kv_delete:{:?}", delete);
         // let res = self
         //     .kv_map
         //     .write()
         //     .remove(&delete.range.as_ref().unwrap().start);
+        let (app, key) = kv_namespace(app, &delete.range.as_ref().unwrap().start);
         self.view
             .kv_store_engine()
-            .del(KeyTypeKvPosition(&delete.range.as_ref().unwrap().start));
-        self.view
-            .kv_store_engine()
-            .del(KeyTypeKv(&delete.range.as_ref().unwrap().start));
+            .del(KeyTypeKvPosition { app, key });
+        self.view.kv_store_engine().del(KeyTypeKv { app, key });
         self.view.kv_store_engine().flush();
         // let mut kvs = vec![];
         // if let Some(v) = res {
@@ -235,11 +344,13 @@ impl MasterKv {
     }
     async fn handle_kv_lock(
         &self,
+        app: &str,
         lock: proto::kv::kv_request::KvLockRequest,
         from: NodeID,
         task: TaskId,
     ) -> KvResponse {
         tracing::debug!("handle_kv_lock:{:?}", lock);
+        let lock_key = lock_key(app, &lock.range.as_ref().unwrap().start);
         let mut notify_last = None;
         loop {
             if let Some(&release_id) = lock.release_id.get(0) {
@@ -249,16 +360,14 @@ impl MasterKv {
                 // - match verify id
                 let mut is_owner = false;
                 let mut write = self.lock_notifiers.write();
-                if let Some((nodeid, real_release_id, _)) =
-                    write.get(&lock.range.as_ref().unwrap().start)
-                {
+                if let Some((nodeid, real_release_id, _)) = write.get(&lock_key) {
                     if *nodeid == from && *real_release_id == release_id {
                         is_owner = true;
                     }
                 }
                 if is_owner {
                     tracing::debug!("unlock success");
-                    let (_, _, notify) = write.remove(&lock.range.as_ref().unwrap().start).unwrap();
+                    let (_, _, notify) = write.remove(&lock_key).unwrap();
                     notify.notify_one();
                     return KvResponse::new_common(vec![]);
                 }
@@ -274,7 +383,7 @@ impl MasterKv {
                         Arc::new(Notify::new())
                     };
                     let _ = write
-                        .entry(lock.range.as_ref().unwrap().start.clone())
+                        .entry(lock_key.clone())
                         .and_modify(|v| {
                             tracing::debug!("lock already exists");
                             notify = Some(v.2.clone());
@@ -1,5 +1,6 @@
 use crate::{
     general::network::{
+        http_handler::HttpHandler,
         m_p2p::{MsgHandler, P2PModule},
         proto,
     },
@@ -9,17 +10,65 @@ use crate::{
     util::JoinHandleWrapper,
 };
 use async_trait::async_trait;
+use axum::{
+    response::{IntoResponse, Response},
+    routing::get,
+    Json,
+};
+use crossbeam_skiplist::SkipMap;
 use prometheus_client::registry::Registry;
+use serde::Serialize;
+use std::{collections::HashMap, sync::OnceLock, time::Instant};
 use ws_derive::LogicalModule;
 
-use self::prometheus::{Metrics, RscLabels, RscType};
+use self::prometheus::{AppRscLabels, Method, Metrics, RequestLabels, RscLabels, RscType};
+use super::m_failure_detector::FailureDetector;
 
-// pub struct NodeRscMetric {
-//     used_cpu: f64,
-//     total_cpu: f64,
-//     used_memory: f64,
-//     total_memory: f64,
-// }
+/// the latest report of a node, for the scheduler and the ui
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeRscMetric {
+    pub node_id: NodeID,
+    pub reported_ms_ago: u64,
+    /// average usage percent of all the cores
+    pub cpu_used: f32,
+    /// logical cores
+    pub cpu_all: f32,
+    pub mem_used: u64,
+    pub mem_all: u64,
+    pub load_avg: [f32; 3],
+    pub disk_used: u64,
+    pub disk_all: u64,
+    pub net_rx_rate: u64,
+    pub net_tx_rate: u64,
+    pub open_fds: u32,
+    pub wasm_instances: u32,
+    pub wasm_running: u32,
+    pub process_instances: u32,
+    pub app_rss: HashMap<String, u64>,
+}
+
+impl NodeRscMetric {
+    fn new(node_id: NodeID, reported: Instant, m: &proto::metric::RscMetric) -> Self {
+        Self {
+            node_id,
+            reported_ms_ago: reported.elapsed().as_millis() as u64,
+            cpu_used: m.cpu_used,
+            cpu_all: m.cpu_all,
+            mem_used: m.mem_used as u64,
+            mem_all: m.mem_all as u64,
+            load_avg: [m.load_avg_1, m.load_avg_5, m.load_avg_15],
+            disk_used: m.disk_used,
+            disk_all: m.disk_all,
+            net_rx_rate: m.net_rx_rate,
+            net_tx_rate: m.net_tx_rate,
+            open_fds: m.open_fds,
+            wasm_instances: m.wasm_instances,
+            wasm_running: m.wasm_running,
+            process_instances: m.process_instances,
+            app_rss: m.app_rss.iter().map(|a| (a.app.clone(), a.rss)).collect(),
+        }
+    }
+}
 
 pub mod prometheus {
     use std::sync::atomic::AtomicU64;
@@ -47,6 +96,7 @@ pub mod prometheus {
     #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
     pub enum Method {
         GET,
+        POST,
     }
 
     #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
@@ -61,11 +111,29 @@ pub mod prometheus {
         MemAll,
         CpuUsed,
         MemUsed,
+        LoadAvg1,
+        LoadAvg5,
+        LoadAvg15,
+        DiskUsed,
+        DiskAll,
+        NetRxRate,
+        NetTxRate,
+        OpenFds,
+        WasmInstances,
+        WasmRunning,
+        ProcessInstances,
+    }
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+    pub struct AppRscLabels {
+        pub node_id: NodeID,
+        pub app: String,
     }
 
     pub struct Metrics {
         pub requests: Family<RequestLabels, Counter>,
         pub rscs: Family<RscLabels, Gauge<f64, AtomicU64>>,
+        pub app_rss: Family<AppRscLabels, Gauge>,
     }
 
     pub fn new_registry_and_metrics() -> (Metrics, Registry) {
@@ -73,6 +141,7 @@ pub mod prometheus {
         let metrics = Metrics {
             requests: Family::default(),
             rscs: Family::default(),
+            app_rss: Family::default(),
         };
         registry.register(
             "requests",
@@ -80,6 +149,11 @@ pub mod prometheus {
             metrics.requests.clone(),
         );
         registry.register("rscs", "Resource usage record", metrics.rscs.clone());
+        registry.register(
+            "app_rss_bytes",
+            "Resident memory of the app processes",
+            metrics.app_rss.clone(),
+        );
         (metrics, registry)
     }
 }
@@ -89,12 +163,22 @@ pub struct NodeFnCacheMetric();
 logical_module_view_impl!(MetricObservorView);
 logical_module_view_impl!(MetricObservorView, p2p, P2PModule);
 logical_module_view_impl!(MetricObservorView, metric_observor, Option<MetricObservor>);
+logical_module_view_impl!(
+    MetricObservorView,
+    failure_detector,
+    Option<FailureDetector>
+);
+logical_module_view_impl!(MetricObservorView, http_handler, Box<dyn HttpHandler>);
+
+lazy_static::lazy_static! {
+    static ref VIEW: OnceLock<MetricObservorView> = OnceLock::new();
+}
 
 #[derive(LogicalModule)]
 pub struct MetricObservor {
     pub registry: Registry,
     metrics: Metrics,
-    // node_rsc_metric: SkipMap<NodeID, proto::metric::RscMetric>,
+    node_rsc_metric: SkipMap<NodeID, (Instant, proto::metric::RscMetric)>,
     view: MetricObservorView,
     msg_handler: MsgHandler<proto::metric::RscMetric>,
 }
@@ -109,11 +193,19 @@ impl LogicalModule for MetricObservor {
         Self {
             registry,
             metrics,
-
+            node_rsc_metric: SkipMap::new(),
             view: MetricObservorView::new(args.logical_modules_ref.clone()),
             msg_handler: MsgHandler::default(),
         }
     }
+    async fn init(&self) -> WSResult<()> {
+        let _ = VIEW.get_or_init(|| self.view.clone());
+        let mut router = self.view.http_handler().building_router();
+        let take = router.option_mut().take().unwrap();
+        let take = take.route("/cluster/metrics", get(http_node_metrics));
+        let _ = router.option_mut().replace(take);
+        Ok(())
+    }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
         // self.view.p2p().regist_dispatch(m, f)
         let view = self.view.clone();
@@ -121,6 +213,7 @@ impl LogicalModule for MetricObservor {
             .regist(self.view.p2p(), move |responser, msg| {
                 let ob = view.metric_observor();
                 // tracing::info!("recv rsc metric from node {} {:?}", responser.node_id, msg);
+                view.failure_detector().on_heartbeat(responser.node_id);
                 let _ = ob.insert_node_rsc_metric(responser.node_id, msg);
                 Ok(())
             });
@@ -130,39 +223,86 @@ impl LogicalModule for MetricObservor {
 }
 
 impl MetricObservor {
-    fn insert_node_rsc_metric(&self, nid: NodeID, msg: proto::metric::RscMetric) {
-        // let _ = self.node_rsc_metric.insert(nid, msg);
-        let _ = self
-            .metrics
-            .rscs
-            .get_or_create(&RscLabels {
-                node_id: nid,
-                rsc_type: RscType::CpuAll,
-            })
-            .set(msg.cpu_all as f64);
+    /// the requests scheduled by master, the executions are exported by the workers
+    pub fn record_request(&self, path: &str) {
         let _ = self
             .metrics
-            .rscs
-            .get_or_create(&RscLabels {
-                node_id: nid,
-                rsc_type: RscType::MemAll,
+            .requests
+            .get_or_create(&RequestLabels {
+                method: Method::POST,
+                path: path.to_owned(),
             })
-            .set(msg.mem_all as f64);
-        let _ = self
-            .metrics
-            .rscs
-            .get_or_create(&RscLabels {
-                node_id: nid,
-                rsc_type: RscType::CpuUsed,
+            .inc();
+    }
+    /// none if the node never reported
+    pub fn node_rsc_metric(&self, nid: NodeID) -> Option<NodeRscMetric> {
+        let entry = self.node_rsc_metric.get(&nid)?;
+        let (reported, m) = entry.value();
+        Some(NodeRscMetric::new(nid, *reported, m))
+    }
+    /// the latest reports of the current members
+    pub fn node_rsc_metrics(&self) -> Vec<NodeRscMetric> {
+        let nodes_config = &self.view.p2p().nodes_config;
+        self.node_rsc_metric
+            .iter()
+            .filter(|e| {
+                *e.key() == nodes_config.this_node() || nodes_config.get_peer(*e.key()).is_some()
             })
-            .set(msg.cpu_used as f64);
+            .map(|e| NodeRscMetric::new(*e.key(), e.value().0, &e.value().1))
+            .collect()
+    }
+    fn set_rsc(&self, nid: NodeID, rsc_type: RscType, v: f64) {
         let _ = self
             .metrics
             .rscs
             .get_or_create(&RscLabels {
                 node_id: nid,
-                rsc_type: RscType::MemUsed,
+                rsc_type,
             })
-            .set(msg.mem_used as f64);
+            .set(v);
+    }
+    fn insert_node_rsc_metric(&self, nid: NodeID, msg: proto::metric::RscMetric) {
+        self.set_rsc(nid, RscType::CpuAll, msg.cpu_all as f64);
+        self.set_rsc(nid, RscType::MemAll, msg.mem_all as f64);
+        self.set_rsc(nid, RscType::CpuUsed, msg.cpu_used as f64);
+        self.set_rsc(nid, RscType::MemUsed, msg.mem_used as f64);
+        self.set_rsc(nid, RscType::LoadAvg1, msg.load_avg_1 as f64);
+        self.set_rsc(nid, RscType::LoadAvg5, msg.load_avg_5 as f64);
+        self.set_rsc(nid, RscType::LoadAvg15, msg.load_avg_15 as f64);
+        self.set_rsc(nid, RscType::DiskUsed, msg.disk_used as f64);
+        self.set_rsc(nid, RscType::DiskAll, msg.disk_all as f64);
+        self.set_rsc(nid, RscType::NetRxRate, msg.net_rx_rate as f64);
+        self.set_rsc(nid, RscType::NetTxRate, msg.net_tx_rate as f64);
+        self.set_rsc(nid, RscType::OpenFds, msg.open_fds as f64);
+        self.set_rsc(nid, RscType::WasmInstances, msg.wasm_instances as f64);
+        self.set_rsc(nid, RscType::WasmRunning, msg.wasm_running as f64);
+        self.set_rsc(nid, RscType::ProcessInstances, msg.process_instances as f64);
+
+        // the apps stopped since the last report are dropped
+        if let Some(prev) = self.node_rsc_metric.get(&nid) {
+            for a in &prev.value().1.app_rss {
+                if !msg.app_rss.iter().any(|n| n.app == a.app) {
+                    let _ = self.metrics.app_rss.remove(&AppRscLabels {
+                        node_id: nid,
+                        app: a.app.clone(),
+                    });
+                }
+            }
+        }
+        for a in &msg.app_rss {
+            let _ = self
+                .metrics
+                .app_rss
+                .get_or_create(&AppRscLabels {
+                    node_id: nid,
+                    app: a.app.clone(),
+                })
+                .set(a.rss as i64);
+        }
+        let _ = self.node_rsc_metric.insert(nid, (Instant::now(), msg));
     }
 }
+
+async fn http_node_metrics() -> Response {
+    Json(VIEW.get().unwrap().metric_observor().node_rsc_metrics()).into_response()
+}
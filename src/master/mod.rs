@@ -1,4 +1,5 @@
 pub mod m_data_master;
+pub mod m_failure_detector;
 pub mod m_http_handler;
 pub mod m_master;
 pub mod m_master_kv;
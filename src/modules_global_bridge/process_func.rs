@@ -1,6 +1,9 @@
 use crate::{
     general::m_appmeta_manager::AppMetaManager,
-    worker::func::{m_instance_manager::InstanceManager, shared::process_rpc::ProcessRpc},
+    worker::{
+        func::{m_instance_manager::InstanceManager, shared::process_rpc::ProcessRpc},
+        m_executor::Executor,
+    },
 };
 
 pub trait ModulesGlobalBrigeInstanceManager: Sized + 'static {
@@ -22,3 +25,13 @@ impl ModulesGlobalBrigeAppMetaManager for ProcessRpc {
         &super::modules().appmeta_manager
     }
 }
+
+pub trait ModulesGlobalBrigeExecutor: Sized + 'static {
+    unsafe fn global_m_executor() -> Option<&'static Executor>;
+}
+
+impl ModulesGlobalBrigeExecutor for ProcessRpc {
+    unsafe fn global_m_executor() -> Option<&'static Executor> {
+        super::modules().executor.as_ref()
+    }
+}
@@ -9,7 +9,10 @@ use wasmedge_sdk::error::WasmEdgeError;
 use zip_extract::ZipExtractError;
 
 use crate::{
-    general::{m_appmeta_manager::FnMeta, network::rpc_model::HashValue},
+    general::{
+        m_appmeta_manager::{limits::Throttled, FnMeta},
+        network::rpc_model::HashValue,
+    },
     sys::NodeID,
     util::TryUtf8VecU8,
 };
@@ -48,6 +51,8 @@ pub enum WsRpcErr {
 #[derive(Debug)]
 pub enum WsIoErr {
     Io(std::io::Error),
+    /// not opened by the invocation, or closed already
+    FdNotOpened(i32),
     Io2(walkdir::Error),
     Zip(ZipExtractError),
     Zip2(zip::result::ZipError),
@@ -107,6 +112,18 @@ pub enum WsPermissionErr {
         func: String,
         access_key: TryUtf8VecU8,
     },
+    RemoteSysDisabled,
+    RemoteCmdNotAllowed {
+        cmd: String,
+    },
+    RemotePathNotAllowed {
+        path: String,
+    },
+    /// escapes the sandbox dir of the app
+    FnPathNotAllowed {
+        app: String,
+        path: String,
+    },
 }
 
 #[derive(Debug)]
@@ -127,6 +144,15 @@ pub enum WsFuncError {
     FuncBackendHttpNotSupported {
         fname: String,
     },
+    FuncRpcNotSupported {
+        app: String,
+        func: String,
+    },
+    FuncRemoteCallFail {
+        app: String,
+        func: String,
+        err: String,
+    },
     FuncHttpFail {
         app: String,
         func: String,
@@ -149,6 +175,24 @@ pub enum WsFuncError {
     },
     InstanceJavaPidNotFound(String),
     InstanceProcessStartFailed(std::io::Error),
+    WasmAbiMismatch {
+        app: String,
+        host: i32,
+        guest: i32,
+    },
+    /// over the limits in app.yaml
+    FuncThrottled {
+        app: String,
+        func: String,
+        throttled: Throttled,
+    },
+    /// the guest failed to take the args of the event
+    WasmPrepareArgFailed {
+        app: String,
+        detail: String,
+    },
+    /// the node doesn't run functions, or not yet
+    NoExecutor,
 }
 
 #[derive(Debug)]
@@ -158,6 +202,45 @@ pub enum WsDataError {
         cur_version: u64,
         data_id: String,
     },
+    WriteDataChunkFailed {
+        unique_id: String,
+        idx: u32,
+        node: NodeID,
+        message: String,
+    },
+    ChunkedDataInvalid {
+        unique_id: String,
+        idx: u32,
+        reason: String,
+    },
+    DataNotHeld {
+        unique_id: String,
+        version: u64,
+    },
+    DataHolderNotFound {
+        unique_id: String,
+    },
+    PullDataFailed {
+        unique_id: String,
+        node: NodeID,
+        message: String,
+    },
+    DeleteDataFailed {
+        unique_id: String,
+        message: String,
+    },
+    /// keys of the kv shared by all the apps before the per app namespaces
+    FlatKvKeysLeft {
+        count: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum WsClusterErr {
+    NodeNotMember(NodeID),
+    DecommissionMaster(NodeID),
+    DrainNodeFailed { node: NodeID, message: String },
+    LeaveClusterFailed { message: String },
 }
 
 #[derive(Error, Debug)]
@@ -192,6 +275,9 @@ pub enum WSError {
     #[error("Data error: {0:?}")]
     WsDataError(WsDataError),
 
+    #[error("Cluster error: {0:?}")]
+    WsClusterErr(WsClusterErr),
+
     #[error("Not Implemented")]
     NotImplemented,
 }
@@ -256,6 +342,12 @@ impl From<WsDataError> for WSError {
     }
 }
 
+impl From<WsClusterErr> for WSError {
+    fn from(value: WsClusterErr) -> Self {
+        WSError::WsClusterErr(value)
+    }
+}
+
 pub struct ErrCvt<T>(pub T);
 
 macro_rules! impl_err_convertor {
@@ -3,20 +3,23 @@ use crate::{
     general::{
         m_appmeta_manager::AppMetaManager,
         m_data_general::DataGeneral,
+        m_fn_logs::FnLogs,
         m_kv_store_engine::KvStoreEngine,
+        m_membership::Membership,
         m_metric_publisher::MetricPublisher,
         m_os::OperatingSystem,
         network::{http_handler::HttpHandlerDispatch, m_p2p::P2PModule},
     },
     master::{
-        m_data_master::DataMaster, m_master::Master, m_master_kv::MasterKv,
-        m_metric_observor::MetricObservor,
+        m_data_master::DataMaster, m_failure_detector::FailureDetector, m_master::Master,
+        m_master_kv::MasterKv, m_metric_observor::MetricObservor,
     },
     modules_global_bridge, util,
     worker::{
         func::{m_instance_manager::InstanceManager, wasm_host_funcs},
         m_data_follower::DataFollower,
         m_executor::Executor,
+        m_fn_metrics::FnMetrics,
         m_kv_user_client::KvUserClient,
         m_worker::WorkerCore,
     },
@@ -296,6 +299,10 @@ start_modules!(
         AppMetaManager,
         data_general,
         DataGeneral,
+        membership,
+        Membership,
+        fn_logs,
+        FnLogs,
         http_handler,
         HttpHandlerDispatch
     ],
@@ -309,7 +316,9 @@ start_modules!(
         master_kv,
         MasterKv,
         data_master,
-        DataMaster
+        DataMaster,
+        failure_detector,
+        FailureDetector
     ],
     [
         worker,
@@ -324,6 +333,8 @@ start_modules!(
         // KvStorage,
         executor,
         Executor,
+        fn_metrics,
+        FnMetrics,
         data_follower,
         DataFollower
     ]
@@ -1,8 +1,11 @@
+use super::shared::process::PID;
 use super::shared::process_rpc::ProcessRpc;
 use super::{owned::wasm, shared::SharedInstance, FnExeCtx, Instance, OwnedInstance};
+use crate::general::m_fn_logs::FnLogs;
 use crate::general::m_os::OperatingSystem;
 use crate::general::network::rpc_model;
 use crate::sys::LogicalModulesRef;
+use crate::worker::m_fn_metrics::StartType;
 use crate::{
     general::m_appmeta_manager::AppType, // worker::host_funcs,
     result::WSResult,
@@ -130,7 +133,11 @@ impl OwnedEachAppCache {
             getting: Notify::new(),
         }
     }
-    pub async fn get(&self, file_dir: impl AsRef<Path>, instance_name: &str) -> OwnedInstance {
+    pub async fn get(
+        &self,
+        file_dir: impl AsRef<Path>,
+        instance_name: &str,
+    ) -> (OwnedInstance, StartType) {
         loop {
             let using = self.getting.notified();
 
@@ -144,15 +151,19 @@ impl OwnedEachAppCache {
 
         if let Some(a) = self.cache.iter().next() {
             if let Some(a) = self.cache.remove(&*a.0) {
-                return unsafe { util::non_null(&*a.0).as_mut().take().unwrap() };
+                return (
+                    unsafe { util::non_null(&*a.0).as_mut().take().unwrap() },
+                    StartType::Warm,
+                );
             }
         }
 
-        wasm::new_wasm_instance(
+        let instance = wasm::new_wasm_instance(
             file_dir,
             instance_name,
             self.next_instance_id.fetch_add(1, Ordering::Relaxed),
-        )
+        );
+        (instance, StartType::Cold)
     }
     pub fn put(&self, value: OwnedInstance) {
         self.cache.insert(
@@ -200,6 +211,7 @@ pub struct InstanceManager {
 
 logical_module_view_impl!(InstanceManagerView);
 logical_module_view_impl!(InstanceManagerView, os, OperatingSystem);
+logical_module_view_impl!(InstanceManagerView, fn_logs, FnLogs);
 
 pub struct UnsafeFunctionCtx(pub NonNull<FnExeCtx>);
 
@@ -256,20 +268,67 @@ impl InstanceManager {
             Instance::Shared(v) => drop(v),
         }
     }
-    pub async fn load_instance(&self, app_type: &AppType, instance_name: &str) -> Instance {
+    /// also tells whether the instance was reused or newly started
+    pub async fn load_instance(
+        &self,
+        app_type: &AppType,
+        instance_name: &str,
+    ) -> (Instance, StartType) {
         match &app_type {
-            AppType::Jar => self.get_process_instance(app_type, instance_name).into(),
-            AppType::Wasm => self
-                .app_instances
-                .get_or_insert(instance_name.to_owned(), OwnedEachAppCache::new().into())
-                .value()
-                .as_owned()
-                .expect("wasm is supposed to be owned, just inserted in prev line")
-                .get(&self.file_dir, instance_name)
-                .await
-                .into(),
+            AppType::Jar => {
+                let (instance, start) = self.get_process_instance(app_type, instance_name);
+                (instance.into(), start)
+            }
+            AppType::Wasm => {
+                let (instance, start) = self
+                    .app_instances
+                    .get_or_insert(instance_name.to_owned(), OwnedEachAppCache::new().into())
+                    .value()
+                    .as_owned()
+                    .expect("wasm is supposed to be owned, just inserted in prev line")
+                    .get(&self.file_dir, instance_name)
+                    .await;
+                (instance.into(), start)
+            }
         }
     }
+    /// whether the app has instances cached or a process running here
+    pub fn is_warm(&self, app: &str) -> bool {
+        let Some(entry) = self.app_instances.get(app) else {
+            return false;
+        };
+        match entry.value() {
+            EachAppCache::Owned(owned) => {
+                owned.cache.entry_count() > 0 || owned.using.load(Ordering::Relaxed) > 0
+            }
+            EachAppCache::Shared(_) => true,
+        }
+    }
+    /// wasm instances cached or running, the running ones, and the app processes
+    pub fn instance_counts(&self) -> (usize, usize, usize) {
+        let (mut wasm, mut running, mut processes) = (0, 0, 0);
+        for entry in self.app_instances.iter() {
+            match entry.value() {
+                EachAppCache::Owned(owned) => {
+                    let using = owned.using.load(Ordering::Relaxed) as usize;
+                    wasm += owned.cache.entry_count() as usize + using;
+                    running += using;
+                }
+                EachAppCache::Shared(_) => processes += 1,
+            }
+        }
+        (wasm, running, processes)
+    }
+    /// pids of the app processes started
+    pub fn app_pids(&self) -> Vec<(String, PID)> {
+        self.app_instances
+            .iter()
+            .filter_map(|entry| {
+                let pid = entry.value().as_shared()?.0.pid()?;
+                Some((entry.key().clone(), pid))
+            })
+            .collect()
+    }
     pub async fn drap_app_instances(&self, app: &str) {
         let _inss = self.app_instances.remove(app);
         // if let Some(inss) = inss {
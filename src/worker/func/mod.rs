@@ -12,6 +12,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use enum_as_inner::EnumAsInner;
+use std::time::{Duration, Instant};
 use tokio::task::JoinHandle;
 
 use self::{
@@ -73,6 +74,8 @@ pub trait InstanceTrait {
 #[derive(Clone, Debug)]
 pub enum EventCtx {
     Http(String),
+    /// called by another function
+    Rpc(String),
     KvSet { key: Vec<u8>, opeid: Option<u32> },
 }
 
@@ -95,20 +98,27 @@ pub struct FnExeCtx {
     pub res: Option<String>,
     /// remote scheduling tasks
     pub sub_waiters: Vec<JoinHandle<()>>, // pub trigger_node: NodeID,
+    pub arrive_time: Instant,
+    /// spent in kv operations
+    pub kv_time: Duration,
+    /// of the execution, the remote calls are traced under it
+    pub span: tracing::Span,
+    /// opened by the function, the ones left are closed when the invocation ends
+    pub fds: Vec<i32>,
 }
 
 impl FnExeCtx {
     pub fn empty_http(&self) -> bool {
         match &self.event_ctx {
-            EventCtx::Http(str) => str.len() == 0,
+            EventCtx::Http(str) | EventCtx::Rpc(str) => str.len() == 0,
             _ => false,
         }
     }
-    /// call this when you are sure it's a http event
+    /// call this when you are sure it's a http or rpc event
     pub fn http_str_unwrap(&self) -> String {
         match &self.event_ctx {
-            EventCtx::Http(str) => str.to_owned(),
-            _ => panic!("not a http event"),
+            EventCtx::Http(str) | EventCtx::Rpc(str) => str.to_owned(),
+            _ => panic!("not a http or rpc event"),
         }
     }
 }
@@ -4,7 +4,7 @@ use crate::{
 };
 use async_trait::async_trait;
 
-use std::{mem::ManuallyDrop, path::Path};
+use std::path::Path;
 use wasmedge_sdk::{
     config::{CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions},
     r#async::AsyncState,
@@ -16,47 +16,48 @@ use wasmedge_sdk::{Vm, WasmValue};
 pub type WasmInstance = Vm;
 
 impl EventCtx {
-    pub fn conv_to_wasm_params(&self, vm: &WasmInstance) -> Vec<WasmValue> {
-        fn prepare_vec_in_vm(vm: &WasmInstance, v: &[u8]) -> (i32, i32) {
+    /// the args are copied into the memory allocated by the `allocate` export of the guest
+    pub fn conv_to_wasm_params(&self, vm: &WasmInstance, app: &str) -> WSResult<Vec<WasmValue>> {
+        fn prepare_vec_in_vm(vm: &WasmInstance, app: &str, v: &[u8]) -> WSResult<(i32, i32)> {
+            let failed = |detail: String| WsFuncError::WasmPrepareArgFailed {
+                app: app.to_owned(),
+                detail,
+            };
+            let len = i32::try_from(v.len())
+                .map_err(|_| failed(format!("arg of {} bytes is too large", v.len())))?;
             let vm_ins = vm.instance_name();
             let ptr = vm
-                .run_func(
-                    Some(&vm_ins),
-                    "allocate",
-                    vec![WasmValue::from_i32(v.len() as i32)],
-                )
-                .unwrap_or_else(|err| {
-                    panic!("err:{:?} vm instance names:{:?}", err, vm.instance_names())
-                })[0]
-                .to_i32();
-            let mut mem = ManuallyDrop::new(unsafe {
-                Vec::from_raw_parts(
-                    vm.named_module(&vm_ins)
-                        .unwrap()
-                        .memory("memory")
-                        .unwrap()
-                        .data_pointer_mut(ptr as u32, v.len() as u32)
-                        .unwrap(),
-                    v.len(),
-                    v.len(),
-                )
-            });
-            mem.copy_from_slice(v);
-            (ptr, v.len() as i32)
+                .run_func(Some(&vm_ins), "allocate", vec![WasmValue::from_i32(len)])
+                .map_err(|err| failed(format!("call allocate failed: {}", err)))?
+                .first()
+                .map(|v| v.to_i32())
+                .ok_or_else(|| failed("allocate returned nothing".to_owned()))?;
+            let Ok(offset) = u32::try_from(ptr) else {
+                return Err(failed(format!("allocate returned a bad ptr {}", ptr)).into());
+            };
+            if !v.is_empty() {
+                let mut mem = vm
+                    .named_module(&vm_ins)
+                    .and_then(|m| m.memory("memory"))
+                    .map_err(|err| failed(format!("no memory export: {}", err)))?;
+                mem.write(v, offset).map_err(|err| {
+                    failed(format!("write {} bytes at {} failed: {}", len, offset, err))
+                })?;
+            }
+            Ok((ptr, len))
         }
         match self {
-            EventCtx::Http(text) => {
+            EventCtx::Http(text) | EventCtx::Rpc(text) => {
                 // if text.len() == 0 {
                 //     return vec![];
                 // }
-                let (ptr, len) = prepare_vec_in_vm(vm, text.as_bytes());
-                vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)]
+                let (ptr, len) = prepare_vec_in_vm(vm, app, text.as_bytes())?;
+                Ok(vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)])
+            }
+            EventCtx::KvSet { key, .. } => {
+                let (ptr, len) = prepare_vec_in_vm(vm, app, key)?;
+                Ok(vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)])
             }
-            // (EventCtx::KvSet { key, .. }) => {
-            //     let (ptr, len) = prepare_vec_in_vm(vm, &key);
-            //     vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)]
-            // }
-            e => panic!("not support event ctx and fn arg: {:?}", e),
         }
     }
 }
@@ -78,9 +79,12 @@ impl InstanceTrait for WasmInstance {
             if self.active_module().is_err() {
                 instance_name_for_first_time_init = Some(self.instance_name())
             }
+            if let Some(name) = &instance_name_for_first_time_init {
+                check_abi_version(self, name, &fn_ctx.app)?;
+            }
 
             // retry loop
-            let mut params = fn_ctx.event_ctx.conv_to_wasm_params(&self);
+            let mut params = fn_ctx.event_ctx.conv_to_wasm_params(&self, &fn_ctx.app)?;
             for turn in 0..2 {
                 let func = fn_ctx.func.clone();
                 let Err(err) = self
@@ -107,7 +111,11 @@ impl InstanceTrait for WasmInstance {
                     }
                 }
 
-                if turn == 0 && fn_ctx.empty_http() && is_func_type_mismatch(&err) {
+                // handlers taking no args, the kv key is optional as well
+                if turn == 0
+                    && (fn_ctx.empty_http() || matches!(fn_ctx.event_ctx, EventCtx::KvSet { .. }))
+                    && is_func_type_mismatch(&err)
+                {
                     fn_ctx.res = None;
                     continue;
                 } else {
@@ -126,6 +134,35 @@ impl InstanceTrait for WasmInstance {
     }
 }
 
+/// guests without the export predate the sdk and are not checked
+fn check_abi_version(vm: &WasmInstance, instance_name: &str, app: &str) -> WSResult<()> {
+    let has_export = vm
+        .named_module(instance_name)
+        .map_or(false, |m| m.func("ws_abi_version").is_ok());
+    if !has_export {
+        return Ok(());
+    }
+    let guest = vm
+        .run_func(Some(instance_name), "ws_abi_version", vec![])
+        .map_err(|err| WsFuncError::WasmError(*err))?[0]
+        .to_i32();
+    if guest != wasm_host_funcs::WASM_ABI_VERSION {
+        tracing::error!(
+            "app {} is built for wasm abi {}, host is {}",
+            app,
+            guest,
+            wasm_host_funcs::WASM_ABI_VERSION
+        );
+        return Err(WsFuncError::WasmAbiMismatch {
+            app: app.to_owned(),
+            host: wasm_host_funcs::WASM_ABI_VERSION,
+            guest,
+        }
+        .into());
+    }
+    Ok(())
+}
+
 // pub fn new_java_instance(_config: NewJavaInstanceConfig) -> ProcessInstance {}
 
 pub fn new_wasm_instance(
@@ -134,7 +171,7 @@ pub fn new_wasm_instance(
     id: u64,
 ) -> OwnedInstance {
     let config = ConfigBuilder::new(CommonConfigOptions::default())
-        .with_host_registration_config(HostRegistrationConfigOptions::default().wasi(true))
+        .with_host_registration_config(HostRegistrationConfigOptions::default().wasi(false))
         .build()
         .expect("failed to create config");
     let module = Module::from_file(
@@ -151,6 +188,9 @@ pub fn new_wasm_instance(
         .build()
         .unwrap_or_else(|err| panic!("failed to create vm: {:?}", err));
     let vm = vm.register_import_module(import).unwrap();
+    let vm = vm
+        .register_import_module(wasm_host_funcs::new_wasi_import_obj())
+        .unwrap();
     let vm = vm
         .register_module(Some(&format!("{}{}", instance_name, id)), module)
         .unwrap();
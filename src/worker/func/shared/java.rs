@@ -3,15 +3,23 @@ use std::str::from_utf8;
 use tokio::process::{self, Command};
 
 use crate::{
-    general::m_os::{OperatingSystem, OsProcessType},
+    general::{
+        m_fn_logs::{FnLogStream, FnLogs},
+        m_os::{OperatingSystem, OsProcessType},
+    },
     result::{WSError, WSResult, WsFuncError},
 };
 
 use super::process::PID;
 
-pub(super) fn cold_start(app: &str, os: &OperatingSystem) -> WSResult<process::Child> {
+pub(super) fn cold_start(
+    app: &str,
+    os: &OperatingSystem,
+    logs: &FnLogs,
+) -> WSResult<process::Child> {
     tracing::debug!("java cold start {}", app);
-    let p = os.start_process(OsProcessType::JavaApp(app.to_owned()));
+    let mut p = os.start_process(OsProcessType::JavaApp(app.to_owned()));
+    capture_output(app, &mut p, logs);
     // .filter(|x| x.starts_with(app))
     // .next()
     // .expect("no pid found")
@@ -49,11 +57,19 @@ pub(super) async fn find_pid(app: &str) -> WSResult<PID> {
     Ok(pid)
 }
 
-pub(super) async fn take_snapshot(app: &str, os: &OperatingSystem) {
-    let res = os
-        .start_process(OsProcessType::JavaCheckpoints(app.to_owned()))
-        .wait()
-        .await
-        .unwrap();
+pub(super) async fn take_snapshot(app: &str, os: &OperatingSystem, logs: &FnLogs) {
+    let mut p = os.start_process(OsProcessType::JavaCheckpoints(app.to_owned()));
+    capture_output(app, &mut p, logs);
+    let res = p.wait().await.unwrap();
     assert!(res.success());
 }
+
+/// the output goes to the fn logs of the app, not tied to any invocation
+fn capture_output(app: &str, p: &mut process::Child, logs: &FnLogs) {
+    if let Some(stdout) = p.stdout.take() {
+        logs.capture_process_output(app, FnLogStream::Stdout, stdout);
+    }
+    if let Some(stderr) = p.stderr.take() {
+        logs.capture_process_output(app, FnLogStream::Stderr, stderr);
+    }
+}
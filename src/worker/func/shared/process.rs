@@ -11,10 +11,11 @@ use tokio::{process::Command, sync::oneshot};
 
 use crate::{
     general::{
-        m_appmeta_manager::{AppType},
+        m_appmeta_manager::{AppType, HttpMethod},
         network::rpc_model::{self, HashValue},
     },
-    worker::func::{shared::java, InstanceTrait},
+    result::{WSResult, WsFuncError},
+    worker::func::{shared::java, EventCtx, FnExeCtx, InstanceTrait},
 };
 
 use super::process_rpc::{self, proc_proto};
@@ -63,6 +64,12 @@ impl ProcessInstance {
     // pub fn checked_pid(&self) -> Option<PID> {
     //     self.state.0.read().1.as_ref().map(|v| v.1)
     // }
+    /// the one reported by the app when verified, or the started child
+    pub fn pid(&self) -> Option<PID> {
+        let state = self.state.0.read();
+        let (child, checked) = state.1.as_ref()?;
+        checked.or_else(|| child.id())
+    }
     pub async fn kill(&self) {
         let takeprocess = self.state.0.write().1.take();
         if let Some((mut p, id)) = takeprocess {
@@ -179,6 +186,34 @@ impl ProcessInstance {
         // drop(wating_verify);
         // let _ = rx.await;
     }
+    /// reverse proxy the http request to the app's own http port
+    async fn call_direct_http(
+        &self,
+        fn_ctx: &FnExeCtx,
+        http_port: &str,
+        httpmethod: HttpMethod,
+    ) -> WSResult<String> {
+        let http_url = format!("http://127.0.0.1:{}/{}", http_port, fn_ctx.func);
+        tracing::debug!("direct call app {} by {}", self.app, http_url);
+        let res = match httpmethod {
+            HttpMethod::Get => reqwest::get(http_url).await,
+            HttpMethod::Post => {
+                reqwest::Client::new()
+                    .post(http_url)
+                    .header("content-type", "application/json")
+                    .body(fn_ctx.http_str_unwrap())
+                    .send()
+                    .await
+            }
+        };
+        let http_err = |e| WsFuncError::FuncHttpFail {
+            app: fn_ctx.app.clone(),
+            func: fn_ctx.func.clone(),
+            http_err: e,
+        };
+        let ok = res.map_err(http_err)?;
+        Ok(ok.text().await.map_err(http_err)?)
+    }
     pub fn before_checkpoint(&self) {
         // state to starting
 
@@ -207,64 +242,28 @@ impl InstanceTrait for ProcessInstance {
         &self,
         fn_ctx: &mut crate::worker::func::FnExeCtx,
     ) -> crate::result::WSResult<Option<String>> {
-        // if rpc_model::start_remote_once(rpc_model::HashValue::Str(fn_ctx.func.to_owned())) {
-        //     // cold start the java process
-        // }
+        let fnverify = self.wait_for_verify().await;
 
-        // if fn_ctx.func_meta.allow_rpc_call()
+        // `call: direct` means the app serves http itself, we just forward the request
+        if let (EventCtx::Http(_), Some(httpmethod)) =
+            (&fn_ctx.event_ctx, fn_ctx.func_meta.direct_http_call())
         {
-            let _ = self.wait_for_verify().await;
-            return process_rpc::call_func(&fn_ctx.app, &fn_ctx.func, fn_ctx.http_str_unwrap())
+            let Some(http_port) = &fnverify.http_port else {
+                return Err(WsFuncError::FuncBackendHttpNotSupported {
+                    fname: fn_ctx.func.to_owned(),
+                }
+                .into());
+            };
+            return self
+                .call_direct_http(fn_ctx, http_port, httpmethod)
                 .await
-                .map(|v| Some(v.ret_str));
+                .map(|ok| Some(ok));
         }
 
-        // if let Some(httpmethod) = fn_ctx.func_meta.allow_http_call() {
-        //     let fnverify = self.wait_for_verify().await;
-        //     let Some(http_port) = &fnverify.http_port else {
-        //         return Err(WsFuncError::FuncBackendHttpNotSupported {
-        //             fname: fn_ctx.func.to_owned(),
-        //         }
-        //         .into());
-        //     };
-        //     let http_url = format!("http://127.0.0.1:{}/{}", http_port, fn_ctx.func);
-        //     let res = match httpmethod {
-        //         HttpMethod::Get => reqwest::get(http_url).await,
-        //         HttpMethod::Post => {
-        //             reqwest::Client::new()
-        //                 .post(http_url)
-        //                 .body(fn_ctx.http_str_unwrap())
-        //                 .send()
-        //                 .await
-        //         }
-        //     };
-
-        //     let ok = match res {
-        //         Err(e) => {
-        //             return Err(WsFuncError::FuncHttpFail {
-        //                 app: fn_ctx.app.clone(),
-        //                 func: fn_ctx.func.clone(),
-        //                 http_err: e,
-        //             }
-        //             .into());
-        //         }
-        //         Ok(ok) => ok,
-        //     };
-
-        //     return ok
-        //         .text()
-        //         .await
-        //         .map_err(|e| {
-        //             WsFuncError::FuncHttpFail {
-        //                 app: fn_ctx.app.clone(),
-        //                 func: fn_ctx.func.clone(),
-        //                 http_err: e,
-        //             }
-        //             .into()
-        //         })
-        //         .map(|ok| Some(ok));
-        // }
-        // unreachable!("Missing call description in func meta");
+        // `call: indirect` or other triggers, call by the agent through uds
+        process_rpc::call_func(&fn_ctx.app, &fn_ctx.func, fn_ctx.http_str_unwrap())
+            .await
+            .map(|v| Some(v.ret_str))
     }
 }
 
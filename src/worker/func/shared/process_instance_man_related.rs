@@ -9,6 +9,7 @@ use crate::{
         m_instance_manager::{EachAppCache, InstanceManager},
         shared::java,
     },
+    worker::m_fn_metrics::StartType,
 };
 
 use super::{process::ProcessInstance, SharedInstance};
@@ -48,7 +49,9 @@ impl InstanceManager {
         {
             tracing::debug!("taking snapshot for app: {}", app_name);
             match proc_ins.app_type {
-                AppType::Jar => java::take_snapshot(app_name, self.view.os()).await,
+                AppType::Jar => {
+                    java::take_snapshot(app_name, self.view.os(), self.view.fn_logs()).await
+                }
                 AppType::Wasm => unreachable!(),
             }
         }
@@ -56,7 +59,7 @@ impl InstanceManager {
         tokio::time::sleep(Duration::from_secs(3)).await;
 
         tracing::debug!("restart app after snapshot: {}", app_name);
-        let res = java::cold_start(app_name, self.view.os());
+        let res = java::cold_start(app_name, self.view.os(), self.view.fn_logs());
         let p = match res {
             Err(e) => {
                 tracing::warn!("cold start failed: {:?}", e);
@@ -82,7 +85,7 @@ impl InstanceManager {
 
     pub async fn make_checkpoint_for_app(&self, app: &str) -> WSResult<()> {
         tracing::debug!("make checkpoint for app: {}", app);
-        let p = self.get_process_instance(&AppType::Jar, app);
+        let (p, _) = self.get_process_instance(&AppType::Jar, app);
         let _ = p.wait_for_verify().await;
         tokio::time::sleep(Duration::from_secs(3)).await;
 
@@ -94,11 +97,21 @@ impl InstanceManager {
     /// We call it when we alreay know it's a process
     ///
     /// So panics will happen if the previous logic is wrong
-    pub fn get_process_instance(&self, app_type: &AppType, app: &str) -> ProcessInstance {
+    pub fn get_process_instance(
+        &self,
+        app_type: &AppType,
+        app: &str,
+    ) -> (ProcessInstance, StartType) {
+        let mut start = StartType::Warm;
         let instance = self.app_instances.get_or_insert_with(app.to_owned(), || {
             // Cold start
             match app_type {
                 AppType::Jar => {
+                    start = if self.view.os().java_has_checkpoint(app) {
+                        StartType::CracRestore
+                    } else {
+                        StartType::Cold
+                    };
                     let instance = ProcessInstance::new(app.to_owned(), AppType::Jar);
                     let _ = self.app_instances.insert(
                         app.to_owned(),
@@ -111,7 +124,8 @@ impl InstanceManager {
                         // let app = app.to_owned();
                         // let instance = instance.clone();
 
-                        let p = java::cold_start(&app, self.view.os()).unwrap();
+                        let p =
+                            java::cold_start(&app, self.view.os(), self.view.fn_logs()).unwrap();
                         instance.bind_process(p);
                     }
 
@@ -129,7 +143,7 @@ impl InstanceManager {
             EachAppCache::Owned(_) => {
                 unreachable!("not a process instance")
             }
-            EachAppCache::Shared(shared) => shared.0.clone(),
+            EachAppCache::Shared(shared) => (shared.0.clone(), start),
         };
     }
 }
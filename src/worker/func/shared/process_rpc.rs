@@ -5,9 +5,10 @@ pub mod proc_proto {
 use crate::{
     general::network::rpc_model::{self, HashValue, MsgIdBind, ReqMsg, RpcCustom},
     modules_global_bridge::process_func::{
-        ModulesGlobalBrigeAppMetaManager, ModulesGlobalBrigeInstanceManager,
+        ModulesGlobalBrigeAppMetaManager, ModulesGlobalBrigeExecutor,
+        ModulesGlobalBrigeInstanceManager,
     },
-    result::WSResult,
+    result::{WSResult, WsFuncError},
     sys::LogicalModulesRef,
     worker::func::shared::process_rpc::proc_proto::AppStarted,
 };
@@ -17,7 +18,7 @@ use prost::Message;
 use std::{collections::HashMap, path::Path, time::Duration};
 use tokio::sync::oneshot;
 
-use self::proc_proto::{FuncCallReq, FuncCallResp};
+use self::proc_proto::{CallFnResp, FuncCallReq, FuncCallResp};
 
 use super::SharedInstance;
 
@@ -98,10 +99,10 @@ impl RpcCustom for ProcessRpc {
         Some(HashValue::Str(res.appid))
     }
 
-    fn handle_remote_call(_conn: &HashValue, id: u8, buf: &[u8]) -> bool {
+    fn handle_remote_call(conn: &HashValue, id: u8, taskid: u32, buf: &[u8]) -> bool {
         tracing::debug!("handle_remote_call: id: {}", id);
         let _ = match id {
-            4 => (),
+            4 | 5 => (),
             id => {
                 tracing::warn!("handle_remote_call: unsupported id: {}", id);
                 return false;
@@ -122,6 +123,39 @@ impl RpcCustom for ProcessRpc {
                 }
                 Err(e) => e,
             },
+            5 => match proc_proto::CallFnReq::decode(buf) {
+                Ok(req) => {
+                    let conn = conn.clone();
+                    let _ = tokio::spawn(async move {
+                        // answered either way, or the java side waits for it forever
+                        let res = match unsafe { ProcessRpc::global_m_executor() } {
+                            Some(executor) => {
+                                executor
+                                    .handle_fn_call(&req.app, &req.func, req.arg_str)
+                                    .await
+                            }
+                            None => Err(WsFuncError::NoExecutor.into()),
+                        };
+                        let resp = match res {
+                            Ok(ret_str) => CallFnResp {
+                                success: true,
+                                ret_str,
+                                err_msg: None,
+                            },
+                            Err(err) => CallFnResp {
+                                success: false,
+                                ret_str: None,
+                                err_msg: Some(format!("{:?}", err)),
+                            },
+                        };
+                        if let Err(err) = rpc_model::send_resp(resp, &conn, taskid).await {
+                            tracing::warn!("send fn call resp to {:?} failed: {:?}", conn, err);
+                        }
+                    });
+                    return true;
+                }
+                Err(e) => e,
+            },
             _ => unreachable!(),
         };
         tracing::warn!("handle_remote_call error: {:?}", err);
@@ -147,6 +181,18 @@ impl MsgIdBind for proc_proto::FuncCallResp {
     }
 }
 
+impl MsgIdBind for proc_proto::CallFnReq {
+    fn id() -> u16 {
+        5
+    }
+}
+
+impl MsgIdBind for proc_proto::CallFnResp {
+    fn id() -> u16 {
+        6
+    }
+}
+
 impl ReqMsg for FuncCallReq {
     type Resp = FuncCallResp;
 }
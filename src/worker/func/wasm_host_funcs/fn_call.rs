@@ -0,0 +1,94 @@
+use super::{
+    res_cache::InvokeResCache, utils, utils::m_executor, HostFuncRegister, ERR_FAILED, ERR_INVALID,
+    ERR_NOT_FOUND,
+};
+use crate::result::{WSError, WsFuncError};
+use std::time::Duration;
+use tracing::Instrument;
+
+#[cfg(target_os = "linux")]
+use wasmedge_sdk::{
+    async_host_function, error::HostFuncError, host_function, Caller, ImportObjectBuilder,
+    NeverType, WasmValue,
+};
+
+lazy_static::lazy_static! {
+    static ref RECENT_CALL_RES_CACHE: InvokeResCache<String>=
+        InvokeResCache::new(Duration::from_secs(10), 10240);
+}
+
+// app_ptr, app_len, func_ptr, func_len, arg_ptr, arg_len, ret_id_ptr, ret_len_ptr
+// ret_len is the error code if the call failed, 0 if the function returns nothing
+type CallFnArgs = (i32, i32, i32, i32, i32, i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn call_fn<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let app = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    let func = utils::u8slice(&caller, args[2].to_i32(), args[3].to_i32())?;
+    let arg = utils::u8slice(&caller, args[4].to_i32(), args[5].to_i32())?;
+    let ret_id = utils::mutref::<i32>(&caller, args[6].to_i32())?;
+    let ret_len = utils::mutref::<i32>(&caller, args[7].to_i32())?;
+
+    let (Ok(app), Ok(func), Ok(arg)) = (
+        std::str::from_utf8(app),
+        std::str::from_utf8(func),
+        std::str::from_utf8(arg),
+    ) else {
+        tracing::warn!("call_fn with non utf8 app, func or arg");
+        *ret_len = ERR_INVALID;
+        return Ok(vec![]);
+    };
+
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    match m_executor()
+        .handle_fn_call(app, func, arg.to_owned())
+        .instrument(fn_ctx.span.clone())
+        .await
+    {
+        Ok(Some(res)) => {
+            *ret_len = res.len() as i32;
+            *ret_id = RECENT_CALL_RES_CACHE.insert(&fn_ctx.app, fn_ctx.req_id, res);
+        }
+        Ok(None) => {
+            *ret_len = 0;
+        }
+        Err(err) => {
+            tracing::warn!("call_fn {}/{} failed with err: {:?}", app, func, err);
+            *ret_len = match err {
+                WSError::WsFuncError(
+                    WsFuncError::AppNotFound { .. } | WsFuncError::FuncNotFound { .. },
+                ) => ERR_NOT_FOUND,
+                _ => ERR_FAILED,
+            };
+        }
+    }
+    Ok(vec![])
+}
+
+// ret_id, ret_ptr
+type CallFnResArgs = (i32, i32);
+#[host_function]
+fn call_fn_res(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let id = args[0].to_i32();
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    if let Some(res) = RECENT_CALL_RES_CACHE.take(&fn_ctx.app, fn_ctx.req_id, id) {
+        utils::mutu8sclice(&caller, args[1].to_i32(), res.len() as i32)?
+            .copy_from_slice(res.as_bytes());
+    }
+    Ok(vec![])
+}
+
+pub(super) struct FnCallFuncsRegister;
+
+impl HostFuncRegister for FnCallFuncsRegister {
+    fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
+        builder
+            .with_async_func::<CallFnArgs, (), NeverType>("call_fn", call_fn, None)
+            .unwrap()
+            .with_func::<CallFnResArgs, (), NeverType>("call_fn_res", call_fn_res, None)
+            .unwrap()
+    }
+}
@@ -1,4 +1,13 @@
-use super::{utils, utils::m_fs, HostFuncRegister};
+use super::{
+    res_cache::InvokeResCache, utils, utils::m_fs, HostFuncRegister, ERR_DENIED, ERR_FAILED,
+    ERR_INVALID, ERR_NOT_FOUND, TRAP_HOST_FAILED,
+};
+use crate::{
+    general::m_os::FnOpenOptions,
+    result::{WSError, WsIoErr},
+    worker::func::FnExeCtx,
+};
+use std::time::{Duration, UNIX_EPOCH};
 
 #[cfg(target_os = "macos")]
 use wasmer::{imports, Function, FunctionType, Imports};
@@ -9,20 +18,57 @@ use wasmedge_sdk::{
     NeverType, WasmValue,
 };
 
+lazy_static::lazy_static! {
+    static ref RECENT_LIST_DIR_CACHE: InvokeResCache<String>=
+        InvokeResCache::new(Duration::from_secs(10), 1024);
+}
+
+pub(super) const OPEN_WRITE: i32 = 1;
+pub(super) const OPEN_APPEND: i32 = 1 << 1;
+pub(super) const OPEN_CREATE: i32 = 1 << 2;
+pub(super) const OPEN_TRUNCATE: i32 = 1 << 3;
+
+fn err_code(err: &WSError) -> i32 {
+    match err {
+        WSError::WsPermissionErr(_) => ERR_DENIED,
+        WSError::WsIoErr(WsIoErr::FdNotOpened(_)) => ERR_INVALID,
+        WSError::WsIoErr(WsIoErr::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            ERR_NOT_FOUND
+        }
+        _ => ERR_FAILED,
+    }
+}
+
+/// only the fds opened by the invocation itself are usable
+fn check_fd(fn_ctx: &FnExeCtx, fd: i32) -> Result<(), i32> {
+    if fn_ctx.fds.contains(&fd) {
+        Ok(())
+    } else {
+        Err(ERR_INVALID)
+    }
+}
+
 // fname_ptr, fname_len, fd_ptr
+// read only, rooted at the shared `files` dir
 type OpenFileArgs = (i32, i32, i32);
 #[cfg_attr(target_os = "linux", host_function)]
 fn open_file(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
-    let fname = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32());
-    let res = utils::mutref::<i32>(&caller, args[2].to_i32());
-    if let Ok(f) = m_fs().open_file(std::str::from_utf8(fname).unwrap()) {
-        *res = f;
-    } else {
-        tracing::error!(
-            "function failed to open file {}",
-            std::str::from_utf8(fname).unwrap()
-        );
-        *res = -1;
+    let fname = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    let res = utils::mutref::<i32>(&caller, args[2].to_i32())?;
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_mut() };
+    let Ok(fname) = std::str::from_utf8(fname) else {
+        *res = ERR_INVALID;
+        return Ok(vec![]);
+    };
+    match m_fs().open_file(&fn_ctx.app, fname) {
+        Ok(fd) => {
+            fn_ctx.fds.push(fd);
+            *res = fd;
+        }
+        Err(err) => {
+            tracing::error!("function failed to open file {}: {:?}", fname, err);
+            *res = err_code(&err);
+        }
     }
 
     Ok(vec![])
@@ -38,19 +84,17 @@ async fn read_file_at_async<T>(
     args: Vec<WasmValue>,
     _ctx: *mut T,
 ) -> Result<Vec<WasmValue>, HostFuncError> {
-    if let Err(err) = tokio::task::spawn_blocking(move || {
-        let fd = args[0].to_i32();
-        let data = utils::mutu8sclice(&caller, args[1].to_i32(), args[2].to_i32()).unwrap();
-        let offset = args[3].to_i32();
-        let retlen = utils::mutref::<i32>(&caller, args[4].to_i32());
-        *retlen = m_fs().read_file_at(fd, offset, data).unwrap() as i32;
+    run_blocking(move || {
+        read_at(
+            &caller,
+            args[0].to_i32(),
+            args[1].to_i32(),
+            args[2].to_i32(),
+            args[3].to_i32() as i64,
+            args[4].to_i32(),
+        )
     })
     .await
-    {
-        tracing::error!("function read_file_at_async: {}", err);
-    }
-
-    Ok(vec![])
 }
 #[cfg(target_os = "macos")]
 fn read_file_at(fd: i32, data_ptr: i32, data_len: i32, offset: i32, retlen_ptr: i32) {
@@ -60,6 +104,222 @@ fn read_file_at(fd: i32, data_ptr: i32, data_len: i32, offset: i32, retlen_ptr:
     *retlen = m_fs().read_file_at(fd, offset, data).unwrap() as i32;
 }
 
+/// the file io blocks, the guest memory stays valid as the guest waits for the call
+async fn run_blocking(
+    f: impl FnOnce() -> Result<(), HostFuncError> + Send + 'static,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(res) => res.map(|_| vec![]),
+        Err(err) => {
+            tracing::error!("function file io task failed: {}", err);
+            Err(HostFuncError::User(TRAP_HOST_FAILED))
+        }
+    }
+}
+
+fn read_at(
+    caller: &Caller,
+    fd: i32,
+    data_ptr: i32,
+    data_len: i32,
+    offset: i64,
+    retlen_ptr: i32,
+) -> Result<(), HostFuncError> {
+    let retlen = utils::mutref::<i32>(caller, retlen_ptr)?;
+    let data = utils::mutu8sclice(caller, data_ptr, data_len)?;
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(caller)?.0.as_ref() };
+    if let Err(code) = check_fd(fn_ctx, fd) {
+        *retlen = code;
+        return Ok(());
+    }
+    let Ok(offset) = u64::try_from(offset) else {
+        *retlen = ERR_INVALID;
+        return Ok(());
+    };
+    *retlen = match m_fs().read_file_at(fd, offset, data) {
+        Ok(len) => len as i32,
+        Err(err) => {
+            tracing::warn!("function read file failed: {:?}", err);
+            err_code(&err)
+        }
+    };
+    Ok(())
+}
+
+// path_ptr, path_len, flags, fd_ptr
+// relative to the sandbox dir of the app, flags are the OPEN_* bits
+type FsOpenArgs = (i32, i32, i32, i32);
+#[cfg_attr(target_os = "linux", host_function)]
+fn fs_open(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let path = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    let flags = args[2].to_i32();
+    let res = utils::mutref::<i32>(&caller, args[3].to_i32())?;
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_mut() };
+    let Ok(path) = std::str::from_utf8(path) else {
+        *res = ERR_INVALID;
+        return Ok(vec![]);
+    };
+    let opts = FnOpenOptions {
+        write: flags & OPEN_WRITE != 0,
+        append: flags & OPEN_APPEND != 0,
+        create: flags & OPEN_CREATE != 0,
+        truncate: flags & OPEN_TRUNCATE != 0,
+    };
+    match m_fs().open_app_file(&fn_ctx.app, path, opts) {
+        Ok(fd) => {
+            fn_ctx.fds.push(fd);
+            *res = fd;
+        }
+        Err(err) => {
+            tracing::warn!("function failed to open {}: {:?}", path, err);
+            *res = err_code(&err);
+        }
+    }
+    Ok(vec![])
+}
+
+// fd, data_ptr, data_len, offset, retlen_ptr
+type FsReadWriteArgs = (i32, i32, i32, i64, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn fs_read_at<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    run_blocking(move || {
+        read_at(
+            &caller,
+            args[0].to_i32(),
+            args[1].to_i32(),
+            args[2].to_i32(),
+            args[3].to_i64(),
+            args[4].to_i32(),
+        )
+    })
+    .await
+}
+
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn fs_write_at<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    run_blocking(move || {
+        let fd = args[0].to_i32();
+        let retlen = utils::mutref::<i32>(&caller, args[4].to_i32())?;
+        let data = utils::u8slice(&caller, args[1].to_i32(), args[2].to_i32())?;
+        let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+        if let Err(code) = check_fd(fn_ctx, fd) {
+            *retlen = code;
+            return Ok(());
+        }
+        let Ok(offset) = u64::try_from(args[3].to_i64()) else {
+            *retlen = ERR_INVALID;
+            return Ok(());
+        };
+        *retlen = match m_fs().write_file_at(fd, offset, data) {
+            Ok(len) => len as i32,
+            Err(err) => {
+                tracing::warn!("function write file failed: {:?}", err);
+                err_code(&err)
+            }
+        };
+        Ok(())
+    })
+    .await
+}
+
+// fd, ret_ptr
+type FsCloseArgs = (i32, i32);
+#[cfg_attr(target_os = "linux", host_function)]
+fn fs_close(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let fd = args[0].to_i32();
+    let res = utils::mutref::<i32>(&caller, args[1].to_i32())?;
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_mut() };
+    if let Err(code) = check_fd(fn_ctx, fd) {
+        *res = code;
+        return Ok(vec![]);
+    }
+    fn_ctx.fds.retain(|v| *v != fd);
+    *res = match m_fs().close_file(fd) {
+        Ok(()) => 0,
+        Err(err) => err_code(&err),
+    };
+    Ok(vec![])
+}
+
+// path_ptr, path_len, stat_ptr
+// stat is 3 i64s, size, is_dir and modified time in ms, size is the error code if failed
+type FsStatArgs = (i32, i32, i32);
+#[cfg_attr(target_os = "linux", host_function)]
+fn fs_stat(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let path = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    let stat = utils::mutref::<[i64; 3]>(&caller, args[2].to_i32())?;
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    let Ok(path) = std::str::from_utf8(path) else {
+        stat[0] = ERR_INVALID as i64;
+        return Ok(vec![]);
+    };
+    match m_fs().stat_app_file(&fn_ctx.app, path) {
+        Ok(meta) => {
+            stat[0] = meta.len() as i64;
+            stat[1] = meta.is_dir() as i64;
+            stat[2] = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_millis() as i64);
+        }
+        Err(err) => stat[0] = err_code(&err) as i64,
+    }
+    Ok(vec![])
+}
+
+// path_ptr, path_len, ret_id_ptr, ret_len_ptr
+// the entries are lines, dirs end with `/`, ret_len is the error code if failed
+type FsListDirArgs = (i32, i32, i32, i32);
+#[cfg_attr(target_os = "linux", host_function)]
+fn fs_list_dir(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let path = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    let ret_id = utils::mutref::<i32>(&caller, args[2].to_i32())?;
+    let ret_len = utils::mutref::<i32>(&caller, args[3].to_i32())?;
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    let Ok(path) = std::str::from_utf8(path) else {
+        *ret_len = ERR_INVALID;
+        return Ok(vec![]);
+    };
+    match m_fs().list_app_dir(&fn_ctx.app, path) {
+        Ok(entries) => {
+            let mut listing = String::new();
+            for entry in entries {
+                listing.push_str(&entry.name);
+                if entry.is_dir {
+                    listing.push('/');
+                }
+                listing.push('\n');
+            }
+            *ret_len = listing.len() as i32;
+            *ret_id = RECENT_LIST_DIR_CACHE.insert(&fn_ctx.app, fn_ctx.req_id, listing);
+        }
+        Err(err) => *ret_len = err_code(&err),
+    }
+    Ok(vec![])
+}
+
+// ret_id, ret_ptr
+type FsListDirResArgs = (i32, i32);
+#[cfg_attr(target_os = "linux", host_function)]
+fn fs_list_dir_res(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let id = args[0].to_i32();
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    if let Some(listing) = RECENT_LIST_DIR_CACHE.take(&fn_ctx.app, fn_ctx.req_id, id) {
+        utils::mutu8sclice(&caller, args[1].to_i32(), listing.len() as i32)?
+            .copy_from_slice(listing.as_bytes());
+    }
+    Ok(vec![])
+}
+
 pub(super) struct FsFuncsRegister;
 
 impl HostFuncRegister for FsFuncsRegister {
@@ -73,5 +333,19 @@ impl HostFuncRegister for FsFuncsRegister {
             .unwrap()
             .with_func::<OpenFileArgs, (), NeverType>("open_file", open_file, None)
             .unwrap()
+            .with_func::<FsOpenArgs, (), NeverType>("fs_open", fs_open, None)
+            .unwrap()
+            .with_async_func::<FsReadWriteArgs, (), NeverType>("fs_read_at", fs_read_at, None)
+            .unwrap()
+            .with_async_func::<FsReadWriteArgs, (), NeverType>("fs_write_at", fs_write_at, None)
+            .unwrap()
+            .with_func::<FsCloseArgs, (), NeverType>("fs_close", fs_close, None)
+            .unwrap()
+            .with_func::<FsStatArgs, (), NeverType>("fs_stat", fs_stat, None)
+            .unwrap()
+            .with_func::<FsListDirArgs, (), NeverType>("fs_list_dir", fs_list_dir, None)
+            .unwrap()
+            .with_func::<FsListDirResArgs, (), NeverType>("fs_list_dir_res", fs_list_dir_res, None)
+            .unwrap()
     }
 }
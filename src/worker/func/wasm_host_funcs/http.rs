@@ -0,0 +1,249 @@
+use super::{
+    res_cache::InvokeResCache, utils, HostFuncRegister, ERR_DENIED, ERR_FAILED, ERR_INVALID,
+    ERR_TIMEOUT, ERR_TOO_LARGE,
+};
+use crate::general::m_appmeta_manager::http_out::HttpOutMeta;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method, Url,
+};
+use std::time::Duration;
+use tracing::Instrument;
+
+#[cfg(target_os = "linux")]
+use wasmedge_sdk::{
+    async_host_function, error::HostFuncError, host_function, Caller, ImportObjectBuilder,
+    NeverType, WasmValue,
+};
+
+lazy_static::lazy_static! {
+    static ref RECENT_HTTP_RES_CACHE: InvokeResCache<Vec<u8>>=
+        InvokeResCache::new(Duration::from_secs(10), 1024);
+    /// redirects are returned to the guest, or they would escape the allowed hosts
+    static ref HTTP_CLIENT: reqwest::Client=reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+}
+
+struct HttpOutRequest {
+    method: Method,
+    url: Url,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+struct HttpOutResponse {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl HttpOutRequest {
+    /// headers are `name: value` lines
+    fn parse(method: &[u8], url: &[u8], headers: &[u8], body: &[u8]) -> Option<Self> {
+        let method = Method::from_bytes(method).ok()?;
+        let url = Url::parse(std::str::from_utf8(url).ok()?).ok()?;
+        let mut header_map = HeaderMap::new();
+        for line in std::str::from_utf8(headers).ok()?.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (name, value) = line.split_once(':')?;
+            let _ = header_map.append(
+                HeaderName::from_bytes(name.trim().as_bytes()).ok()?,
+                HeaderValue::from_str(value.trim()).ok()?,
+            );
+        }
+        Some(Self {
+            method,
+            url,
+            headers: header_map,
+            body: body.to_owned(),
+        })
+    }
+}
+
+async fn send_http(meta: &HttpOutMeta, req: HttpOutRequest) -> Result<HttpOutResponse, i32> {
+    if !meta.allow(&req.url) {
+        tracing::warn!("http request to {} denied", req.url);
+        return Err(ERR_DENIED);
+    }
+    let map_err = |e: reqwest::Error| {
+        tracing::warn!("http request failed: {}", e);
+        if e.is_timeout() {
+            ERR_TIMEOUT
+        } else {
+            ERR_FAILED
+        }
+    };
+    let send_and_read = async {
+        let mut resp = HTTP_CLIENT
+            .request(req.method, req.url)
+            .headers(req.headers)
+            .body(req.body)
+            .send()
+            .await
+            .map_err(map_err)?;
+        if resp
+            .content_length()
+            .map_or(false, |len| len > meta.max_resp_bytes as u64)
+        {
+            return Err(ERR_TOO_LARGE);
+        }
+        let status = resp.status().as_u16();
+        let mut body = vec![];
+        while let Some(chunk) = resp.chunk().await.map_err(map_err)? {
+            if body.len() + chunk.len() > meta.max_resp_bytes {
+                return Err(ERR_TOO_LARGE);
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok::<_, i32>(HttpOutResponse { status, body })
+    };
+    // covers reading the body as well
+    tokio::time::timeout(meta.timeout(), send_and_read)
+        .await
+        .unwrap_or(Err(ERR_TIMEOUT))
+}
+
+// args_ptr, args_len, out_ptr
+// args are method, url, headers and body, each as ptr and len
+// out is ret id, status and body len, ret id is negative if the request is not done
+type HttpRequestArgs = (i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn http_request<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let req_args = utils::i32slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    let out = utils::mutref::<[i32; 3]>(&caller, args[2].to_i32())?;
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+
+    if req_args.len() < 8 {
+        out[0] = ERR_INVALID;
+        return Ok(vec![]);
+    }
+    let Some(req) = HttpOutRequest::parse(
+        utils::u8slice(&caller, req_args[0], req_args[1])?,
+        utils::u8slice(&caller, req_args[2], req_args[3])?,
+        utils::u8slice(&caller, req_args[4], req_args[5])?,
+        utils::u8slice(&caller, req_args[6], req_args[7])?,
+    ) else {
+        out[0] = ERR_INVALID;
+        return Ok(vec![]);
+    };
+    let Some(app_meta) = utils::m_appmeta_manager()
+        .meta
+        .read()
+        .await
+        .get_app_meta(&fn_ctx.app)
+        .await
+    else {
+        out[0] = ERR_FAILED;
+        return Ok(vec![]);
+    };
+
+    match send_http(&app_meta.http_out, req)
+        .instrument(fn_ctx.span.clone())
+        .await
+    {
+        Ok(resp) => {
+            out[1] = resp.status as i32;
+            out[2] = resp.body.len() as i32;
+            out[0] = RECENT_HTTP_RES_CACHE.insert(&fn_ctx.app, fn_ctx.req_id, resp.body);
+        }
+        Err(code) => out[0] = code,
+    }
+    Ok(vec![])
+}
+
+// ret_id, body_ptr
+type HttpResponseArgs = (i32, i32);
+#[cfg_attr(target_os = "linux", host_function)]
+fn http_response(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let id = args[0].to_i32();
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    if let Some(body) = RECENT_HTTP_RES_CACHE.take(&fn_ctx.app, fn_ctx.req_id, id) {
+        utils::mutu8sclice(&caller, args[1].to_i32(), body.len() as i32)?.copy_from_slice(&body);
+    }
+    Ok(vec![])
+}
+
+pub(super) struct HttpFuncsRegister;
+
+impl HostFuncRegister for HttpFuncsRegister {
+    fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
+        builder
+            .with_async_func::<HttpRequestArgs, (), NeverType>("http_request", http_request, None)
+            .unwrap()
+            .with_func::<HttpResponseArgs, (), NeverType>("http_response", http_response, None)
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{routing::get, Router};
+    use std::net::SocketAddr;
+
+    async fn start_server() -> SocketAddr {
+        let router = Router::new()
+            .route("/hello", get(|| async { "hello" }))
+            .route("/large", get(|| async { "x".repeat(4096) }))
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    "slow"
+                }),
+            );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _ = tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        addr
+    }
+
+    fn get_req(url: String) -> HttpOutRequest {
+        HttpOutRequest::parse(b"GET", url.as_bytes(), b"accept: text/plain\n", b"").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_http_request_limits() {
+        let addr = start_server().await;
+        let meta = HttpOutMeta {
+            allowed_hosts: vec![format!("127.0.0.1:{}", addr.port())],
+            timeout_ms: 500,
+            max_resp_bytes: 1024,
+        };
+
+        let resp = send_http(&meta, get_req(format!("http://{}/hello", addr)))
+            .await
+            .unwrap_or_else(|code| panic!("request failed with {}", code));
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body, b"hello");
+
+        let other_port = format!("http://127.0.0.1:{}/hello", addr.port() + 1);
+        assert_eq!(
+            send_http(&meta, get_req(other_port)).await.err(),
+            Some(ERR_DENIED)
+        );
+        assert_eq!(
+            send_http(&meta, get_req(format!("http://{}/large", addr)))
+                .await
+                .err(),
+            Some(ERR_TOO_LARGE)
+        );
+        assert_eq!(
+            send_http(&meta, get_req(format!("http://{}/slow", addr)))
+                .await
+                .err(),
+            Some(ERR_TIMEOUT)
+        );
+    }
+}
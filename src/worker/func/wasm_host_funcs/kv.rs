@@ -1,4 +1,9 @@
-use super::{utils, utils::m_kv_user_client, HostFuncRegister};
+use super::{
+    res_cache::InvokeResCache,
+    utils,
+    utils::{m_appmeta_manager, m_kv_user_client},
+    HostFuncRegister, ERR_DENIED, ERR_FAILED, ERR_INVALID,
+};
 use crate::general::{
     kv_interface::{KvInterface, KvOptions},
     network::{
@@ -9,8 +14,8 @@ use crate::general::{
         },
     },
 };
-use moka::sync::Cache;
-use std::{sync::atomic::AtomicI32, time::Duration};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 #[cfg(target_os = "macos")]
 use wasmer::{imports, Function, FunctionType, Imports};
 
@@ -105,13 +110,8 @@ fn kv_get_len(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, Ho
 // }
 
 lazy_static::lazy_static! {
-
-    static ref RECENT_KV_CACHE: Cache<i32, KvResponses>=Cache::builder()
-        .time_to_live(Duration::from_secs(10))
-        // This cache will hold up to 32MiB of values.
-        .max_capacity(10240)
-        .build();
-    static ref NEXT_CACHE_ID: AtomicI32=AtomicI32::new(0);
+    static ref RECENT_KV_CACHE: InvokeResCache<KvResponses>=
+        InvokeResCache::new(Duration::from_secs(10), 10240);
 }
 
 const SET_ID: usize = 1;
@@ -119,6 +119,41 @@ const GET_ID: usize = 2;
 const LOCK_ID: usize = 3;
 const DELETE_ID: usize = 4;
 
+/// args of each ope after the type
+fn ope_args_len(ope_type: i32) -> Option<usize> {
+    match ope_type as usize {
+        SET_ID => Some(4),
+        GET_ID => Some(3),
+        LOCK_ID => Some(4),
+        DELETE_ID => Some(2),
+        _ => None,
+    }
+}
+
+fn key_range(key: &[u8]) -> Option<KeyRange> {
+    Some(KeyRange {
+        start: key.to_owned(),
+        end: vec![],
+    })
+}
+
+/// splits the args into the opes, checking the count and the types
+fn split_opes(args: &[i32]) -> Option<Vec<(i32, &[i32])>> {
+    let (&ope_cnt, mut rest) = args.split_first()?;
+    let mut opes = vec![];
+    for _ in 0..ope_cnt {
+        let (&ope_type, after_type) = rest.split_first()?;
+        let len = ope_args_len(ope_type)?;
+        if after_type.len() < len {
+            return None;
+        }
+        let (ope_args, after_ope) = after_type.split_at(len);
+        opes.push((ope_type, ope_args));
+        rest = after_ope;
+    }
+    Some(opes)
+}
+
 type KvBatchOpe = (i32, i32, i32);
 #[cfg_attr(target_os = "linux", async_host_function)]
 async fn kv_batch_ope<T>(
@@ -126,99 +161,75 @@ async fn kv_batch_ope<T>(
     args: Vec<WasmValue>,
     _ctx: *mut T,
 ) -> Result<Vec<WasmValue>, HostFuncError> {
-    let opes_arg_ptr = args[0].to_i32();
-    let opes_arg_len = args[1].to_i32();
-    let opes_id = utils::mutref::<i32>(&caller, args[2].to_i32());
-    let args = utils::i32slice(&caller, opes_arg_ptr, opes_arg_len);
-    let func_ctx = unsafe { utils::current_app_fn_ctx(&caller).0.as_mut() };
+    let opes_id = utils::mutref::<i32>(&caller, args[2].to_i32())?;
+    let args = utils::i32slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    let func_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_mut() };
+    // written first, so the guest never reads the one of a previous batch
+    *opes_id = ERR_FAILED;
 
-    // request and response mem position
-    let ope_cnt = args[0];
-    let mut requests: Vec<KvRequest> = vec![];
-    let mut cur_idx = 1;
-    // tracing::debug!("args:{:?}", args);
+    let Some(opes) = split_opes(args) else {
+        tracing::warn!("kv batch ope with invalid args:{:?}", args);
+        *opes_id = ERR_INVALID;
+        return Ok(vec![]);
+    };
     // Construct the requests
-    for _ in 0..ope_cnt {
-        let ope_type = args[cur_idx];
-        match ope_type as usize {
-            // set
+    let mut requests: Vec<KvRequest> = vec![];
+    for (ope_type, ope_args) in &opes {
+        let key = utils::u8slice(&caller, ope_args[0], ope_args[1])?;
+        let op = match *ope_type as usize {
             SET_ID => {
-                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
-                let value = utils::u8slice(&caller, args[cur_idx + 3], args[cur_idx + 4]);
-                requests.push(KvRequest {
-                    op: Some(proto::kv::kv_request::Op::Set(
-                        proto::kv::kv_request::KvPutRequest {
-                            kv: Some(KvPair {
-                                key: key.to_owned(),
-                                value: value.to_owned(),
-                            }),
-                        },
-                    )),
-                });
-                cur_idx += 5;
+                let value = utils::u8slice(&caller, ope_args[2], ope_args[3])?;
+                proto::kv::kv_request::Op::Set(proto::kv::kv_request::KvPutRequest {
+                    kv: Some(KvPair {
+                        key: key.to_owned(),
+                        value: value.to_owned(),
+                    }),
+                })
             }
-            // get
-            GET_ID => {
-                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
-                // tracing::debug!("ptr:{} len:{} get key:{:?}", args[cur_idx + 1], args[cur_idx + 2],key);
-                requests.push(KvRequest {
-                    op: Some(proto::kv::kv_request::Op::Get(
-                        proto::kv::kv_request::KvGetRequest {
-                            range: Some(KeyRange {
-                                start: key.to_owned(),
-                                end: vec![],
-                            }),
-                        },
-                    )),
-                });
-                cur_idx += 4;
-            }
-            // lock
+            GET_ID => proto::kv::kv_request::Op::Get(proto::kv::kv_request::KvGetRequest {
+                range: key_range(key),
+            }),
             LOCK_ID => {
-                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
-                // // first bit
-                // let read_or_write = args[cur_idx + 3] & 1 == 1;
                 // <0 means get
-                let release_id = args[cur_idx + 3];
-                requests.push(KvRequest {
-                    op: Some(proto::kv::kv_request::Op::Lock(
-                        proto::kv::kv_request::KvLockRequest {
-                            read_or_write: false,
-                            release_id: if release_id < 0 {
-                                vec![]
-                            } else {
-                                vec![release_id as u32]
-                            },
-                            range: Some(KeyRange {
-                                start: key.to_owned(),
-                                end: vec![],
-                            }),
-                        },
-                    )),
-                });
-                cur_idx += 5;
-            }
-            DELETE_ID => {
-                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
-                requests.push(KvRequest {
-                    op: Some(proto::kv::kv_request::Op::Delete(
-                        proto::kv::kv_request::KvDeleteRequest {
-                            range: Some(KeyRange {
-                                start: key.to_owned(),
-                                end: vec![],
-                            }),
-                        },
-                    )),
-                });
-                cur_idx += 3;
+                let release_id = ope_args[2];
+                proto::kv::kv_request::Op::Lock(proto::kv::kv_request::KvLockRequest {
+                    read_or_write: false,
+                    release_id: if release_id < 0 {
+                        vec![]
+                    } else {
+                        vec![release_id as u32]
+                    },
+                    range: key_range(key),
+                })
             }
-            _ => {
-                panic!("not implemented, reqs{:?},{:X}", requests, ope_type);
-            }
-        }
+            // checked by split_opes
+            _ => proto::kv::kv_request::Op::Delete(proto::kv::kv_request::KvDeleteRequest {
+                range: key_range(key),
+            }),
+        };
+        requests.push(KvRequest { op: Some(op) });
+    }
+    let bf_kv = Instant::now();
+    if let Err(err) = func_ctx
+        .func_meta
+        .check_kv_requests(&func_ctx.app, &func_ctx.func, &requests)
+    {
+        tracing::warn!("kv batch ope denied:{}", err);
+        *opes_id = ERR_DENIED;
+        return Ok(vec![]);
+    }
+    if let Err(err) = m_appmeta_manager()
+        .meta
+        .read()
+        .await
+        .check_kv_shares(&func_ctx.app, &func_ctx.func, &requests)
+        .await
+    {
+        tracing::warn!("kv batch ope denied:{}", err);
+        *opes_id = ERR_DENIED;
+        return Ok(vec![]);
     }
-    // tracing::debug!("requests:{:?}", requests);
-    match m_kv_user_client()
+    let res = m_kv_user_client()
         .call(
             KvRequests {
                 requests,
@@ -228,92 +239,73 @@ async fn kv_batch_ope<T>(
                     .event_ctx
                     .take_prev_kv_opeid()
                     .map_or(-1, |v| v as i64),
+                trace_ctx: Default::default(),
             },
             KvOptions::new(),
         )
-        .await
-    {
+        .instrument(func_ctx.span.clone())
+        .await;
+    func_ctx.kv_time += bf_kv.elapsed();
+    let res = match res {
+        Ok(res) if res.responses.len() == opes.len() => res,
         Ok(res) => {
-            let id = NEXT_CACHE_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            // Write back the results to wasm runtime
-            let mut cur_idx = 1;
-            let mut resps = res.responses.iter();
-            // Construct the requests
-            for _ in 0..ope_cnt {
-                let ope_type = args[cur_idx];
-                match ope_type as usize {
-                    // set
-                    SET_ID => {
-                        let _ = resps.next().unwrap();
-                        cur_idx += 5;
-                    }
-                    // get
-                    GET_ID => {
-                        let kvs = resps.next().unwrap().common_kvs().unwrap();
-                        // get len
-                        *utils::mutref::<i32>(&caller, args[cur_idx + 3]) = if kvs.len() > 0 {
-                            kvs.get(0).unwrap().value.len() as i32
-                        } else {
-                            -1
-                        };
-                        cur_idx += 4;
-                    }
-                    // lock
-                    LOCK_ID => {
-                        if let Some(lockid) = resps.next().unwrap().lock_id() {
-                            // lock id is allocated by the remote when call the lock
-                            *utils::mutref::<u32>(&caller, args[cur_idx + 4]) = lockid;
-                        } else {
-                            // unlock, no response
-                        }
-                        cur_idx += 5;
-                    }
-                    DELETE_ID => {
-                        let _ = resps.next().unwrap();
-                        cur_idx += 3;
-                    }
-                    _ => {
-                        panic!("not implemented");
-                    }
-                }
-            }
-            RECENT_KV_CACHE.insert(id, res);
-            *opes_id = id;
+            tracing::error!(
+                "kv batch ope got {} responses for {} opes",
+                res.responses.len(),
+                opes.len()
+            );
+            return Ok(vec![]);
         }
         Err(err) => {
             tracing::error!("kv batch ope error:{}", err);
+            return Ok(vec![]);
+        }
+    };
+
+    // Write back the results to wasm runtime
+    for ((ope_type, ope_args), resp) in opes.iter().zip(res.responses.iter()) {
+        match *ope_type as usize {
+            GET_ID => {
+                let len = utils::mutref::<i32>(&caller, ope_args[2])?;
+                *len = match resp.common_kvs().and_then(|kvs| kvs.get(0)) {
+                    Some(kv) => kv.value.len() as i32,
+                    None => -1,
+                };
+            }
+            LOCK_ID => {
+                // lock id is allocated by the remote when call the lock, unlock has none
+                if let Some(lockid) = resp.lock_id() {
+                    *utils::mutref::<u32>(&caller, ope_args[3])? = lockid;
+                }
+            }
+            _ => {}
         }
     }
+    *opes_id = RECENT_KV_CACHE.insert(&func_ctx.app, func_ctx.req_id, res);
     Ok(vec![])
 }
 
+// opes_id, args_ptr, args_len
+// args are pairs of the get ope index and the ptr to copy the value to, the others are skipped
 #[host_function]
 fn kv_batch_res(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
     let id = args[0].to_i32();
-
-    if let Some(res) = RECENT_KV_CACHE.get(&id) {
-        let args_ptr = args[1].to_i32();
-        let args_len = args[2].to_i32();
-        let args = utils::i32slice(&caller, args_ptr, args_len);
-        let mut cur_idx = 0;
-        while cur_idx < args.len() {
-            let ope_idx = args[cur_idx];
-            if let Some(res) = res.responses.get(ope_idx as usize) {
-                if let Some(kvs) = res.common_kvs() {
-                    if let Some(kv) = kvs.get(0) {
-                        let slice =
-                            utils::mutu8sclice(&caller, args[cur_idx + 1], kv.value.len() as i32)
-                                .unwrap();
-                        slice.copy_from_slice(kvs.get(0).unwrap().value.as_slice());
-                    }
-                } else if let Some(_lock_id) = res.lock_id() {
-                    // do nothing
-                } else {
-                    panic!("not implemented");
-                }
-            }
-            cur_idx += 2;
-        }
+    let pairs = utils::i32slice(&caller, args[1].to_i32(), args[2].to_i32())?;
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    let Some(res) = RECENT_KV_CACHE.take(&fn_ctx.app, fn_ctx.req_id, id) else {
+        return Ok(vec![]);
+    };
+    for pair in pairs.chunks_exact(2) {
+        let Some(kv) = usize::try_from(pair[0])
+            .ok()
+            .and_then(|idx| res.responses.get(idx))
+            .and_then(|res| res.common_kvs())
+            .and_then(|kvs| kvs.get(0))
+        else {
+            continue;
+        };
+        utils::mutu8sclice(&caller, pair[1], kv.value.len() as i32)?
+            .copy_from_slice(kv.value.as_slice());
     }
     Ok(vec![])
 }
@@ -333,3 +325,49 @@ impl HostFuncRegister for KvFuncsRegister {
         // .unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_opes() {
+        let opes = split_opes(&[2, 1, 10, 3, 20, 4, 2, 30, 5, 40]).unwrap();
+        assert_eq!(opes, vec![(1, &[10, 3, 20, 4][..]), (2, &[30, 5, 40][..])]);
+        assert_eq!(split_opes(&[0]), Some(vec![]));
+        // truncated, unknown type and missing count
+        assert_eq!(split_opes(&[1, 1, 10, 3]), None);
+        assert_eq!(split_opes(&[1, 9, 10, 3]), None);
+        assert_eq!(split_opes(&[]), None);
+    }
+
+    #[test]
+    fn test_sdk_batch_split() {
+        assert_eq!(ws_sdk::abi::KV_SET as usize, SET_ID);
+        assert_eq!(ws_sdk::abi::KV_GET as usize, GET_ID);
+        assert_eq!(ws_sdk::abi::KV_LOCK as usize, LOCK_ID);
+        assert_eq!(ws_sdk::abi::KV_DELETE as usize, DELETE_ID);
+
+        let mut batch = ws_sdk::kv::Batch::new();
+        let _ = batch
+            .set(b"a", b"vvvvv")
+            .get(b"bb")
+            .lock(b"ccc")
+            .unlock(b"ccc", 7)
+            .delete(b"dddd");
+        let mut outs = vec![0; 5];
+        let args = batch.encode_args(&mut outs);
+        let opes = split_opes(&args).unwrap();
+        assert_eq!(
+            opes.iter().map(|(t, _)| *t as usize).collect::<Vec<_>>(),
+            vec![SET_ID, GET_ID, LOCK_ID, LOCK_ID, DELETE_ID]
+        );
+        // the key lens are where the host reads them
+        let key_lens = opes.iter().map(|(_, a)| a[1]).collect::<Vec<_>>();
+        assert_eq!(key_lens, vec![1, 2, 3, 3, 4]);
+        // value len of the set, release id of the locks
+        assert_eq!(opes[0].1[3], 5);
+        assert_eq!(opes[2].1[2], -1);
+        assert_eq!(opes[3].1[2], 7);
+    }
+}
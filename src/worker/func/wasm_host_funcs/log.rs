@@ -0,0 +1,39 @@
+use super::{utils, HostFuncRegister};
+use crate::general::m_fn_logs::FnLogStream;
+
+#[cfg(target_os = "linux")]
+use wasmedge_sdk::{
+    error::HostFuncError, host_function, Caller, ImportObjectBuilder, NeverType, WasmValue,
+};
+
+// text_ptr, text_len, stream (0 stdout, 1 stderr)
+type WriteLogArgs = (i32, i32, i32);
+#[host_function]
+fn write_log(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let text = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    let stream = if args[2].to_i32() == 1 {
+        FnLogStream::Stderr
+    } else {
+        FnLogStream::Stdout
+    };
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    utils::m_fn_logs().push(
+        &fn_ctx.app,
+        &fn_ctx.func,
+        Some(fn_ctx.req_id),
+        stream,
+        &String::from_utf8_lossy(text),
+    );
+
+    Ok(vec![])
+}
+
+pub(super) struct LogFuncsRegister;
+
+impl HostFuncRegister for LogFuncsRegister {
+    fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
+        builder
+            .with_func::<WriteLogArgs, (), NeverType>("write_log", write_log, None)
+            .unwrap()
+    }
+}
@@ -1,29 +1,41 @@
 #[cfg(target_os = "linux")]
 use wasmedge_sdk::{ImportObject, ImportObjectBuilder, NeverType};
+mod fn_call;
 mod fs;
+mod http;
 mod kv;
+mod log;
+mod res_cache;
 mod result;
+mod wasi;
 
+use fn_call::FnCallFuncsRegister;
 use fs::FsFuncsRegister;
+use http::HttpFuncsRegister;
 use kv::KvFuncsRegister;
+use log::LogFuncsRegister;
 use result::ResultFuncsRegister;
+use wasi::WasiFuncsRegister;
 
 use crate::sys::LogicalModulesRef;
 
 mod utils {
 
-    use wasmedge_sdk::{Caller, CallingFrame, Instance, Memory};
+    use wasmedge_sdk::{error::HostFuncError, Caller, CallingFrame, Instance, Memory};
 
     use crate::{
-        general::m_os::OperatingSystem,
+        general::{m_appmeta_manager::AppMetaManager, m_fn_logs::FnLogs, m_os::OperatingSystem},
         sys::LogicalModulesRef,
         util::SendNonNull,
         worker::{
             func::{m_instance_manager::InstanceManager, FnExeCtx},
+            m_executor::Executor,
             m_kv_user_client::KvUserClient,
         },
     };
 
+    const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
     pub trait WasmCtx {
         fn memory(&self, idx: u32) -> Option<Memory>;
         fn instance(&self) -> Option<&Instance>;
@@ -47,59 +59,105 @@ mod utils {
         }
     }
 
-    pub fn u8slice<'a>(caller: &impl WasmCtx, ptr: i32, len: i32) -> &'a [u8] {
-        // tracing::debug!("u8slice ptr: {}, len: {}", ptr, len);
-        let mem = caller
-            .memory(0)
-            .unwrap()
-            .data_pointer(ptr as u32, len as u32)
-            .unwrap();
-        let res = unsafe { std::slice::from_raw_parts(mem, len as usize) };
-        res
-    }
-
-    pub fn i32slice<'a>(caller: &impl WasmCtx, ptr: i32, len: i32) -> &'a [i32] {
-        let mem = caller
-            .memory(0)
-            .unwrap()
-            .data_pointer(ptr as u32, len as u32)
-            .unwrap();
-        unsafe { std::slice::from_raw_parts(mem as *const i32, len as usize) }
-    }
-
-    pub fn mutu8sclice<'a>(caller: &impl WasmCtx, ptr: i32, len: i32) -> Option<&'a mut [u8]> {
-        if let Ok(mem) = caller
-            .memory(0)
-            .unwrap()
-            .data_pointer_mut(ptr as u32, len as u32)
-        {
-            Some(unsafe { std::slice::from_raw_parts_mut(mem, len as usize) })
-        } else {
-            None
+    fn bad_memory() -> HostFuncError {
+        HostFuncError::User(super::TRAP_BAD_MEMORY)
+    }
+
+    fn guest_len(len: i32) -> Result<usize, HostFuncError> {
+        usize::try_from(len).map_err(|_| bad_memory())
+    }
+
+    /// traps when the range is not in the guest memory or ptr is misaligned for the type
+    fn guest_ptr(
+        caller: &impl WasmCtx,
+        ptr: i32,
+        len: usize,
+        align: usize,
+    ) -> Result<*mut u8, HostFuncError> {
+        let Ok(offset) = u32::try_from(ptr) else {
+            return Err(bad_memory());
+        };
+        let mut mem = caller.memory(0).ok_or_else(bad_memory)?;
+        let mem_size = mem.size() as u64 * WASM_PAGE_SIZE;
+        if offset as usize % align != 0 || offset as u64 + len as u64 > mem_size {
+            return Err(bad_memory());
         }
+        mem.data_pointer_mut(offset, len as u32)
+            .map_err(|_| bad_memory())
     }
 
-    pub fn mutref<'a, T: Sized>(caller: &impl WasmCtx, ptr: i32) -> &'a mut T {
-        unsafe {
-            &mut *(caller
-                .memory(0)
-                .unwrap()
-                .data_pointer_mut(ptr as u32, std::mem::size_of::<T>() as u32)
-                .unwrap() as *mut T)
+    pub fn u8slice<'a>(
+        caller: &impl WasmCtx,
+        ptr: i32,
+        len: i32,
+    ) -> Result<&'a [u8], HostFuncError> {
+        let len = guest_len(len)?;
+        if len == 0 {
+            return Ok(&[]);
         }
+        let mem = guest_ptr(caller, ptr, len, 1)?;
+        Ok(unsafe { std::slice::from_raw_parts(mem, len) })
     }
 
-    pub fn current_app_fn_ctx(caller: &impl WasmCtx) -> SendNonNull<FnExeCtx> {
-        let app_fn = SendNonNull(
-            m_instance_manager()
-                .instance_running_function
-                .read()
-                .get(&caller.instance().unwrap().name().unwrap())
-                .unwrap()
-                .0
-                .clone(),
-        );
-        app_fn
+    /// len is the count of i32s
+    pub fn i32slice<'a>(
+        caller: &impl WasmCtx,
+        ptr: i32,
+        len: i32,
+    ) -> Result<&'a [i32], HostFuncError> {
+        let len = guest_len(len)?;
+        if len == 0 {
+            return Ok(&[]);
+        }
+        let mem = guest_ptr(
+            caller,
+            ptr,
+            len * std::mem::size_of::<i32>(),
+            std::mem::align_of::<i32>(),
+        )?;
+        Ok(unsafe { std::slice::from_raw_parts(mem as *const i32, len) })
+    }
+
+    pub fn mutu8sclice<'a>(
+        caller: &impl WasmCtx,
+        ptr: i32,
+        len: i32,
+    ) -> Result<&'a mut [u8], HostFuncError> {
+        let len = guest_len(len)?;
+        if len == 0 {
+            return Ok(&mut []);
+        }
+        let mem = guest_ptr(caller, ptr, len, 1)?;
+        Ok(unsafe { std::slice::from_raw_parts_mut(mem, len) })
+    }
+
+    pub fn mutref<'a, T: Sized>(
+        caller: &impl WasmCtx,
+        ptr: i32,
+    ) -> Result<&'a mut T, HostFuncError> {
+        let mem = guest_ptr(
+            caller,
+            ptr,
+            std::mem::size_of::<T>(),
+            std::mem::align_of::<T>(),
+        )?;
+        Ok(unsafe { &mut *(mem as *mut T) })
+    }
+
+    /// traps when called outside of an invocation
+    pub fn current_app_fn_ctx(
+        caller: &impl WasmCtx,
+    ) -> Result<SendNonNull<FnExeCtx>, HostFuncError> {
+        let name = caller
+            .instance()
+            .and_then(|ins| ins.name())
+            .ok_or(HostFuncError::User(super::TRAP_NO_FN_CTX))?;
+        m_instance_manager()
+            .instance_running_function
+            .read()
+            .get(&name)
+            .map(|v| SendNonNull(v.0.clone()))
+            .ok_or(HostFuncError::User(super::TRAP_NO_FN_CTX))
     }
 
     lazy_static::lazy_static! {
@@ -126,6 +184,35 @@ mod utils {
         }
     }
 
+    pub fn m_appmeta_manager() -> &'static AppMetaManager {
+        unsafe {
+            &(*MODULES.as_ref().unwrap().inner.as_ptr())
+                .as_ref()
+                .unwrap()
+                .appmeta_manager
+        }
+    }
+
+    pub fn m_fn_logs() -> &'static FnLogs {
+        unsafe {
+            &(*MODULES.as_ref().unwrap().inner.as_ptr())
+                .as_ref()
+                .unwrap()
+                .fn_logs
+        }
+    }
+
+    pub fn m_executor() -> &'static Executor {
+        unsafe {
+            &(*MODULES.as_ref().unwrap().inner.as_ptr())
+                .as_ref()
+                .unwrap()
+                .executor
+                .as_ref()
+                .unwrap()
+        }
+    }
+
     pub fn m_instance_manager() -> &'static InstanceManager {
         unsafe {
             &(*MODULES.as_ref().unwrap().inner.as_ptr())
@@ -159,6 +246,29 @@ pub fn set_singleton_modules(modules: LogicalModulesRef) {
 //     }
 // }
 
+/// bumped when the host functions change incompatibly,
+/// guests built with `ws_sdk` export theirs as `ws_abi_version`
+pub const WASM_ABI_VERSION: i32 = 2;
+
+/// written where a len, id or fd is expected when the call is not done, the valid ones are never negative
+const ERR_FAILED: i32 = -1;
+/// not allowed by app.yaml or out of the sandbox
+const ERR_DENIED: i32 = -2;
+const ERR_INVALID: i32 = -3;
+const ERR_NOT_FOUND: i32 = -4;
+const ERR_TIMEOUT: i32 = -5;
+const ERR_TOO_LARGE: i32 = -6;
+
+/// the guest is trapped with these, as there is nowhere to write a code to
+/// ptr or len out of the guest memory
+const TRAP_BAD_MEMORY: u32 = 1;
+/// host function called outside of an invocation
+const TRAP_NO_FN_CTX: u32 = 2;
+/// the host failed to run the call
+const TRAP_HOST_FAILED: u32 = 3;
+/// wasi proc_exit, the invocation ends without a result
+const TRAP_PROC_EXIT: u32 = 4;
+
 trait HostFuncRegister {
     fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder;
 }
@@ -169,6 +279,42 @@ pub fn new_import_obj() -> ImportObject {
     let builder = KvFuncsRegister {}.register(builder);
     let builder = FsFuncsRegister {}.register(builder);
     let builder = ResultFuncsRegister.register(builder);
+    let builder = FnCallFuncsRegister.register(builder);
+    let builder = LogFuncsRegister.register(builder);
+    let builder = HttpFuncsRegister.register(builder);
 
     builder.build::<NeverType>("env", None).unwrap()
 }
+
+/// stdio of the guests goes to the fn logs instead of the host process
+#[cfg(target_os = "linux")]
+pub fn new_wasi_import_obj() -> ImportObject {
+    let builder = WasiFuncsRegister.register(ImportObjectBuilder::new());
+
+    builder
+        .build::<NeverType>("wasi_snapshot_preview1", None)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ws_sdk::abi;
+
+    #[test]
+    fn test_sdk_abi_consts() {
+        assert_eq!(abi::ABI_VERSION, WASM_ABI_VERSION);
+
+        assert_eq!(abi::ERR_FAILED, ERR_FAILED);
+        assert_eq!(abi::ERR_DENIED, ERR_DENIED);
+        assert_eq!(abi::ERR_INVALID, ERR_INVALID);
+        assert_eq!(abi::ERR_NOT_FOUND, ERR_NOT_FOUND);
+        assert_eq!(abi::ERR_TIMEOUT, ERR_TIMEOUT);
+        assert_eq!(abi::ERR_TOO_LARGE, ERR_TOO_LARGE);
+
+        assert_eq!(abi::FS_OPEN_WRITE, fs::OPEN_WRITE);
+        assert_eq!(abi::FS_OPEN_APPEND, fs::OPEN_APPEND);
+        assert_eq!(abi::FS_OPEN_CREATE, fs::OPEN_CREATE);
+        assert_eq!(abi::FS_OPEN_TRUNCATE, fs::OPEN_TRUNCATE);
+    }
+}
@@ -0,0 +1,92 @@
+//! results made by one host call and copied out by the next one of the guest,
+//! the id is only a handle, an entry is taken only by the invocation it's made for
+
+use crate::general::network::http_handler::ReqId;
+use moka::sync::Cache;
+use std::{
+    sync::atomic::{AtomicI32, Ordering},
+    time::Duration,
+};
+
+#[derive(Clone)]
+struct Owned<V> {
+    app: String,
+    req_id: ReqId,
+    value: V,
+}
+
+pub(super) struct InvokeResCache<V> {
+    cache: Cache<i32, Owned<V>>,
+    next_id: AtomicI32,
+}
+
+impl<V: Clone + Send + Sync + 'static> InvokeResCache<V> {
+    pub fn new(ttl: Duration, max_capacity: u64) -> Self {
+        Self {
+            cache: Cache::builder()
+                .time_to_live(ttl)
+                .max_capacity(max_capacity)
+                .build(),
+            next_id: AtomicI32::new(0),
+        }
+    }
+
+    pub fn insert(&self, app: &str, req_id: ReqId, value: V) -> i32 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) & i32::MAX;
+        self.cache.insert(
+            id,
+            Owned {
+                app: app.to_owned(),
+                req_id,
+                value,
+            },
+        );
+        id
+    }
+
+    /// none when the id is not made for the app and req, which leaves the entry there
+    pub fn take(&self, app: &str, req_id: ReqId, id: i32) -> Option<V> {
+        let owned = self.cache.get(&id)?;
+        if owned.app != app || owned.req_id != req_id {
+            tracing::warn!(
+                "app {} req {} takes the host call result {} of app {} req {}",
+                app,
+                req_id,
+                id,
+                owned.app,
+                owned.req_id
+            );
+            return None;
+        }
+        self.cache.remove(&id).map(|owned| owned.value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cache() -> InvokeResCache<String> {
+        InvokeResCache::new(Duration::from_secs(10), 16)
+    }
+
+    #[test]
+    fn test_take_by_owner() {
+        let cache = cache();
+        let id = cache.insert("app1", 1, "res".to_owned());
+        assert_eq!(cache.take("app1", 1, id), Some("res".to_owned()));
+        // removed on read
+        assert_eq!(cache.take("app1", 1, id), None);
+    }
+
+    #[test]
+    fn test_take_by_others() {
+        let cache = cache();
+        let id = cache.insert("app1", 1, "res".to_owned());
+        assert_eq!(cache.take("app2", 1, id), None);
+        assert_eq!(cache.take("app1", 2, id), None);
+        assert_eq!(cache.take("app1", 1, id + 1), None);
+        // still there for the owner
+        assert_eq!(cache.take("app1", 1, id), Some("res".to_owned()));
+    }
+}
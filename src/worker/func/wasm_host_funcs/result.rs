@@ -8,13 +8,14 @@ use wasmedge_sdk::{
     error::HostFuncError, host_function, Caller, ImportObjectBuilder, NeverType, WasmValue,
 };
 
-// fname_ptr, fname_len, fd_ptr
+// res_ptr, res_len
+// the result is a string, invalid utf8 is replaced
 type WriteResultArgs = (i32, i32);
 #[host_function]
 fn write_result(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
-    let fname = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32());
-    unsafe { utils::current_app_fn_ctx(&caller).0.as_mut() }.res =
-        Some(std::str::from_utf8(fname).unwrap().to_string());
+    let res = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    unsafe { utils::current_app_fn_ctx(&caller)?.0.as_mut() }.res =
+        Some(String::from_utf8_lossy(res).into_owned());
 
     Ok(vec![])
 }
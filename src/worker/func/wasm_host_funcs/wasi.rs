@@ -0,0 +1,290 @@
+//! `wasi_snapshot_preview1` for the guests, replacing the builtin wasi of wasmedge
+//! whose stdout and stderr are the ones of the host process.
+//!
+//! Only stdio is there (no args, envs or preopens, same as the default wasi ctx used before),
+//! fd 1 and 2 go to the fn logs tagged with the req id of the invocation.
+
+use super::{utils, HostFuncRegister, TRAP_PROC_EXIT};
+use crate::general::m_fn_logs::FnLogStream;
+use rand::RngCore;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(target_os = "linux")]
+use wasmedge_sdk::{
+    error::HostFuncError, host_function, Caller, ImportObjectBuilder, NeverType, WasmValue,
+};
+
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_NOSYS: i32 = 52;
+const ERRNO_SPIPE: i32 = 70;
+
+const CLOCK_REALTIME: i32 = 0;
+const CLOCK_MONOTONIC: i32 = 1;
+
+const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+const FDSTAT_SIZE: i32 = 24;
+
+lazy_static::lazy_static! {
+    static ref MONOTONIC_START: Instant = Instant::now();
+}
+
+fn errno(v: i32) -> Result<Vec<WasmValue>, HostFuncError> {
+    Ok(vec![WasmValue::from_i32(v)])
+}
+
+fn is_stdio(fd: i32) -> bool {
+    (0..=2).contains(&fd)
+}
+
+type FdWriteArgs = (i32, i32, i32, i32);
+/// fd, iovs_ptr, iovs_len, nwritten_ptr
+#[host_function]
+fn fd_write(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let stream = match args[0].to_i32() {
+        1 => FnLogStream::Stdout,
+        2 => FnLogStream::Stderr,
+        _ => return errno(ERRNO_BADF),
+    };
+    // each iov is buf_ptr, buf_len
+    let iovs = utils::i32slice(
+        &caller,
+        args[1].to_i32(),
+        args[2]
+            .to_i32()
+            .checked_mul(2)
+            .ok_or(HostFuncError::User(super::TRAP_BAD_MEMORY))?,
+    )?;
+    let mut text = Vec::new();
+    for iov in iovs.chunks_exact(2) {
+        text.extend_from_slice(utils::u8slice(&caller, iov[0], iov[1])?);
+    }
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller)?.0.as_ref() };
+    utils::m_fn_logs().push(
+        &fn_ctx.app,
+        &fn_ctx.func,
+        Some(fn_ctx.req_id),
+        stream,
+        &String::from_utf8_lossy(&text),
+    );
+    *utils::mutref::<i32>(&caller, args[3].to_i32())? = text.len() as i32;
+    errno(ERRNO_SUCCESS)
+}
+
+type FdReadArgs = (i32, i32, i32, i32);
+/// stdin is always at eof
+#[host_function]
+fn fd_read(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    if args[0].to_i32() != 0 {
+        return errno(ERRNO_BADF);
+    }
+    *utils::mutref::<i32>(&caller, args[3].to_i32())? = 0;
+    errno(ERRNO_SUCCESS)
+}
+
+type FdFdstatGetArgs = (i32, i32);
+#[host_function]
+fn fd_fdstat_get(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    if !is_stdio(args[0].to_i32()) {
+        return errno(ERRNO_BADF);
+    }
+    // filetype u8, flags u16, rights_base u64, rights_inheriting u64
+    let stat = utils::mutu8sclice(&caller, args[1].to_i32(), FDSTAT_SIZE)?;
+    stat.fill(0);
+    stat[0] = FILETYPE_CHARACTER_DEVICE;
+    stat[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+    errno(ERRNO_SUCCESS)
+}
+
+type FdArgs = i32;
+#[host_function]
+fn fd_close(_caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    if !is_stdio(args[0].to_i32()) {
+        return errno(ERRNO_BADF);
+    }
+    errno(ERRNO_SUCCESS)
+}
+
+type FdSeekArgs = (i32, i64, i32, i32);
+#[host_function]
+fn fd_seek(_caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    if !is_stdio(args[0].to_i32()) {
+        return errno(ERRNO_BADF);
+    }
+    errno(ERRNO_SPIPE)
+}
+
+type SizesGetArgs = (i32, i32);
+/// no args and envs, both the count and the buf size are 0
+#[host_function]
+fn sizes_get(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    *utils::mutref::<i32>(&caller, args[0].to_i32())? = 0;
+    *utils::mutref::<i32>(&caller, args[1].to_i32())? = 0;
+    errno(ERRNO_SUCCESS)
+}
+
+type ClockResGetArgs = (i32, i32);
+#[host_function]
+fn clock_res_get(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    match args[0].to_i32() {
+        CLOCK_REALTIME | CLOCK_MONOTONIC => {
+            *utils::mutref::<u64>(&caller, args[1].to_i32())? = 1;
+            errno(ERRNO_SUCCESS)
+        }
+        _ => errno(ERRNO_INVAL),
+    }
+}
+
+type ClockTimeGetArgs = (i32, i64, i32);
+#[host_function]
+fn clock_time_get(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let nanos = match args[0].to_i32() {
+        CLOCK_REALTIME => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0),
+        CLOCK_MONOTONIC => MONOTONIC_START.elapsed().as_nanos(),
+        _ => return errno(ERRNO_INVAL),
+    };
+    *utils::mutref::<u64>(&caller, args[2].to_i32())? = nanos as u64;
+    errno(ERRNO_SUCCESS)
+}
+
+type RandomGetArgs = (i32, i32);
+#[host_function]
+fn random_get(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let buf = utils::mutu8sclice(&caller, args[0].to_i32(), args[1].to_i32())?;
+    rand::thread_rng().fill_bytes(buf);
+    errno(ERRNO_SUCCESS)
+}
+
+/// nothing to write as there are no args and envs
+#[host_function]
+fn no_entries_get(_caller: Caller, _args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    errno(ERRNO_SUCCESS)
+}
+
+#[host_function]
+fn proc_exit(_caller: Caller, _args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    Err(HostFuncError::User(TRAP_PROC_EXIT))
+}
+
+#[host_function]
+fn sched_yield(_caller: Caller, _args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    errno(ERRNO_SUCCESS)
+}
+
+/// there is no fd other than stdio, also ends the preopen scan of the guest libc
+#[host_function]
+fn badf(_caller: Caller, _args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    errno(ERRNO_BADF)
+}
+
+#[host_function]
+fn nosys(_caller: Caller, _args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    errno(ERRNO_NOSYS)
+}
+
+pub(super) struct WasiFuncsRegister;
+
+impl HostFuncRegister for WasiFuncsRegister {
+    fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
+        // all of preview1 is there, or the guests importing any of the rest fail to instantiate
+        type I = i32;
+        type L = i64;
+        builder
+            .with_func::<FdWriteArgs, I, NeverType>("fd_write", fd_write, None)
+            .unwrap()
+            .with_func::<FdReadArgs, I, NeverType>("fd_read", fd_read, None)
+            .unwrap()
+            .with_func::<FdFdstatGetArgs, I, NeverType>("fd_fdstat_get", fd_fdstat_get, None)
+            .unwrap()
+            .with_func::<FdArgs, I, NeverType>("fd_close", fd_close, None)
+            .unwrap()
+            .with_func::<FdSeekArgs, I, NeverType>("fd_seek", fd_seek, None)
+            .unwrap()
+            .with_func::<SizesGetArgs, I, NeverType>("args_sizes_get", sizes_get, None)
+            .unwrap()
+            .with_func::<SizesGetArgs, I, NeverType>("environ_sizes_get", sizes_get, None)
+            .unwrap()
+            .with_func::<(I, I), I, NeverType>("args_get", no_entries_get, None)
+            .unwrap()
+            .with_func::<(I, I), I, NeverType>("environ_get", no_entries_get, None)
+            .unwrap()
+            .with_func::<ClockResGetArgs, I, NeverType>("clock_res_get", clock_res_get, None)
+            .unwrap()
+            .with_func::<ClockTimeGetArgs, I, NeverType>("clock_time_get", clock_time_get, None)
+            .unwrap()
+            .with_func::<RandomGetArgs, I, NeverType>("random_get", random_get, None)
+            .unwrap()
+            .with_func::<I, (), NeverType>("proc_exit", proc_exit, None)
+            .unwrap()
+            .with_func::<(), I, NeverType>("sched_yield", sched_yield, None)
+            .unwrap()
+            .with_func::<(I, I), I, NeverType>("fd_prestat_get", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I), I, NeverType>("fd_prestat_dir_name", badf, None)
+            .unwrap()
+            .with_func::<(I, L, L, I), I, NeverType>("fd_advise", badf, None)
+            .unwrap()
+            .with_func::<(I, L, L), I, NeverType>("fd_allocate", badf, None)
+            .unwrap()
+            .with_func::<I, I, NeverType>("fd_datasync", badf, None)
+            .unwrap()
+            .with_func::<(I, I), I, NeverType>("fd_fdstat_set_flags", badf, None)
+            .unwrap()
+            .with_func::<(I, L, L), I, NeverType>("fd_fdstat_set_rights", badf, None)
+            .unwrap()
+            .with_func::<(I, I), I, NeverType>("fd_filestat_get", badf, None)
+            .unwrap()
+            .with_func::<(I, L), I, NeverType>("fd_filestat_set_size", badf, None)
+            .unwrap()
+            .with_func::<(I, L, L, I), I, NeverType>("fd_filestat_set_times", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, L, I), I, NeverType>("fd_pread", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, L, I), I, NeverType>("fd_pwrite", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, L, I), I, NeverType>("fd_readdir", badf, None)
+            .unwrap()
+            .with_func::<(I, I), I, NeverType>("fd_renumber", badf, None)
+            .unwrap()
+            .with_func::<I, I, NeverType>("fd_sync", badf, None)
+            .unwrap()
+            .with_func::<(I, I), I, NeverType>("fd_tell", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I), I, NeverType>("path_create_directory", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, I), I, NeverType>("path_filestat_get", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, L, L, I), I, NeverType>("path_filestat_set_times", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, I, I, I), I, NeverType>("path_link", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, I, L, L, I, I), I, NeverType>("path_open", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, I, I), I, NeverType>("path_readlink", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I), I, NeverType>("path_remove_directory", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, I, I), I, NeverType>("path_rename", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, I), I, NeverType>("path_symlink", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I), I, NeverType>("path_unlink_file", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I), I, NeverType>("poll_oneoff", nosys, None)
+            .unwrap()
+            .with_func::<I, I, NeverType>("proc_raise", nosys, None)
+            .unwrap()
+            .with_func::<(I, I, I), I, NeverType>("sock_accept", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, I, I), I, NeverType>("sock_recv", badf, None)
+            .unwrap()
+            .with_func::<(I, I, I, I, I), I, NeverType>("sock_send", badf, None)
+            .unwrap()
+            .with_func::<(I, I), I, NeverType>("sock_shutdown", badf, None)
+            .unwrap()
+    }
+}
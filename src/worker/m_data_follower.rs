@@ -1,20 +1,28 @@
-
-
-use crate::general::m_data_general::{DataGeneral};
-use crate::general::m_kv_store_engine::{KvStoreEngine};
-use crate::general::network::m_p2p::{P2PModule, RPCHandler, RPCResponsor};
-use crate::general::network::proto::{self, DataVersionRequest, DataVersionResponse};
+use crate::general::m_data_general::{send_version_resp, DataGeneral, DataVersion};
+use crate::general::m_kv_store_engine::{KeyTypeDataSetMeta, KvStoreEngine};
+use crate::general::network::m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor};
+use crate::general::network::proto::{
+    self, DataModeDistribute, DataSetVersionsRequest, DataVersionPhase, DataVersionRequest,
+    DataVersionResponse,
+};
 use crate::result::{WSError, WSResult, WsDataError};
 use crate::sys::LogicalModulesRef;
 use crate::util::JoinHandleWrapper;
 use crate::{
     general::network::http_handler::HttpHandler,
-    logical_module_view_impl,
+    logging, logical_module_view_impl,
     sys::{LogicalModule, LogicalModuleNewArgs},
 };
 use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::Duration};
+use tracing::Instrument;
 use ws_derive::LogicalModule;
 
+/// wait for the connections to master before the first reconcile
+const DATA_RECONCILE_FIRST_DELAY: Duration = Duration::from_secs(5);
+const DATA_RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
 logical_module_view_impl!(DataFollowerView);
 logical_module_view_impl!(DataFollowerView, data_follower, Option<DataFollower>);
 logical_module_view_impl!(DataFollowerView, p2p, P2PModule);
@@ -26,6 +34,45 @@ logical_module_view_impl!(DataFollowerView, data_general, DataGeneral);
 pub struct DataFollower {
     view: DataFollowerView,
     rpc_handler: RPCHandler<proto::DataVersionRequest>,
+    rpc_call_data_set_versions: RPCCaller<proto::DataSetVersionsRequest>,
+    prepared: Mutex<PreparedVersions>,
+}
+
+/// unique id 2 version prepared by master but not committed yet
+#[derive(Default)]
+struct PreparedVersions(HashMap<String, DataVersion>);
+
+impl PreparedVersions {
+    /// err with the current version when the version is not newer
+    fn prepare(
+        &mut self,
+        unique_id: &str,
+        version: DataVersion,
+        cur_version: DataVersion,
+    ) -> Result<(), DataVersion> {
+        if cur_version >= version {
+            return Err(cur_version);
+        }
+        let _ = self.0.insert(unique_id.to_owned(), version);
+        Ok(())
+    }
+    /// only the version prepared here could be committed, err with the prepared one
+    fn check_commit(
+        &self,
+        unique_id: &str,
+        version: DataVersion,
+    ) -> Result<(), Option<DataVersion>> {
+        match self.0.get(unique_id) {
+            Some(v) if *v == version => Ok(()),
+            prepared => Err(prepared.copied()),
+        }
+    }
+    /// when aborted or committed, a stale one won't drop the version prepared after it
+    fn finish(&mut self, unique_id: &str, version: DataVersion) {
+        if self.0.get(unique_id) == Some(&version) {
+            let _ = self.0.remove(unique_id);
+        }
+    }
 }
 
 #[async_trait]
@@ -36,6 +83,8 @@ impl LogicalModule for DataFollower {
     {
         Self {
             rpc_handler: RPCHandler::new(),
+            rpc_call_data_set_versions: RPCCaller::new(),
+            prepared: Mutex::new(PreparedVersions::default()),
             view: DataFollowerView::new(args.logical_modules_ref.clone()),
             // view: DataFollowerView::new(args.logical_modules_ref.clone()),
         }
@@ -50,19 +99,97 @@ impl LogicalModule for DataFollower {
         self.rpc_handler
             .regist(self.view.p2p(), move |responsor, req| {
                 let view = view.clone();
-                let _ = tokio::spawn(async move {
-                    view.data_follower()
-                        .rpc_handler_dataversion(responsor, req)
-                        .await
-                });
+                let span = tracing::info_span!("data_version", data = %req.unique_id);
+                logging::set_remote_parent(&span, &req.trace_ctx);
+                let _ = tokio::spawn(
+                    async move {
+                        view.data_follower()
+                            .rpc_handler_dataversion(responsor, req)
+                            .await
+                    }
+                    .instrument(span),
+                );
 
                 Ok(())
             });
-        Ok(vec![])
+        self.rpc_call_data_set_versions.regist(self.view.p2p());
+        let view = self.view.clone();
+        Ok(vec![JoinHandleWrapper::from(tokio::spawn(async move {
+            reconcile_task(view).await;
+        }))])
+    }
+}
+
+/// compare with master at startup and periodically,
+/// so that a node missed the broadcasts catches up without a re-upload
+async fn reconcile_task(view: DataFollowerView) {
+    tokio::time::sleep(DATA_RECONCILE_FIRST_DELAY).await;
+    loop {
+        if let Err(e) = view.data_follower().reconcile().await {
+            tracing::warn!("reconcile data with master failed: {:?}", e);
+        }
+        tokio::time::sleep(DATA_RECONCILE_INTERVAL).await;
     }
 }
 
 impl DataFollower {
+    async fn reconcile(&self) -> WSResult<()> {
+        let p2p = self.view.p2p();
+        let resp = self
+            .rpc_call_data_set_versions
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                DataSetVersionsRequest {},
+                Some(Duration::from_secs(30)),
+            )
+            .await?;
+        let data_general = self.view.data_general();
+        for set in resp.sets {
+            let local_version = self
+                .view
+                .kv_store_engine()
+                .get(KeyTypeDataSetMeta(set.unique_id.as_bytes()))
+                .map_or(0, |meta| meta.version);
+            if local_version > set.version {
+                tracing::warn!(
+                    "data({}) local version {} is newer than master {}",
+                    set.unique_id,
+                    local_version,
+                    set.version
+                );
+                continue;
+            }
+            let broadcast = set.data_metas.first().map_or(false, |meta| {
+                meta.distribute == DataModeDistribute::BroadcastRough as i32
+            });
+            if local_version < set.version {
+                tracing::debug!(
+                    "data({}) catch up version {} -> {}",
+                    set.unique_id,
+                    local_version,
+                    set.version
+                );
+                data_general
+                    .set_dataversion(DataVersionRequest {
+                        unique_id: set.unique_id.clone(),
+                        version: set.version,
+                        data_metas: set.data_metas,
+                        phase: DataVersionPhase::Commit as i32,
+                        trace_ctx: Default::default(),
+                    })
+                    .await?;
+            }
+            // the others are pulled when they are needed
+            if broadcast && !data_general.data_synced_locally(&set.unique_id) {
+                if let Err(e) = data_general.pull_data(&set.unique_id).await {
+                    tracing::warn!("reconcile pull data({}) failed: {:?}", set.unique_id, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
     async fn rpc_handler_dataversion(
         &self,
         responsor: RPCResponsor<DataVersionRequest>,
@@ -70,31 +197,145 @@ impl DataFollower {
     ) -> WSResult<()> {
         let targetv = req.version;
         tracing::debug!(
-            "follower receive version({}) for data({})",
+            "follower receive version({}) phase({}) for data({})",
             req.version,
+            req.phase,
             req.unique_id
         );
 
-        if let Err(e) = self.view.data_general().set_dataversion(req).await {
-            tracing::warn!("set_dataversion failed, err:{:?}", e);
-            let cur_version = match e {
-                WSError::WsDataError(WsDataError::SetExpiredDataVersion {
-                    cur_version, ..
-                }) => cur_version,
-                _ => 0,
-            };
-            responsor
-                .send_resp(DataVersionResponse {
-                    version: cur_version,
-                })
-                .await;
-            return Err(e);
+        match DataVersionPhase::from_i32(req.phase) {
+            Some(DataVersionPhase::Prepare) => {
+                let cur_version = self
+                    .view
+                    .kv_store_engine()
+                    .get(KeyTypeDataSetMeta(req.unique_id.as_bytes()))
+                    .map_or(0, |meta| meta.version);
+                let prepared = self
+                    .prepared
+                    .lock()
+                    .prepare(&req.unique_id, targetv, cur_version);
+                if let Err(cur_version) = prepared {
+                    tracing::warn!(
+                        "prepare expired version({}) for data({}), cur: {}",
+                        targetv,
+                        req.unique_id,
+                        cur_version
+                    );
+                    let resp = DataVersionResponse {
+                        version: cur_version,
+                        error: "expired version".to_owned(),
+                    };
+                    send_version_resp(&responsor, resp).await;
+                    return Ok(());
+                }
+            }
+            Some(DataVersionPhase::Abort) => {
+                self.prepared.lock().finish(&req.unique_id, targetv);
+            }
+            Some(DataVersionPhase::Commit) => {
+                let unique_id = req.unique_id.clone();
+                // only the version prepared here, the missed ones are caught up by reconciling
+                let prepared = self.prepared.lock().check_commit(&unique_id, targetv);
+                if let Err(prepared) = prepared {
+                    tracing::warn!(
+                        "commit version({}) for data({}) not prepared, prepared: {:?}",
+                        targetv,
+                        unique_id,
+                        prepared
+                    );
+                    let resp = DataVersionResponse {
+                        version: 0,
+                        error: format!("version {} not prepared", targetv),
+                    };
+                    send_version_resp(&responsor, resp).await;
+                    return Ok(());
+                }
+                if let Err(e) = self.view.data_general().set_dataversion(req).await {
+                    tracing::warn!("set_dataversion failed, err:{:?}", e);
+                    let cur_version = match e {
+                        WSError::WsDataError(WsDataError::SetExpiredDataVersion {
+                            cur_version,
+                            ..
+                        }) => cur_version,
+                        _ => 0,
+                    };
+                    let resp = DataVersionResponse {
+                        version: cur_version,
+                        error: format!("{:?}", e),
+                    };
+                    send_version_resp(&responsor, resp).await;
+                    return Err(e);
+                }
+                self.prepared.lock().finish(&unique_id, targetv);
+            }
+            Some(DataVersionPhase::Reconcile) => {
+                let view = self.view.clone();
+                let _ = tokio::spawn(
+                    async move {
+                        if let Err(e) = view.data_follower().reconcile().await {
+                            tracing::warn!("reconcile data with master failed: {:?}", e);
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+            None => {
+                let resp = DataVersionResponse {
+                    version: 0,
+                    error: format!("unknown phase {}", req.phase),
+                };
+                send_version_resp(&responsor, resp).await;
+                return Ok(());
+            }
         }
 
         tracing::debug!("follower updated version({})", targetv);
-        responsor
-            .send_resp(DataVersionResponse { version: targetv })
-            .await;
+        let resp = DataVersionResponse {
+            version: targetv,
+            error: "".to_owned(),
+        };
+        send_version_resp(&responsor, resp).await;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prepare_commit() {
+        let mut prepared = PreparedVersions::default();
+        assert_eq!(prepared.check_commit("d", 2), Err(None));
+        assert_eq!(prepared.prepare("d", 2, 1), Ok(()));
+        assert_eq!(prepared.check_commit("d", 3), Err(Some(2)));
+        assert_eq!(prepared.check_commit("d", 2), Ok(()));
+        prepared.finish("d", 2);
+        // committed, not again
+        assert_eq!(prepared.check_commit("d", 2), Err(None));
+    }
+
+    #[test]
+    fn test_prepare_expired() {
+        let mut prepared = PreparedVersions::default();
+        assert_eq!(prepared.prepare("d", 2, 2), Err(2));
+        assert_eq!(prepared.prepare("d", 2, 3), Err(3));
+        assert_eq!(prepared.check_commit("d", 2), Err(None));
+    }
+
+    #[test]
+    fn test_abort() {
+        let mut prepared = PreparedVersions::default();
+        assert_eq!(prepared.prepare("d", 2, 1), Ok(()));
+        prepared.finish("d", 2);
+        assert_eq!(prepared.check_commit("d", 2), Err(None));
+
+        // the abort of a stale version keeps the newer one prepared
+        assert_eq!(prepared.prepare("d", 3, 1), Ok(()));
+        prepared.finish("d", 2);
+        assert_eq!(prepared.check_commit("d", 3), Ok(()));
+        // the others are not touched
+        prepared.finish("e", 3);
+        assert_eq!(prepared.check_commit("d", 3), Ok(()));
+    }
+}
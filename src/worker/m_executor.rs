@@ -1,33 +1,42 @@
 use crate::{
     general::{
-        m_appmeta_manager::AppMetaManager,
+        m_appmeta_manager::{limits::InvokeLimits, AppMeta, AppMetaManager, AppType, FnMeta},
+        m_fn_logs::{FnLogStream, FnLogs},
+        m_os::OperatingSystem,
         network::{
             http_handler::ReqId,
-            m_p2p::{P2PModule, RPCHandler, RPCResponsor},
+            m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor},
             proto::{
                 self,
-                sche::{distribute_task_req, DistributeTaskResp},
+                sche::{call_fn_resp, distribute_task_req, CallFnResp, DistributeTaskResp},
             },
         },
     },
-    logical_module_view_impl,
+    logging, logical_module_view_impl,
     result::{WSResult, WsFuncError},
-    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
-    worker::func::{m_instance_manager::UnsafeFunctionCtx, EventCtx, FnExeCtx, InstanceTrait},
+    worker::func::{
+        m_instance_manager::UnsafeFunctionCtx, EventCtx, FnExeCtx, Instance, InstanceTrait,
+    },
 };
 use async_trait::async_trait;
+use rand::Rng;
 
 use std::{
     ptr::NonNull,
     sync::atomic::{AtomicU32, AtomicUsize},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::oneshot;
+use tracing::Instrument;
 #[cfg(target_os = "linux")]
 use ws_derive::LogicalModule;
 
-use super::func::m_instance_manager::InstanceManager;
+use super::{
+    func::m_instance_manager::InstanceManager,
+    m_fn_metrics::{FnInvocationRecord, FnMetrics},
+};
 
 pub type SubTaskId = u32;
 
@@ -40,11 +49,16 @@ logical_module_view_impl!(ExecutorView, p2p, P2PModule);
 logical_module_view_impl!(ExecutorView, appmeta_manager, AppMetaManager);
 logical_module_view_impl!(ExecutorView, instance_manager, Option<InstanceManager>);
 logical_module_view_impl!(ExecutorView, executor, Option<Executor>);
+logical_module_view_impl!(ExecutorView, fn_metrics, Option<FnMetrics>);
+logical_module_view_impl!(ExecutorView, fn_logs, FnLogs);
+logical_module_view_impl!(ExecutorView, os, OperatingSystem);
 
 #[derive(LogicalModule)]
 pub struct Executor {
     sub_task_id: AtomicU32,
     rpc_handler_distribute_task: RPCHandler<proto::sche::DistributeTaskReq>,
+    rpc_handler_call_fn: RPCHandler<proto::sche::CallFnReq>,
+    rpc_caller_call_fn: RPCCaller<proto::sche::CallFnReq>,
     next_req_id: AtomicUsize,
     view: ExecutorView,
 }
@@ -80,6 +94,8 @@ impl LogicalModule for Executor {
     {
         Self {
             rpc_handler_distribute_task: RPCHandler::default(),
+            rpc_handler_call_fn: RPCHandler::default(),
+            rpc_caller_call_fn: RPCCaller::default(),
             view: ExecutorView::new(args.logical_modules_ref.clone()),
             sub_task_id: AtomicU32::new(0),
             next_req_id: AtomicUsize::new(0),
@@ -92,19 +108,39 @@ impl LogicalModule for Executor {
             move |responser, r| {
                 // tracing::info!("rpc recv: {:?}", r);
                 let view = view.clone();
-                let _ = tokio::spawn(async move {
-                    view.executor().handle_distribute_task(responser, r).await;
-
-                    // if let Err(err) = responser
-                    //     .send_resp(proto::sche::DistributeTaskResp {})
-                    //     .await
-                    // {
-                    //     tracing::error!("send sche resp failed with err: {}", err);
-                    // }
-                });
+                let span = tracing::info_span!("distribute_task", app = %r.app, func = %r.func);
+                logging::set_remote_parent(&span, &r.trace_ctx);
+                let _ = tokio::spawn(
+                    async move {
+                        view.executor().handle_distribute_task(responser, r).await;
+
+                        // if let Err(err) = responser
+                        //     .send_resp(proto::sche::DistributeTaskResp {})
+                        //     .await
+                        // {
+                        //     tracing::error!("send sche resp failed with err: {}", err);
+                        // }
+                    }
+                    .instrument(span),
+                );
                 Ok(())
             },
         );
+        self.rpc_caller_call_fn.regist(self.view.p2p());
+        let view = self.view.clone();
+        self.rpc_handler_call_fn
+            .regist(self.view.p2p(), move |responser, r| {
+                let view = view.clone();
+                let span = tracing::info_span!("remote_fn_call", app = %r.app, func = %r.func);
+                logging::set_remote_parent(&span, &r.trace_ctx);
+                let _ = tokio::spawn(
+                    async move {
+                        view.executor().handle_remote_fn_call(responser, r).await;
+                    }
+                    .instrument(span),
+                );
+                Ok(())
+            });
         // self.view
         //     .p2p()
         //     .regist_rpc::<proto::sche::ScheReq, _>();
@@ -125,6 +161,7 @@ impl Executor {
         req: proto::sche::DistributeTaskReq,
     ) {
         tracing::debug!("receive distribute task: {:?}", req);
+        let arrive_time = Instant::now();
         let app = req.app.to_owned();
         let func = req.func.to_owned();
         let (apptype, fnmeta) = {
@@ -154,6 +191,10 @@ impl Executor {
                 },
             },
             sub_waiters: vec![],
+            arrive_time,
+            kv_time: Duration::ZERO,
+            span: tracing::Span::none(),
+            fds: vec![],
         };
         if let Err(err) = resp.send_resp(DistributeTaskResp {}).await {
             tracing::error!("send sche resp for app:{app} fn:{func} failed with err: {err}");
@@ -161,7 +202,144 @@ impl Executor {
         let _ = self.execute(ctx).await;
     }
 
+    /// function to function call, only the functions declared with `rpc` can be called.
+    /// run here if the app is warm, otherwise dispatch to a random worker.
+    pub async fn handle_fn_call(
+        &self,
+        app: &str,
+        func: &str,
+        arg: String,
+    ) -> WSResult<Option<String>> {
+        let (app_type, func_meta, app_limits) = self.rpc_fn_meta(app, func).await?;
+
+        let p2p = self.view.p2p();
+        let target = fn_call_target(
+            p2p.nodes_config.this_node(),
+            self.view.instance_manager().is_warm(app),
+            p2p.nodes_config.get_worker_nodes().into_iter().collect(),
+        );
+        if target == p2p.nodes_config.this_node() {
+            return self
+                .execute_fn_call(app, func, app_type, func_meta, &app_limits, arg)
+                .await;
+        }
+
+        tracing::debug!("dispatch fn call {}/{} to node {}", app, func, target);
+        let resp = self
+            .rpc_caller_call_fn
+            .call(
+                p2p,
+                target,
+                proto::sche::CallFnReq {
+                    app: app.to_owned(),
+                    func: func.to_owned(),
+                    arg_str: arg,
+                    trace_ctx: Default::default(),
+                },
+                Some(Duration::from_secs(20)),
+            )
+            .await?;
+        match resp.dispatch {
+            None => Ok(None),
+            Some(call_fn_resp::Dispatch::RetStr(ret)) => Ok(Some(ret)),
+            Some(call_fn_resp::Dispatch::Err(err)) => Err(WsFuncError::FuncRemoteCallFail {
+                app: app.to_owned(),
+                func: func.to_owned(),
+                err,
+            }
+            .into()),
+        }
+    }
+
+    async fn handle_remote_fn_call(
+        &self,
+        resp: RPCResponsor<proto::sche::CallFnReq>,
+        req: proto::sche::CallFnReq,
+    ) {
+        tracing::debug!("receive fn call: {:?}", req);
+        // the caller already chose this node, run here whether warm or not
+        let res = match self.rpc_fn_meta(&req.app, &req.func).await {
+            Ok((app_type, func_meta, app_limits)) => {
+                self.execute_fn_call(
+                    &req.app,
+                    &req.func,
+                    app_type,
+                    func_meta,
+                    &app_limits,
+                    req.arg_str,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        let dispatch = match res {
+            Ok(ret) => ret.map(call_fn_resp::Dispatch::RetStr),
+            Err(err) => Some(call_fn_resp::Dispatch::Err(format!("{:?}", err))),
+        };
+        if let Err(err) = resp.send_resp(CallFnResp { dispatch }).await {
+            tracing::error!(
+                "send fn call resp for app:{} fn:{} failed with err: {}",
+                req.app,
+                req.func,
+                err
+            );
+        }
+    }
+
+    /// also the limits of the app
+    async fn rpc_fn_meta(
+        &self,
+        app: &str,
+        func: &str,
+    ) -> WSResult<(AppType, FnMeta, InvokeLimits)> {
+        let app_meta_man = self.view.appmeta_manager().meta.read().await;
+        let appmeta = app_meta_man.get_app_meta(app).await;
+        rpc_fn_meta_of(app, func, appmeta.as_deref())
+    }
+
+    /// limited on the node running it, same as the http calls
+    async fn execute_fn_call(
+        &self,
+        app: &str,
+        func: &str,
+        app_type: AppType,
+        func_meta: FnMeta,
+        app_limits: &InvokeLimits,
+        arg: String,
+    ) -> WSResult<Option<String>> {
+        let _in_flight = self
+            .view
+            .appmeta_manager()
+            .limiter
+            .acquire(app, func, app_limits, &func_meta.limits)
+            .map_err(|throttled| WsFuncError::FuncThrottled {
+                app: app.to_owned(),
+                func: func.to_owned(),
+                throttled,
+            })?;
+        let arrive_time = Instant::now();
+        let req_id: ReqId = self
+            .next_req_id
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let ctx = FnExeCtx {
+            app: app.to_owned(),
+            app_type,
+            func: func.to_owned(),
+            req_id,
+            res: None,
+            event_ctx: EventCtx::Rpc(arg),
+            sub_waiters: vec![],
+            arrive_time,
+            kv_time: Duration::ZERO,
+            span: tracing::Span::none(),
+            fds: vec![],
+            func_meta,
+        };
+        self.execute(ctx).await
+    }
+
     pub async fn handle_http_task(&self, route: &str, text: String) -> WSResult<Option<String>> {
+        let arrive_time = Instant::now();
         let req_id: ReqId = self
             .next_req_id
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -240,6 +418,10 @@ impl Executor {
             res: None,
             event_ctx: EventCtx::Http(text),
             sub_waiters: vec![],
+            arrive_time,
+            kv_time: Duration::ZERO,
+            span: tracing::Span::none(),
+            fds: vec![],
             func_meta: func.clone(),
         };
         drop(app_meta_man);
@@ -275,16 +457,34 @@ impl Executor {
     //     //     .finish_using(&sche_req.app, vm)
     //     //     .await
     // }
+    #[tracing::instrument(
+        skip_all,
+        fields(app = %fn_ctx.app, func = %fn_ctx.func, req_id = fn_ctx.req_id)
+    )]
     async fn execute(&self, mut fn_ctx: FnExeCtx) -> WSResult<Option<String>> {
+        // host functions may run out of this span
+        fn_ctx.span = tracing::Span::current();
         // let app = fn_ctx.app.clone();
         // let func = fn_ctx.func.clone();
         // let event = fn_ctx.event_ctx.clone();
 
-        let instance = self
+        let bf_instance = Instant::now();
+        let (instance, start) = self
             .view
             .instance_manager()
             .load_instance(&fn_ctx.app_type, &fn_ctx.app)
             .await;
+        // a process is ready once it reports started
+        if let Instance::Shared(shared) = &instance {
+            let _ = shared.0.wait_for_verify().await;
+        }
+        let instance_time = bf_instance.elapsed();
+        tracing::debug!(
+            "app {} got instance by {} start in {:?}",
+            fn_ctx.app,
+            start.as_str(),
+            instance_time
+        );
 
         let _ = self
             .view
@@ -310,7 +510,28 @@ impl Executor {
             .expect("Time went backwards")
             .as_millis() as u64;
 
+        let bf_exec = Instant::now();
         let res = instance.execute(&mut fn_ctx).await;
+        let exec_time = bf_exec.elapsed();
+        if let Err(e) = &res {
+            self.view.fn_logs().push(
+                &fn_ctx.app,
+                &fn_ctx.func,
+                Some(fn_ctx.req_id),
+                FnLogStream::Stderr,
+                &format!("invocation failed: {:?}", e),
+            );
+        }
+        self.view.fn_metrics().record(&FnInvocationRecord {
+            app: fn_ctx.app.clone(),
+            func: fn_ctx.func.clone(),
+            ok: res.is_ok(),
+            queue: bf_instance.duration_since(fn_ctx.arrive_time),
+            start,
+            instance: instance_time,
+            exec: exec_time,
+            kv: fn_ctx.kv_time,
+        });
 
         // let return_to_agent_time = SystemTime::now()
         //     .duration_since(UNIX_EPOCH)
@@ -319,11 +540,31 @@ impl Executor {
 
         let res = res.map(|v| {
             v.map(|v| {
-                let mut res: serde_json::Value = serde_json::from_str(&v).unwrap();
-                let _ = res.as_object_mut().unwrap().insert(
+                // direct http apps may return anything, only inject into json object
+                let Ok(mut res) = serde_json::from_str::<serde_json::Value>(&v) else {
+                    return v;
+                };
+                let Some(obj) = res.as_object_mut() else {
+                    return v;
+                };
+                let _ = obj.insert(
                     "bf_exec_time".to_owned(),
                     serde_json::Value::from(bf_exec_time),
                 );
+                // to fetch the logs of this invocation
+                let _ = obj.insert(
+                    "node_id".to_owned(),
+                    serde_json::Value::from(self.view.p2p().nodes_config.this_node()),
+                );
+                let _ = obj.insert("req_id".to_owned(), serde_json::Value::from(fn_ctx.req_id));
+                let _ = obj.insert(
+                    "start_type".to_owned(),
+                    serde_json::Value::from(start.as_str()),
+                );
+                let _ = obj.insert(
+                    "instance_time".to_owned(),
+                    serde_json::Value::from(instance_time.as_millis() as u64),
+                );
                 // let _ = res.as_object_mut().unwrap().insert(
                 //     "return_to_agent_time".to_owned(),
                 //     serde_json::Value::from(return_to_agent_time),
@@ -339,6 +580,7 @@ impl Executor {
             .write()
             .remove(&instance.instance_name())
             .unwrap();
+        self.view.os().close_files(&fn_ctx.fds);
 
         tracing::debug!(
             "finish run instance {} fn {}, res:{:?}",
@@ -359,3 +601,93 @@ impl Executor {
         // TODO：wait for related tasks triggered.
     }
 }
+
+/// warm on this node runs here, otherwise on any of the workers, this node included
+fn fn_call_target(this_node: NodeID, warm: bool, mut workers: Vec<NodeID>) -> NodeID {
+    if warm {
+        return this_node;
+    }
+    workers.push(this_node);
+    workers[rand::thread_rng().gen_range(0..workers.len())]
+}
+
+fn rpc_fn_meta_of(
+    app: &str,
+    func: &str,
+    appmeta: Option<&AppMeta>,
+) -> WSResult<(AppType, FnMeta, InvokeLimits)> {
+    let Some(appmeta) = appmeta else {
+        tracing::warn!("app {} not found", app);
+        return Err(WsFuncError::AppNotFound {
+            app: app.to_owned(),
+        }
+        .into());
+    };
+    let Some(fnmeta) = appmeta.get_fn_meta(func) else {
+        tracing::warn!("func {} not found, exist:{:?}", func, appmeta.fns());
+        return Err(WsFuncError::FuncNotFound {
+            app: app.to_owned(),
+            func: func.to_owned(),
+        }
+        .into());
+    };
+    if !fnmeta.allow_rpc_call() {
+        tracing::warn!("func {} not support rpc call, meta:{:?}", func, fnmeta);
+        return Err(WsFuncError::FuncRpcNotSupported {
+            app: app.to_owned(),
+            func: func.to_owned(),
+        }
+        .into());
+    }
+    Ok((appmeta.app_type.clone(), fnmeta.clone(), appmeta.limits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{general::m_appmeta_manager::AppMetaYaml, result::WSError};
+
+    fn app_meta() -> AppMeta {
+        let yaml: AppMetaYaml = serde_yaml::from_str(
+            r#"
+fns:
+  rpc_fn:
+    rpc:
+  http_fn:
+    http.get:
+      call: indirect
+"#,
+        )
+        .unwrap();
+        AppMeta::from_yaml(yaml, AppType::Wasm)
+    }
+
+    #[test]
+    fn test_rpc_fn_meta() {
+        let appmeta = app_meta();
+        assert!(rpc_fn_meta_of("app", "rpc_fn", Some(&appmeta)).is_ok());
+        assert!(matches!(
+            rpc_fn_meta_of("app", "http_fn", Some(&appmeta)),
+            Err(WSError::WsFuncError(
+                WsFuncError::FuncRpcNotSupported { .. }
+            ))
+        ));
+        assert!(matches!(
+            rpc_fn_meta_of("app", "nofn", Some(&appmeta)),
+            Err(WSError::WsFuncError(WsFuncError::FuncNotFound { .. }))
+        ));
+        assert!(matches!(
+            rpc_fn_meta_of("app", "rpc_fn", None),
+            Err(WSError::WsFuncError(WsFuncError::AppNotFound { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_fn_call_target() {
+        assert_eq!(fn_call_target(1, true, vec![2, 3]), 1);
+        assert_eq!(fn_call_target(1, false, vec![]), 1);
+        for _ in 0..20 {
+            assert!([1, 2, 3].contains(&fn_call_target(1, false, vec![2, 3])));
+        }
+    }
+}
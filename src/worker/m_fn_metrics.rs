@@ -0,0 +1,220 @@
+//! Per function invocation metrics of this worker, exported at `/metrics`.
+
+use crate::{
+    general::network::http_handler::{ApiRole, HttpHandler},
+    logical_module_view_impl,
+    result::WSResult,
+    sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef},
+    util::JoinHandleWrapper,
+};
+use async_trait::async_trait;
+use axum::{
+    extract::Extension,
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet, EncodeLabelValue},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use std::{sync::OnceLock, time::Duration};
+use ws_derive::LogicalModule;
+
+logical_module_view_impl!(FnMetricsView);
+logical_module_view_impl!(FnMetricsView, http_handler, Box<dyn HttpHandler>);
+logical_module_view_impl!(FnMetricsView, fn_metrics, Option<FnMetrics>);
+
+lazy_static::lazy_static! {
+    static ref VIEW: OnceLock<FnMetricsView> = OnceLock::new();
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FnLabels {
+    pub app: String,
+    pub func: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum FnResult {
+    Ok,
+    Err,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum StartType {
+    Warm,
+    Cold,
+    /// java process restored from a CRaC checkpoint
+    CracRestore,
+}
+
+impl StartType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StartType::Warm => "warm",
+            StartType::Cold => "cold",
+            StartType::CracRestore => "crac_restore",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FnResultLabels {
+    pub app: String,
+    pub func: String,
+    pub result: FnResult,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FnStartLabels {
+    pub app: String,
+    pub func: String,
+    pub start: StartType,
+}
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+fn new_histogram() -> Histogram {
+    // 1ms to about 33s
+    Histogram::new(exponential_buckets(0.001, 2.0, 16))
+}
+
+/// timings of one invocation
+pub struct FnInvocationRecord {
+    pub app: String,
+    pub func: String,
+    pub ok: bool,
+    /// from arriving to getting the instance
+    pub queue: Duration,
+    pub start: StartType,
+    /// getting the instance ready
+    pub instance: Duration,
+    pub exec: Duration,
+    /// spent in kv operations during exec
+    pub kv: Duration,
+}
+
+#[derive(LogicalModule)]
+pub struct FnMetrics {
+    view: FnMetricsView,
+    registry: Registry,
+    invocations: Family<FnResultLabels, Counter>,
+    queue_seconds: HistogramFamily<FnLabels>,
+    instance_seconds: HistogramFamily<FnStartLabels>,
+    exec_seconds: HistogramFamily<FnLabels>,
+    kv_seconds: HistogramFamily<FnLabels>,
+}
+
+#[async_trait]
+impl LogicalModule for FnMetrics {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        let mut registry = Registry::default();
+        let invocations = Family::<FnResultLabels, Counter>::default();
+        let queue_seconds = HistogramFamily::<FnLabels>::new_with_constructor(new_histogram);
+        let instance_seconds =
+            HistogramFamily::<FnStartLabels>::new_with_constructor(new_histogram);
+        let exec_seconds = HistogramFamily::<FnLabels>::new_with_constructor(new_histogram);
+        let kv_seconds = HistogramFamily::<FnLabels>::new_with_constructor(new_histogram);
+        registry.register(
+            "fn_invocations",
+            "Function invocations by result",
+            invocations.clone(),
+        );
+        registry.register(
+            "fn_queue_seconds",
+            "Time from arriving to getting the instance",
+            queue_seconds.clone(),
+        );
+        registry.register(
+            "fn_instance_seconds",
+            "Time to get the instance ready, by start type",
+            instance_seconds.clone(),
+        );
+        registry.register(
+            "fn_exec_seconds",
+            "Function execution time",
+            exec_seconds.clone(),
+        );
+        registry.register(
+            "fn_kv_seconds",
+            "Time spent in kv operations during execution",
+            kv_seconds.clone(),
+        );
+        Self {
+            view: FnMetricsView::new(args.logical_modules_ref.clone()),
+            registry,
+            invocations,
+            queue_seconds,
+            instance_seconds,
+            exec_seconds,
+            kv_seconds,
+        }
+    }
+    async fn init(&self) -> WSResult<()> {
+        let _ = VIEW.get_or_init(|| self.view.clone());
+        let mut router = self.view.http_handler().building_router();
+        let take = router.option_mut().take().unwrap();
+        let take = take.route("/metrics", get(http_metrics));
+        let _ = router.option_mut().replace(take);
+        Ok(())
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        Ok(vec![])
+    }
+}
+
+async fn http_metrics(Extension(role): Extension<ApiRole>) -> Response {
+    if role < ApiRole::Invoke {
+        return (StatusCode::UNAUTHORIZED, "api key required").into_response();
+    }
+    let mut body = String::new();
+    encode(&mut body, &VIEW.get().unwrap().fn_metrics().registry).unwrap();
+    let mut resp = (StatusCode::OK, body).into_response();
+    let _ = resp.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("application/openmetrics-text; version=1.0.0; charset=utf-8"),
+    );
+    resp
+}
+
+impl FnMetrics {
+    pub fn record(&self, rec: &FnInvocationRecord) {
+        let labels = FnLabels {
+            app: rec.app.clone(),
+            func: rec.func.clone(),
+        };
+        let _ = self
+            .invocations
+            .get_or_create(&FnResultLabels {
+                app: rec.app.clone(),
+                func: rec.func.clone(),
+                result: if rec.ok { FnResult::Ok } else { FnResult::Err },
+            })
+            .inc();
+        self.queue_seconds
+            .get_or_create(&labels)
+            .observe(rec.queue.as_secs_f64());
+        self.instance_seconds
+            .get_or_create(&FnStartLabels {
+                app: rec.app.clone(),
+                func: rec.func.clone(),
+                start: rec.start,
+            })
+            .observe(rec.instance.as_secs_f64());
+        self.exec_seconds
+            .get_or_create(&labels)
+            .observe(rec.exec.as_secs_f64());
+        self.kv_seconds
+            .get_or_create(&labels)
+            .observe(rec.kv.as_secs_f64());
+    }
+}
@@ -2,6 +2,7 @@
 pub mod func;
 pub mod m_data_follower;
 pub mod m_executor;
+pub mod m_fn_metrics;
 pub mod m_http_handler;
 pub mod m_kv_user_client;
 pub mod m_worker;
@@ -0,0 +1,75 @@
+//! The raw host functions, kept in sync with `src/worker/func/wasm_host_funcs` of the host.
+//!
+//! Pointers and lens are i32 offsets into the guest memory.
+
+/// checked by the host against its own before running the guest
+pub const ABI_VERSION: i32 = 2;
+
+/// written where a len, id or fd is expected when the call is not done
+pub const ERR_FAILED: i32 = -1;
+pub const ERR_DENIED: i32 = -2;
+pub const ERR_INVALID: i32 = -3;
+pub const ERR_NOT_FOUND: i32 = -4;
+pub const ERR_TIMEOUT: i32 = -5;
+pub const ERR_TOO_LARGE: i32 = -6;
+
+pub const KV_SET: i32 = 1;
+pub const KV_GET: i32 = 2;
+pub const KV_LOCK: i32 = 3;
+pub const KV_DELETE: i32 = 4;
+
+pub const FS_OPEN_WRITE: i32 = 1;
+pub const FS_OPEN_APPEND: i32 = 1 << 1;
+pub const FS_OPEN_CREATE: i32 = 1 << 2;
+pub const FS_OPEN_TRUNCATE: i32 = 1 << 3;
+
+#[link(wasm_import_module = "env")]
+extern "C" {
+    /// args: ope count, then per ope
+    /// set `1, k_ptr, k_len, v_ptr, v_len`, get `2, k_ptr, k_len, v_len_ptr`,
+    /// lock `3, k_ptr, k_len, release_id (-1 to acquire), lock_id_ptr`, delete `4, k_ptr, k_len`
+    pub fn kv_batch_ope(args_ptr: i32, args_len: i32, opes_id_ptr: i32);
+    /// args: pairs of the get ope index and the ptr to copy the value to
+    pub fn kv_batch_res(opes_id: i32, args_ptr: i32, args_len: i32);
+
+    pub fn open_file(fname_ptr: i32, fname_len: i32, fd_ptr: i32);
+    pub fn read_file_at(fd: i32, data_ptr: i32, data_len: i32, offset: i32, retlen_ptr: i32);
+    pub fn fs_open(path_ptr: i32, path_len: i32, flags: i32, fd_ptr: i32);
+    pub fn fs_read_at(fd: i32, data_ptr: i32, data_len: i32, offset: i64, retlen_ptr: i32);
+    pub fn fs_write_at(fd: i32, data_ptr: i32, data_len: i32, offset: i64, retlen_ptr: i32);
+    pub fn fs_close(fd: i32, ret_ptr: i32);
+    /// stat: size, is dir and modified time in ms
+    pub fn fs_stat(path_ptr: i32, path_len: i32, stat_ptr: i32);
+    pub fn fs_list_dir(path_ptr: i32, path_len: i32, ret_id_ptr: i32, ret_len_ptr: i32);
+    pub fn fs_list_dir_res(ret_id: i32, ret_ptr: i32);
+
+    pub fn write_result(ptr: i32, len: i32);
+
+    pub fn call_fn(
+        app_ptr: i32,
+        app_len: i32,
+        func_ptr: i32,
+        func_len: i32,
+        arg_ptr: i32,
+        arg_len: i32,
+        ret_id_ptr: i32,
+        ret_len_ptr: i32,
+    );
+    pub fn call_fn_res(ret_id: i32, ret_ptr: i32);
+
+    /// stream 0 stdout, 1 stderr
+    pub fn write_log(ptr: i32, len: i32, stream: i32);
+
+    /// args: ptr and len of the method, url, headers and body,
+    /// out: ret id, status and body len
+    pub fn http_request(args_ptr: i32, args_len: i32, out_ptr: i32);
+    pub fn http_response(ret_id: i32, body_ptr: i32);
+}
+
+pub(crate) fn ptr<T>(v: *const T) -> i32 {
+    v as usize as i32
+}
+
+pub(crate) fn bytes(v: &[u8]) -> (i32, i32) {
+    (ptr(v.as_ptr()), v.len() as i32)
+}
@@ -0,0 +1,27 @@
+//! Calls the other functions in the cluster.
+use crate::{abi, Error, Result};
+
+/// returns the result of the called function, empty if it sets none
+pub fn call(app: &str, func: &str, arg: &str) -> Result<String> {
+    let (app_ptr, app_len) = abi::bytes(app.as_bytes());
+    let (func_ptr, func_len) = abi::bytes(func.as_bytes());
+    let (arg_ptr, arg_len) = abi::bytes(arg.as_bytes());
+    let (mut ret_id, mut ret_len) = (0, 0);
+    unsafe {
+        abi::call_fn(
+            app_ptr,
+            app_len,
+            func_ptr,
+            func_len,
+            arg_ptr,
+            arg_len,
+            abi::ptr(&mut ret_id as *const i32),
+            abi::ptr(&mut ret_len as *const i32),
+        )
+    };
+    let mut ret = vec![0u8; Error::check(ret_len)? as usize];
+    if !ret.is_empty() {
+        unsafe { abi::call_fn_res(ret_id, abi::ptr(ret.as_mut_ptr())) };
+    }
+    String::from_utf8(ret).map_err(|_| Error::Invalid)
+}
@@ -0,0 +1,55 @@
+use crate::abi;
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// the negative codes written by the host functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Failed,
+    /// not allowed by app.yaml or out of the sandbox
+    Denied,
+    Invalid,
+    NotFound,
+    Timeout,
+    TooLarge,
+    Other(i32),
+}
+
+impl Error {
+    pub(crate) fn from_code(code: i32) -> Self {
+        match code {
+            abi::ERR_FAILED => Error::Failed,
+            abi::ERR_DENIED => Error::Denied,
+            abi::ERR_INVALID => Error::Invalid,
+            abi::ERR_NOT_FOUND => Error::NotFound,
+            abi::ERR_TIMEOUT => Error::Timeout,
+            abi::ERR_TOO_LARGE => Error::TooLarge,
+            code => Error::Other(code),
+        }
+    }
+
+    pub(crate) fn check(code: i32) -> Result<i32> {
+        if code >= 0 {
+            Ok(code)
+        } else {
+            Err(Self::from_code(code))
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Failed => write!(f, "host call failed"),
+            Error::Denied => write!(f, "host call denied"),
+            Error::Invalid => write!(f, "invalid host call args"),
+            Error::NotFound => write!(f, "not found"),
+            Error::Timeout => write!(f, "host call timed out"),
+            Error::TooLarge => write!(f, "host call result too large"),
+            Error::Other(code) => write!(f, "host call failed with code {}", code),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
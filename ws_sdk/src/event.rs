@@ -0,0 +1,21 @@
+/// the payload the handler is called with,
+/// the http body, the rpc arg, or the key for the kv triggered ones
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    payload: Vec<u8>,
+}
+
+impl Event {
+    pub(crate) fn new(payload: Vec<u8>) -> Self {
+        Self { payload }
+    }
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.payload).ok()
+    }
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+}
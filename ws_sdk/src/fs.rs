@@ -0,0 +1,185 @@
+//! Files in the sandbox dir of the app, the paths are relative to it.
+use crate::{abi, Error, Result};
+
+/// read only when no flag is set
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenOptions {
+    flags: i32,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn flag(mut self, flag: i32, on: bool) -> Self {
+        if on {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+        self
+    }
+    pub fn write(self, on: bool) -> Self {
+        self.flag(abi::FS_OPEN_WRITE, on)
+    }
+    pub fn append(self, on: bool) -> Self {
+        self.flag(abi::FS_OPEN_APPEND, on)
+    }
+    pub fn create(self, on: bool) -> Self {
+        self.flag(abi::FS_OPEN_CREATE, on)
+    }
+    pub fn truncate(self, on: bool) -> Self {
+        self.flag(abi::FS_OPEN_TRUNCATE, on)
+    }
+    pub fn open(&self, path: &str) -> Result<File> {
+        let (ptr, len) = abi::bytes(path.as_bytes());
+        let mut fd = 0;
+        unsafe { abi::fs_open(ptr, len, self.flags, abi::ptr(&mut fd as *const i32)) };
+        Ok(File {
+            fd: Error::check(fd)?,
+        })
+    }
+}
+
+/// closed on drop, the host closes the ones left when the invocation ends as well
+#[derive(Debug)]
+pub struct File {
+    fd: i32,
+}
+
+impl File {
+    pub fn open(path: &str) -> Result<Self> {
+        OpenOptions::new().open(path)
+    }
+    pub fn create(path: &str) -> Result<Self> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    }
+    /// read only, rooted at the files shared by all the apps
+    pub fn open_shared(path: &str) -> Result<Self> {
+        let (ptr, len) = abi::bytes(path.as_bytes());
+        let mut fd = 0;
+        unsafe { abi::open_file(ptr, len, abi::ptr(&mut fd as *const i32)) };
+        Ok(File {
+            fd: Error::check(fd)?,
+        })
+    }
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let (ptr, len) = abi::bytes(buf);
+        let mut ret = 0;
+        unsafe {
+            abi::fs_read_at(
+                self.fd,
+                ptr,
+                len,
+                offset as i64,
+                abi::ptr(&mut ret as *const i32),
+            )
+        };
+        Error::check(ret).map(|n| n as usize)
+    }
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let (ptr, len) = abi::bytes(buf);
+        let mut ret = 0;
+        unsafe {
+            abi::fs_write_at(
+                self.fd,
+                ptr,
+                len,
+                offset as i64,
+                abi::ptr(&mut ret as *const i32),
+            )
+        };
+        Error::check(ret).map(|n| n as usize)
+    }
+    pub fn read_to_end(&self) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let n = self.read_at(&mut buf, data.len() as u64)?;
+            if n == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+    }
+    pub fn close(self) -> Result<()> {
+        let fd = self.fd;
+        std::mem::forget(self);
+        close(fd)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+fn close(fd: i32) -> Result<()> {
+    let mut ret = 0;
+    unsafe { abi::fs_close(fd, abi::ptr(&mut ret as *const i32)) };
+    Error::check(ret).map(|_| ())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub len: u64,
+    pub is_dir: bool,
+    /// unix time in ms
+    pub modified_ms: i64,
+}
+
+pub fn metadata(path: &str) -> Result<Metadata> {
+    let (ptr, len) = abi::bytes(path.as_bytes());
+    let mut stat = [0i64; 3];
+    unsafe { abi::fs_stat(ptr, len, abi::ptr(stat.as_mut_ptr())) };
+    if stat[0] < 0 {
+        return Err(Error::from_code(stat[0] as i32));
+    }
+    Ok(Metadata {
+        len: stat[0] as u64,
+        is_dir: stat[1] != 0,
+        modified_ms: stat[2],
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+}
+
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>> {
+    let (ptr, len) = abi::bytes(path.as_bytes());
+    let (mut ret_id, mut ret_len) = (0, 0);
+    unsafe {
+        abi::fs_list_dir(
+            ptr,
+            len,
+            abi::ptr(&mut ret_id as *const i32),
+            abi::ptr(&mut ret_len as *const i32),
+        )
+    };
+    let mut listing = vec![0u8; Error::check(ret_len)? as usize];
+    if !listing.is_empty() {
+        unsafe { abi::fs_list_dir_res(ret_id, abi::ptr(listing.as_mut_ptr())) };
+    }
+    // dirs end with `/`
+    Ok(String::from_utf8_lossy(&listing)
+        .lines()
+        .map(|line| match line.strip_suffix('/') {
+            Some(name) => DirEntry {
+                name: name.to_owned(),
+                is_dir: true,
+            },
+            None => DirEntry {
+                name: line.to_owned(),
+                is_dir: false,
+            },
+        })
+        .collect())
+}
@@ -0,0 +1,49 @@
+//! Outbound http, limited by the `http_out` of app.yaml.
+use crate::{abi, Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// redirects are returned as is
+pub fn request(method: &str, url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<Response> {
+    let headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\n", name, value))
+        .collect();
+    let (method_ptr, method_len) = abi::bytes(method.as_bytes());
+    let (url_ptr, url_len) = abi::bytes(url.as_bytes());
+    let (headers_ptr, headers_len) = abi::bytes(headers.as_bytes());
+    let (body_ptr, body_len) = abi::bytes(body);
+    let args = [
+        method_ptr,
+        method_len,
+        url_ptr,
+        url_len,
+        headers_ptr,
+        headers_len,
+        body_ptr,
+        body_len,
+    ];
+    let mut out = [0i32; 3];
+    unsafe {
+        abi::http_request(
+            abi::ptr(args.as_ptr()),
+            args.len() as i32,
+            abi::ptr(out.as_mut_ptr()),
+        )
+    };
+    let ret_id = Error::check(out[0])?;
+    let mut body = vec![0u8; out[2] as usize];
+    unsafe { abi::http_response(ret_id, abi::ptr(body.as_mut_ptr())) };
+    Ok(Response {
+        status: out[1] as u16,
+        body,
+    })
+}
+
+pub fn get(url: &str) -> Result<Response> {
+    request("GET", url, &[], &[])
+}
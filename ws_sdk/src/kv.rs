@@ -0,0 +1,144 @@
+//! The kv store of the cluster, limited by the kv events and shares declared in app.yaml.
+use crate::{abi, Error, Result};
+
+enum Ope<'a> {
+    Set(&'a [u8], &'a [u8]),
+    Get(&'a [u8]),
+    /// the lock id to release, acquires if none
+    Lock(&'a [u8], Option<u32>),
+    Delete(&'a [u8]),
+}
+
+/// the result of each ope in the batch, in the order they were added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpeRes {
+    Done,
+    Value(Option<Vec<u8>>),
+    Locked(u32),
+}
+
+/// opes sent to the host in one call
+#[derive(Default)]
+pub struct Batch<'a> {
+    opes: Vec<Ope<'a>>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set(&mut self, key: &'a [u8], value: &'a [u8]) -> &mut Self {
+        self.opes.push(Ope::Set(key, value));
+        self
+    }
+    pub fn get(&mut self, key: &'a [u8]) -> &mut Self {
+        self.opes.push(Ope::Get(key));
+        self
+    }
+    pub fn lock(&mut self, key: &'a [u8]) -> &mut Self {
+        self.opes.push(Ope::Lock(key, None));
+        self
+    }
+    pub fn unlock(&mut self, key: &'a [u8], lock_id: u32) -> &mut Self {
+        self.opes.push(Ope::Lock(key, Some(lock_id)));
+        self
+    }
+    pub fn delete(&mut self, key: &'a [u8]) -> &mut Self {
+        self.opes.push(Ope::Delete(key));
+        self
+    }
+
+    /// the args of `kv_batch_ope`, the host writes the value len of the gets
+    /// and the lock id of the locks to `outs`, one for each ope
+    #[doc(hidden)]
+    pub fn encode_args(&self, outs: &mut [i32]) -> Vec<i32> {
+        assert_eq!(outs.len(), self.opes.len());
+        let mut args = vec![self.opes.len() as i32];
+        for (ope, out) in self.opes.iter().zip(outs.iter_mut()) {
+            let out = abi::ptr(out as *const i32);
+            match ope {
+                Ope::Set(key, value) => {
+                    let (kptr, klen) = abi::bytes(key);
+                    let (vptr, vlen) = abi::bytes(value);
+                    args.extend([abi::KV_SET, kptr, klen, vptr, vlen]);
+                }
+                Ope::Get(key) => {
+                    let (kptr, klen) = abi::bytes(key);
+                    args.extend([abi::KV_GET, kptr, klen, out]);
+                }
+                Ope::Lock(key, release_id) => {
+                    let (kptr, klen) = abi::bytes(key);
+                    let release_id = release_id.map_or(-1, |id| id as i32);
+                    args.extend([abi::KV_LOCK, kptr, klen, release_id, out]);
+                }
+                Ope::Delete(key) => {
+                    let (kptr, klen) = abi::bytes(key);
+                    args.extend([abi::KV_DELETE, kptr, klen]);
+                }
+            }
+        }
+        args
+    }
+
+    pub fn run(&self) -> Result<Vec<OpeRes>> {
+        let mut outs = vec![0i32; self.opes.len()];
+        let args = self.encode_args(&mut outs);
+        let mut opes_id = 0;
+        unsafe {
+            abi::kv_batch_ope(
+                abi::ptr(args.as_ptr()),
+                args.len() as i32,
+                abi::ptr(&mut opes_id as *const i32),
+            )
+        };
+        let opes_id = Error::check(opes_id)?;
+
+        let mut res = Vec::with_capacity(self.opes.len());
+        let mut pairs = vec![];
+        for (idx, (ope, out)) in self.opes.iter().zip(outs.iter()).enumerate() {
+            res.push(match ope {
+                Ope::Get(_) if *out >= 0 => OpeRes::Value(Some(vec![0u8; *out as usize])),
+                Ope::Get(_) => OpeRes::Value(None),
+                Ope::Lock(_, None) => OpeRes::Locked(*out as u32),
+                _ => OpeRes::Done,
+            });
+            if let Some(OpeRes::Value(Some(value))) = res.last() {
+                pairs.extend([idx as i32, abi::ptr(value.as_ptr())]);
+            }
+        }
+        if !pairs.is_empty() {
+            unsafe { abi::kv_batch_res(opes_id, abi::ptr(pairs.as_ptr()), pairs.len() as i32) };
+        }
+        Ok(res)
+    }
+}
+
+pub fn set(key: &[u8], value: &[u8]) -> Result<()> {
+    let _ = Batch::new().set(key, value).run()?;
+    Ok(())
+}
+
+pub fn get(key: &[u8]) -> Result<Option<Vec<u8>>> {
+    match Batch::new().get(key).run()?.pop() {
+        Some(OpeRes::Value(value)) => Ok(value),
+        _ => Err(Error::Failed),
+    }
+}
+
+pub fn delete(key: &[u8]) -> Result<()> {
+    let _ = Batch::new().delete(key).run()?;
+    Ok(())
+}
+
+/// waits for the lock, returns the id to unlock with
+pub fn lock(key: &[u8]) -> Result<u32> {
+    match Batch::new().lock(key).run()?.pop() {
+        Some(OpeRes::Locked(id)) => Ok(id),
+        _ => Err(Error::Failed),
+    }
+}
+
+pub fn unlock(key: &[u8], lock_id: u32) -> Result<()> {
+    let _ = Batch::new().unlock(key, lock_id).run()?;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! Guest side sdk of the wasm functions, wrapping the host functions of the worker.
+//!
+//! ```ignore
+//! use ws_sdk::{handler, kv, Event};
+//!
+//! #[handler]
+//! fn hello(event: Event) -> Result<String, ws_sdk::Error> {
+//!     kv::set(b"last_caller", event.payload())?;
+//!     Ok(format!("hello {}", event.as_str().unwrap_or("")))
+//! }
+//! ```
+
+pub mod abi;
+pub mod call;
+mod error;
+mod event;
+pub mod fs;
+pub mod http;
+pub mod kv;
+pub mod log;
+pub mod result;
+
+pub use error::{Error, Result};
+pub use event::Event;
+pub use ws_sdk_macro::handler;
+
+/// called by the host to place the event payload before calling a handler,
+/// the handler takes the buffer back
+#[no_mangle]
+pub extern "C" fn allocate(size: i32) -> i32 {
+    let buf = vec![0u8; size.max(0) as usize].into_boxed_slice();
+    abi::ptr(Box::into_raw(buf) as *const u8)
+}
+
+/// checked by the host when the instance is loaded
+#[no_mangle]
+pub extern "C" fn ws_abi_version() -> i32 {
+    abi::ABI_VERSION
+}
+
+#[doc(hidden)]
+pub mod __private {
+    use crate::{result::IntoResult, Event};
+
+    pub fn run_handler<R: IntoResult>(ptr: i32, len: i32, f: impl FnOnce(Event) -> R) {
+        let payload = if len > 0 {
+            // allocated by `allocate` with exactly this len
+            unsafe {
+                Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                    ptr as usize as *mut u8,
+                    len as usize,
+                ))
+            }
+            .into_vec()
+        } else {
+            vec![]
+        };
+        if let Err(err) = f(Event::new(payload)).into_result() {
+            crate::log::error(&err);
+            #[cfg(target_arch = "wasm32")]
+            core::arch::wasm32::unreachable();
+            #[cfg(not(target_arch = "wasm32"))]
+            panic!("{}", err);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Logs collected into the function logs of the worker.
+use crate::abi;
+use std::fmt::Display;
+
+const STDOUT: i32 = 0;
+const STDERR: i32 = 1;
+
+fn write(msg: &str, stream: i32) {
+    let (ptr, len) = abi::bytes(msg.as_bytes());
+    unsafe { abi::write_log(ptr, len, stream) }
+}
+
+pub fn info(msg: impl Display) {
+    write(&msg.to_string(), STDOUT)
+}
+
+pub fn error(msg: impl Display) {
+    write(&msg.to_string(), STDERR)
+}
@@ -0,0 +1,40 @@
+use crate::abi;
+use std::fmt::Display;
+
+/// sets the result of the invocation, returned to the http or rpc caller
+pub fn set(res: impl AsRef<str>) {
+    let (ptr, len) = abi::bytes(res.as_ref().as_bytes());
+    unsafe { abi::write_result(ptr, len) }
+}
+
+/// the return values a handler may have
+pub trait IntoResult {
+    /// sets the result, the error is only reported
+    fn into_result(self) -> Result<(), String>;
+}
+
+impl IntoResult for () {
+    fn into_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl IntoResult for String {
+    fn into_result(self) -> Result<(), String> {
+        set(self);
+        Ok(())
+    }
+}
+
+impl IntoResult for &str {
+    fn into_result(self) -> Result<(), String> {
+        set(self);
+        Ok(())
+    }
+}
+
+impl<T: IntoResult, E: Display> IntoResult for Result<T, E> {
+    fn into_result(self) -> Result<(), String> {
+        self.map_err(|e| e.to_string())?.into_result()
+    }
+}
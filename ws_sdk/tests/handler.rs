@@ -0,0 +1,64 @@
+//! The expansion of `#[handler]` run natively, with the host functions it calls mocked.
+use std::cell::RefCell;
+use ws_sdk::{handler, Event};
+
+thread_local! {
+    /// the len of each `write_result` and the stream of each `write_log`
+    static CALLS: RefCell<Vec<(&'static str, i32)>> = const { RefCell::new(vec![]) };
+}
+
+#[no_mangle]
+extern "C" fn write_result(_ptr: i32, len: i32) {
+    CALLS.with(|c| c.borrow_mut().push(("write_result", len)));
+}
+
+#[no_mangle]
+extern "C" fn write_log(_ptr: i32, _len: i32, stream: i32) {
+    CALLS.with(|c| c.borrow_mut().push(("write_log", stream)));
+}
+
+fn take_calls() -> Vec<(&'static str, i32)> {
+    CALLS.with(|c| std::mem::take(&mut *c.borrow_mut()))
+}
+
+#[handler]
+fn no_event() -> String {
+    "hello".to_owned()
+}
+
+#[handler]
+fn with_event(event: Event) -> Result<String, ws_sdk::Error> {
+    assert!(event.payload().is_empty());
+    Ok("hi".to_owned())
+}
+
+#[handler]
+fn no_result() {}
+
+#[handler]
+fn failed(_event: Event) -> Result<(), ws_sdk::Error> {
+    Err(ws_sdk::Error::Denied)
+}
+
+#[test]
+fn test_handler_exports() {
+    // the exported ones are what the host calls, the payload is empty when len is 0
+    let exported: [extern "C" fn(i32, i32); 3] = [no_event, with_event, no_result];
+    for f in exported {
+        f(0, 0);
+    }
+    assert_eq!(take_calls(), vec![("write_result", 5), ("write_result", 2)]);
+    // the original fns are kept under another name
+    assert_eq!(__ws_handler_no_event(), "hello");
+}
+
+#[test]
+fn test_handler_err_logged() {
+    // the export traps on wasm, natively it panics, which can't unwind out of the extern fn
+    let _: extern "C" fn(i32, i32) = failed;
+    let res =
+        std::panic::catch_unwind(|| ws_sdk::__private::run_handler(0, 0, __ws_handler_failed));
+    assert!(res.is_err());
+    // to stderr
+    assert_eq!(take_calls(), vec![("write_log", 1)]);
+}
@@ -0,0 +1,44 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, ItemFn};
+
+/// Exports the function as a handler of the app, taking the event optionally.
+///
+/// The return value is written as the result, `Err` fails the invocation.
+#[proc_macro_attribute]
+pub fn handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut func = parse_macro_input!(item as ItemFn);
+    if !attr.is_empty() {
+        return syn::Error::new(proc_macro2::Span::call_site(), "handler takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    if func.sig.asyncness.is_some() || !func.sig.generics.params.is_empty() {
+        return syn::Error::new_spanned(&func.sig, "handler must be a plain sync fn")
+            .to_compile_error()
+            .into();
+    }
+
+    let name = func.sig.ident.clone();
+    let inner = format_ident!("__ws_handler_{}", name);
+    func.sig.ident = inner.clone();
+    let call = match func.sig.inputs.len() {
+        0 => quote!(|_event| #inner()),
+        1 => quote!(#inner),
+        _ => {
+            return syn::Error::new_spanned(&func.sig.inputs, "handler takes at most the event")
+                .to_compile_error()
+                .into();
+        }
+    };
+
+    quote! {
+        #func
+
+        #[no_mangle]
+        pub extern "C" fn #name(ptr: i32, len: i32) {
+            ::ws_sdk::__private::run_handler(ptr, len, #call)
+        }
+    }
+    .into()
+}